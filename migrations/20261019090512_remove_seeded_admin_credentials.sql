@@ -0,0 +1,12 @@
+-- Add migration script here
+-- The seeded admin user has a well-known password, checked into the repository.
+-- Remove it unless its password has been changed since; use `newsletter admin create-user` instead.
+DELETE FROM idempotency
+WHERE user_id IN (
+    SELECT user_id FROM users
+    WHERE user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6'
+    AND password_hash = '$argon2id$v=19$m=15000,t=2,p=1$PjOaO8A8sGZZvLjDw1aVQQ$lNHsqhqBrbv80GluDiGWdC7Fl9zSFFBy+8P/051z5a0'
+);
+DELETE FROM users
+WHERE user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6'
+AND password_hash = '$argon2id$v=19$m=15000,t=2,p=1$PjOaO8A8sGZZvLjDw1aVQQ$lNHsqhqBrbv80GluDiGWdC7Fl9zSFFBy+8P/051z5a0';
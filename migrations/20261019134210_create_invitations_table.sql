@@ -0,0 +1,15 @@
+-- Add migration script here
+-- Invited users are identified by their email address
+ALTER TABLE users ADD COLUMN email TEXT NULL UNIQUE;
+
+CREATE TABLE invitations (
+    invitation_id uuid PRIMARY KEY,
+    email TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    -- only a signature of the token is stored, the token itself is only sent to the invitee
+    token_signature TEXT NOT NULL UNIQUE,
+    invited_by uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    accepted_at timestamptz NULL
+);
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE password_reset_tokens (
+    -- only a signature of the token is stored, the token itself is only sent to the user
+    token_signature TEXT PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz NULL
+);
@@ -0,0 +1,20 @@
+-- Add migration script here
+-- base32 TOTP secret, users without one haven't enrolled in two-factor authentication
+ALTER TABLE users ADD COLUMN totp_secret TEXT NULL;
+-- time step of the last code accepted, so that a code can't be used twice
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT NULL;
+
+CREATE TABLE recovery_codes (
+    -- only a signature of the code is stored, like for the tokens sent by email
+    code_signature TEXT PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    used_at timestamptz NULL
+);
+
+-- Settings of the whole instance, managed by owners: a single row
+CREATE TABLE admin_settings (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    two_factor_required BOOLEAN NOT NULL
+);
+INSERT INTO admin_settings (two_factor_required) VALUES (FALSE);
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- Every login, so that users can see and revoke their sessions.
+-- Sessions logged in before this table existed are not registered: they have to log in again
+CREATE TABLE user_sessions (
+    session_id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    ip_address TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    -- updated by every request made with the session
+    last_seen_at timestamptz NOT NULL,
+    -- the session is rejected past this instant, whatever the session store
+    expires_at timestamptz NOT NULL,
+    revoked_at timestamptz NULL
+);
+CREATE INDEX user_sessions_user_id_idx ON user_sessions (user_id);
@@ -0,0 +1,25 @@
+-- Add migration script here
+-- Append-only record of who did what, actor_id isn't a foreign key so that
+-- entries outlive the users who performed them
+CREATE TABLE audit_log (
+    audit_id BIGSERIAL PRIMARY KEY,
+    occurred_at timestamptz NOT NULL,
+    actor_id uuid NULL,
+    actor TEXT NULL,
+    action TEXT NOT NULL,
+    target TEXT NULL,
+    ip_address TEXT NULL,
+    details TEXT NULL
+);
+CREATE INDEX audit_log_occurred_at_idx ON audit_log (occurred_at);
+CREATE INDEX audit_log_action_idx ON audit_log (action);
+
+CREATE FUNCTION reject_audit_log_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE OR TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION reject_audit_log_changes();
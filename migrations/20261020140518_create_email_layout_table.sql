@@ -0,0 +1,25 @@
+-- Add migration script here
+-- Layout wrapping every email, managed by owners: a single row
+CREATE TABLE email_layout (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    -- HTML, trusted like the content of the issues
+    header TEXT NOT NULL,
+    footer TEXT NOT NULL,
+    -- CSS, inlined into the elements when an email is rendered
+    styles TEXT NOT NULL,
+    -- shown at the bottom of every email, as required by anti-spam laws
+    postal_address TEXT NOT NULL
+);
+INSERT INTO email_layout (header, footer, styles, postal_address) VALUES (
+    '<h1>Our newsletter</h1>',
+    '',
+    'body { font-family: Helvetica, Arial, sans-serif; color: #222222; }
+.footer { font-size: 12px; color: #777777; }',
+    ''
+);
+
+-- Sent in the unsubscribe link of every issue
+ALTER TABLE subscriptions ADD COLUMN unsubscribe_token TEXT NULL UNIQUE;
+-- random() is not cryptographically secure, gen_random_uuid() is (122 random bits)
+UPDATE subscriptions SET unsubscribe_token = replace(gen_random_uuid()::text, '-', '');
+ALTER TABLE subscriptions ALTER COLUMN unsubscribe_token SET NOT NULL;
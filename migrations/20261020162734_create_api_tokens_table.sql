@@ -0,0 +1,18 @@
+-- Add migration script here
+-- Tokens authenticating the requests to `/api/v1` on behalf of a user
+CREATE TABLE api_tokens (
+    token_id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    -- chosen by the user, to tell their tokens apart
+    name TEXT NOT NULL,
+    -- only a signature of the token is stored, the token itself is only shown once to the user
+    token_signature TEXT NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL,
+    last_used_at timestamptz NULL,
+    revoked_at timestamptz NULL
+);
+
+-- Issues can be created as drafts through the API, and published later on
+ALTER TABLE newsletter_issues
+    ALTER COLUMN published_at TYPE timestamptz USING published_at::timestamptz,
+    ALTER COLUMN published_at DROP NOT NULL;
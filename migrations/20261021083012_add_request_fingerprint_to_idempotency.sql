@@ -0,0 +1,3 @@
+-- Hash of the request that used the key, a key reused for another request is rejected.
+-- NULL for the responses saved before fingerprints were stored.
+ALTER TABLE idempotency ADD COLUMN request_fingerprint TEXT;
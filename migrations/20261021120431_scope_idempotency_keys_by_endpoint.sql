@@ -0,0 +1,6 @@
+-- The same key can be used on different endpoints, each with its own retention.
+-- Keys saved before are given an empty endpoint and expire with the default retention.
+ALTER TABLE idempotency ADD COLUMN endpoint TEXT NOT NULL DEFAULT '';
+ALTER TABLE idempotency ALTER COLUMN endpoint DROP DEFAULT;
+ALTER TABLE idempotency DROP CONSTRAINT idempotency_pkey;
+ALTER TABLE idempotency ADD PRIMARY KEY (user_id, endpoint, idempotency_key);
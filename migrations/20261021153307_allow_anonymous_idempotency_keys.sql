@@ -0,0 +1,12 @@
+-- Anonymous requests have no user: their keys are scoped by a hash of the client's IP address
+-- and user agent instead
+ALTER TABLE idempotency ADD COLUMN client_fingerprint TEXT;
+ALTER TABLE idempotency DROP CONSTRAINT idempotency_pkey;
+ALTER TABLE idempotency ALTER COLUMN user_id DROP NOT NULL;
+ALTER TABLE idempotency ADD CONSTRAINT idempotency_scope_check
+    CHECK ((user_id IS NULL) <> (client_fingerprint IS NULL));
+CREATE UNIQUE INDEX idempotency_user_key ON idempotency (user_id, endpoint, idempotency_key)
+    WHERE user_id IS NOT NULL;
+CREATE UNIQUE INDEX idempotency_client_key
+    ON idempotency (client_fingerprint, endpoint, idempotency_key)
+    WHERE client_fingerprint IS NOT NULL;
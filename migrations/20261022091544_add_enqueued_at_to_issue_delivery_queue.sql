@@ -0,0 +1,3 @@
+-- The delivery latency is measured from the moment a task is enqueued.
+-- Tasks already queued are considered enqueued now.
+ALTER TABLE issue_delivery_queue ADD COLUMN enqueued_at timestamptz NOT NULL DEFAULT now();
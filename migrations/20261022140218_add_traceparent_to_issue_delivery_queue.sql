@@ -0,0 +1,3 @@
+-- W3C trace context of the request which published the issue, continued by the delivery worker.
+-- NULL when traces are not exported.
+ALTER TABLE issue_delivery_queue ADD COLUMN traceparent TEXT;
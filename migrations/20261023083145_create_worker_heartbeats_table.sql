@@ -0,0 +1,6 @@
+-- Background loops record when they last ran, reported by the readiness check.
+-- Workers can run in other processes than the API, possibly several of them per kind.
+CREATE TABLE worker_heartbeats (
+    worker TEXT PRIMARY KEY,
+    last_seen_at timestamptz NOT NULL
+);
@@ -0,0 +1,14 @@
+-- Session state of deployments storing sessions in Postgres rather than Redis
+CREATE TABLE sessions(
+    session_key TEXT PRIMARY KEY,
+    state TEXT NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
+
+-- Failed login attempts, counted here when Redis isn't used
+CREATE TABLE login_failure_counters(
+    counter_key TEXT PRIMARY KEY,
+    failures BIGINT NOT NULL,
+    expires_at timestamptz NOT NULL
+);
@@ -0,0 +1,256 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+// Actions recorded in the append-only `audit_log` table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    Logout,
+    PasswordChanged,
+    PasswordReset,
+    NewsletterCreated,
+    NewsletterPublished,
+    SubscriberCreated,
+    SubscriberConfirmed,
+    SubscriberUnsubscribed,
+    SubscriberUpdated,
+    SubscriberDeleted,
+    UserCreated,
+    UserDeleted,
+    UserInvited,
+    RoleChanged,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    SessionRevoked,
+    SettingsUpdated,
+    ApiTokenCreated,
+    ApiTokenRevoked,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 22] = [
+        AuditAction::Login,
+        AuditAction::LoginFailed,
+        AuditAction::Logout,
+        AuditAction::PasswordChanged,
+        AuditAction::PasswordReset,
+        AuditAction::NewsletterCreated,
+        AuditAction::NewsletterPublished,
+        AuditAction::SubscriberCreated,
+        AuditAction::SubscriberConfirmed,
+        AuditAction::SubscriberUnsubscribed,
+        AuditAction::SubscriberUpdated,
+        AuditAction::SubscriberDeleted,
+        AuditAction::UserCreated,
+        AuditAction::UserDeleted,
+        AuditAction::UserInvited,
+        AuditAction::RoleChanged,
+        AuditAction::TwoFactorEnabled,
+        AuditAction::TwoFactorDisabled,
+        AuditAction::SessionRevoked,
+        AuditAction::SettingsUpdated,
+        AuditAction::ApiTokenCreated,
+        AuditAction::ApiTokenRevoked,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::Logout => "logout",
+            AuditAction::PasswordChanged => "password_changed",
+            AuditAction::PasswordReset => "password_reset",
+            AuditAction::NewsletterCreated => "newsletter_created",
+            AuditAction::NewsletterPublished => "newsletter_published",
+            AuditAction::SubscriberCreated => "subscriber_created",
+            AuditAction::SubscriberConfirmed => "subscriber_confirmed",
+            AuditAction::SubscriberUnsubscribed => "subscriber_unsubscribed",
+            AuditAction::SubscriberUpdated => "subscriber_updated",
+            AuditAction::SubscriberDeleted => "subscriber_deleted",
+            AuditAction::UserCreated => "user_created",
+            AuditAction::UserDeleted => "user_deleted",
+            AuditAction::UserInvited => "user_invited",
+            AuditAction::RoleChanged => "role_changed",
+            AuditAction::TwoFactorEnabled => "two_factor_enabled",
+            AuditAction::TwoFactorDisabled => "two_factor_disabled",
+            AuditAction::SessionRevoked => "session_revoked",
+            AuditAction::SettingsUpdated => "settings_updated",
+            AuditAction::ApiTokenCreated => "api_token_created",
+            AuditAction::ApiTokenRevoked => "api_token_revoked",
+        }
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for AuditAction {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        AuditAction::ALL
+            .into_iter()
+            .find(|a| a.as_str() == s)
+            .ok_or_else(|| format!("{} is not an audited action.", s))
+    }
+}
+
+// Who performed an action
+#[derive(Clone, Copy, Debug)]
+pub enum Actor {
+    User(Uuid),
+    // e.g. subscribers, or someone trying to log in
+    Anonymous,
+    // the `newsletter admin` commands
+    CommandLine,
+}
+
+pub struct AuditEvent {
+    actor: Actor,
+    action: AuditAction,
+    target: Option<String>,
+    ip_address: Option<String>,
+    details: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(actor: Actor, action: AuditAction) -> Self {
+        Self {
+            actor,
+            action,
+            target: None,
+            ip_address: None,
+            details: None,
+        }
+    }
+
+    // what the action was performed on, e.g. a username or a newsletter issue id
+    pub fn target(mut self, target: impl ToString) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn ip_address(mut self, ip_address: impl ToString) -> Self {
+        self.ip_address = Some(ip_address.to_string());
+        self
+    }
+
+    pub fn details(mut self, details: impl ToString) -> Self {
+        self.details = Some(details.to_string());
+        self
+    }
+
+    // Pass the transaction of the action when there is one, so that both are committed together
+    #[tracing::instrument(name = "Record audit event", skip(self, executor), fields(action=%self.action))]
+    pub async fn record(self, executor: impl PgExecutor<'_>) -> Result<(), anyhow::Error> {
+        let (actor_id, actor_name) = match self.actor {
+            Actor::User(user_id) => (Some(user_id), None),
+            Actor::Anonymous => (None, None),
+            Actor::CommandLine => (None, Some("command line")),
+        };
+        // the username is copied, so that entries stay readable once the user is deleted
+        sqlx::query!(
+            r#"
+            INSERT INTO audit_log (occurred_at, actor_id, actor, action, target, ip_address, details)
+            VALUES (
+                $1,
+                $2,
+                COALESCE((SELECT username FROM users WHERE user_id = $2), $3),
+                $4,
+                $5,
+                $6,
+                $7
+            )
+            "#,
+            Utc::now(),
+            actor_id,
+            actor_name,
+            self.action.as_str(),
+            self.target,
+            self.ip_address,
+            self.details,
+        )
+        .execute(executor)
+        .await
+        .context("Failed to record an audit event.")?;
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditLogEntry {
+    pub audit_id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub actor_id: Option<Uuid>,
+    pub actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip_address: Option<String>,
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditLogFilter {
+    pub action: Option<String>,
+    pub actor: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+// Newest entries first, all of them when there is no limit
+#[tracing::instrument(name = "Search audit log", skip(pool))]
+pub async fn search_audit_log(
+    filter: &AuditLogFilter,
+    limit: Option<i64>,
+    pool: &PgPool,
+) -> Result<Vec<AuditLogEntry>, anyhow::Error> {
+    let entries = sqlx::query_as!(
+        AuditLogEntry,
+        r#"
+        SELECT audit_id, occurred_at, actor_id, actor, action, target, ip_address, details
+        FROM audit_log
+        WHERE
+            ($1::text IS NULL OR action = $1) AND
+            ($2::text IS NULL OR actor = $2) AND
+            ($3::timestamptz IS NULL OR occurred_at >= $3) AND
+            ($4::timestamptz IS NULL OR occurred_at < $4)
+        ORDER BY audit_id DESC
+        LIMIT $5
+        "#,
+        filter.action,
+        filter.actor,
+        filter.since,
+        filter.until,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to search the audit log.")?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditAction;
+
+    #[test]
+    fn actions_round_trip_through_their_name() {
+        for action in AuditAction::ALL {
+            assert_eq!(
+                AuditAction::try_from(action.as_str().to_string()),
+                Ok(action)
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_actions_are_rejected() {
+        assert!(AuditAction::try_from("drop_database".to_string()).is_err());
+    }
+}
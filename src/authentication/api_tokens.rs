@@ -0,0 +1,110 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{Role, generate_token, sign_token};
+
+pub struct ApiToken {
+    pub token_id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+// The token is returned to be shown once to the user, only its signature is stored
+#[tracing::instrument(name = "Create API token", skip(hmac_secret, pool))]
+pub async fn create_api_token(
+    user_id: Uuid,
+    name: &str,
+    hmac_secret: &Secret<String>,
+    pool: &PgPool,
+) -> Result<(Uuid, Secret<String>), anyhow::Error> {
+    let token_id = Uuid::new_v4();
+    let token = generate_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_signature, created_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        token_id,
+        user_id,
+        name,
+        sign_token(token.expose_secret(), hmac_secret),
+        Utc::now(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store an API token.")?;
+    Ok((token_id, token))
+}
+
+#[tracing::instrument(name = "List API tokens", skip(pool))]
+pub async fn list_api_tokens(user_id: Uuid, pool: &PgPool) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT token_id, name, created_at, last_used_at
+        FROM api_tokens
+        WHERE user_id = $1 AND revoked_at IS NULL
+        ORDER BY created_at DESC
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to list the API tokens of a user.")?;
+    Ok(tokens)
+}
+
+// Users can only revoke their own tokens, returns whether a token has been revoked
+#[tracing::instrument(name = "Revoke API token", skip(pool))]
+pub async fn revoke_api_token(
+    user_id: Uuid,
+    token_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE user_id = $1 AND token_id = $2 AND revoked_at IS NULL
+        "#,
+        user_id,
+        token_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke an API token.")?
+    .rows_affected();
+    Ok(revoked > 0)
+}
+
+// The user and role a token acts for, None when it is unknown or revoked
+#[tracing::instrument(name = "Authenticate API token", skip_all)]
+pub async fn authenticate_api_token(
+    token: &str,
+    hmac_secret: &Secret<String>,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, Role)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens t
+        SET last_used_at = now()
+        FROM users u
+        WHERE t.token_signature = $1 AND t.revoked_at IS NULL AND u.user_id = t.user_id
+        RETURNING u.user_id, u.role
+        "#,
+        sign_token(token, hmac_secret),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to authenticate an API token.")?;
+
+    row.map(|r| {
+        let role = Role::try_from(r.role).map_err(anyhow::Error::msg)?;
+        Ok((r.user_id, role))
+    })
+    .transpose()
+}
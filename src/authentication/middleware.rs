@@ -1,16 +1,26 @@
 use std::ops::Deref;
 
 use actix_web::{
-    FromRequest, HttpMessage,
+    FromRequest, HttpMessage, HttpResponse,
     body::MessageBody,
     dev::{ServiceRequest, ServiceResponse},
     error::InternalError,
+    http::{
+        Method,
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    },
+    web,
 };
 use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
 use uuid::Uuid;
 
 use crate::{
+    authentication::{Role, authenticate_api_token},
     session_state::TypedSession,
+    startup::HmacSecret,
     utils::{e500, see_other},
 };
 
@@ -40,15 +50,218 @@ pub async fn reject_anonymous_users(
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id().map_err(e500)? {
+    let user_id = session.get_user_id().map_err(e500)?;
+    let session_id = session.get_session_id().map_err(e500)?;
+    // the user is looked up on every request, so that a change takes effect immediately
+    let user = match user_id {
         Some(user_id) => {
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .expect("The Postgres pool is not registered as application data");
+            get_session_user(user_id, session_id, pool)
+                .await
+                .map_err(e500)?
+        }
+        None => None,
+    };
+
+    match (user_id, user) {
+        (Some(user_id), Some(user)) => {
+            if !user.session_active {
+                session.log_out();
+                let response = see_other("/login");
+                let e = anyhow::anyhow!("The session has been revoked");
+                return Err(InternalError::from_response(e, response).into());
+            }
+            // users can't do anything else until they comply with a mandatory 2FA setting
+            if user.two_factor_missing && !is_allowed_without_two_factor(req.path()) {
+                let response = see_other("/admin/two-factor");
+                let e = anyhow::anyhow!("The user must set up two-factor authentication");
+                return Err(InternalError::from_response(e, response).into());
+            }
             req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(user.role);
             next.call(req).await
         }
-        None => {
+        // the user has been deleted since they logged in
+        (Some(_), None) => {
+            session.log_out();
+            let response = see_other("/login");
+            let e = anyhow::anyhow!("The user does not exist anymore");
+            Err(InternalError::from_response(e, response).into())
+        }
+        (None, _) => {
             let response = see_other("/login");
             let e = anyhow::anyhow!("The user has not logged in");
             Err(InternalError::from_response(e, response).into())
         }
     }
 }
+
+// Must be wrapped by `reject_anonymous_users`, which stores the role of the user
+pub async fn reject_viewers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Editor, &req)?;
+    next.call(req).await
+}
+
+// Must be wrapped by `reject_anonymous_users`, which stores the role of the user
+pub async fn reject_non_owners(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Owner, &req)?;
+    next.call(req).await
+}
+
+#[derive(serde::Deserialize)]
+struct CsrfForm {
+    csrf_token: Option<String>,
+}
+
+// State-changing requests must submit the CSRF token of the session in a `csrf_token` form field
+pub async fn reject_invalid_csrf_tokens(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.call(req).await;
+    }
+
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let expected = session.get_csrf_token().map_err(e500)?;
+
+    // the body is read to find the token, then put back for the handler
+    let body = req.extract::<web::Bytes>().await?;
+    let submitted = serde_urlencoded::from_bytes::<CsrfForm>(&body)
+        .ok()
+        .and_then(|form| form.csrf_token);
+    req.set_payload(body.into());
+
+    // compared in constant time, so that the response time doesn't leak the token
+    match (expected, submitted) {
+        (Some(expected), Some(submitted))
+            if bool::from(expected.as_bytes().ct_eq(submitted.as_bytes())) =>
+        {
+            next.call(req).await
+        }
+        _ => {
+            let e = anyhow::anyhow!("The CSRF token is missing or invalid");
+            Err(InternalError::from_response(e, HttpResponse::Forbidden().finish()).into())
+        }
+    }
+}
+
+// Requests to the API authenticate with an `Authorization: Bearer <token>` header instead of a session.
+// Stores the user and their role, like `reject_anonymous_users`
+pub async fn reject_invalid_api_tokens(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::to_owned);
+    let user = match token {
+        Some(token) => {
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .expect("The Postgres pool is not registered as application data");
+            let hmac_secret = req
+                .app_data::<web::Data<HmacSecret>>()
+                .expect("The HMAC secret is not registered as application data");
+            authenticate_api_token(&token, &hmac_secret.0, pool)
+                .await
+                .map_err(e500)?
+        }
+        None => None,
+    };
+
+    match user {
+        Some((user_id, role)) => {
+            req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(role);
+            next.call(req).await
+        }
+        None => {
+            let response = HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                .json(serde_json::json!({ "error": "The API token is missing or invalid." }));
+            let e = anyhow::anyhow!("The API token is missing or invalid");
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+fn require_role(required: Role, req: &ServiceRequest) -> Result<(), actix_web::Error> {
+    let role = req.extensions().get::<Role>().copied();
+    match role {
+        Some(role) if role >= required => Ok(()),
+        _ => {
+            let e = anyhow::anyhow!("The user must be at least {}", required);
+            Err(InternalError::from_response(e, HttpResponse::Forbidden().finish()).into())
+        }
+    }
+}
+
+struct SessionUser {
+    role: Role,
+    // registered, neither revoked nor expired
+    session_active: bool,
+    // two-factor authentication is required, but the user hasn't set it up yet
+    two_factor_missing: bool,
+}
+
+fn is_allowed_without_two_factor(path: &str) -> bool {
+    path.starts_with("/admin/two-factor") || path == "/admin/logout"
+}
+
+#[tracing::instrument(name = "Get session user", skip(pool))]
+async fn get_session_user(
+    user_id: Uuid,
+    session_id: Option<Uuid>,
+    pool: &PgPool,
+) -> Result<Option<SessionUser>, anyhow::Error> {
+    // the session is marked as seen by the same query
+    let row = sqlx::query!(
+        r#"
+        WITH seen AS (
+            UPDATE user_sessions
+            SET last_seen_at = now()
+            WHERE
+                session_id = $2 AND
+                user_id = $1 AND
+                revoked_at IS NULL AND
+                expires_at > now()
+            RETURNING session_id
+        )
+        SELECT
+            role,
+            EXISTS (SELECT 1 FROM seen) as "session_active!",
+            totp_secret IS NULL AND two_factor_required as "two_factor_missing!"
+        FROM users u, admin_settings
+        WHERE u.user_id = $1
+        "#,
+        user_id,
+        session_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve the user of a session.")?;
+
+    row.map(|r| {
+        Ok(SessionUser {
+            role: Role::try_from(r.role).map_err(anyhow::Error::msg)?,
+            session_active: r.session_active,
+            two_factor_missing: r.two_factor_missing,
+        })
+    })
+    .transpose()
+}
@@ -1,5 +1,38 @@
+mod api_tokens;
 mod middleware;
 mod password;
+mod role;
+mod sessions;
+mod throttling;
+mod token;
+mod two_factor;
+mod users;
+pub use api_tokens::{
+    ApiToken, authenticate_api_token, create_api_token, list_api_tokens, revoke_api_token,
+};
 pub use middleware::UserId;
-pub use middleware::reject_anonymous_users;
-pub use password::{AuthError, Credentials, change_password, validate_credentials};
+pub use middleware::{
+    reject_anonymous_users, reject_invalid_api_tokens, reject_invalid_csrf_tokens,
+    reject_non_owners, reject_viewers,
+};
+pub use password::{
+    AuthError, Credentials, change_password, check_new_password, compute_password_hash,
+    validate_credentials, validate_new_password,
+};
+pub use role::Role;
+pub use sessions::{
+    UserSession, list_active_sessions, register_session, revoke_session, revoke_sessions,
+};
+pub use throttling::{
+    FailedLoginReason, LoginThrottle, ThrottleDecision, delete_expired_login_failures,
+    record_failed_login,
+};
+pub use token::{generate_token, sign_token};
+pub use two_factor::{
+    disable_two_factor, enable_two_factor, generate_totp_secret, get_totp_secret,
+    is_two_factor_required, set_two_factor_required, totp_code_step, totp_qr_code_svg, totp_uri,
+    use_recovery_code, verify_totp_code,
+};
+pub use users::{
+    CreateUserError, User, create_user, delete_user, get_user_id, insert_user, list_users, set_role,
+};
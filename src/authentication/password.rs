@@ -3,7 +3,7 @@ use anyhow::Context;
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
 use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
@@ -95,11 +95,11 @@ fn verify_password_hash(
         .map_err(|_| AuthError::InvalidCredentials(anyhow::anyhow!("Invalid password.")))
 }
 
-#[tracing::instrument(name = "Change password", skip(password, pool))]
+#[tracing::instrument(name = "Change password", skip(password, executor))]
 pub async fn change_password(
     user_id: uuid::Uuid,
     password: Secret<String>,
-    pool: &PgPool,
+    executor: impl PgExecutor<'_>,
 ) -> Result<(), anyhow::Error> {
     // move compute heavy task off to separate thread to prevent blocking the executor
     let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
@@ -115,14 +115,33 @@ pub async fn change_password(
         password_hash.expose_secret(),
         user_id
     )
-    .execute(pool)
+    .execute(executor)
     .await
     .context("Failed to change user's password in the database.")?;
 
     Ok(())
 }
 
-fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+// Checks of the new password fields of a form, which asks for the password twice
+pub fn check_new_password(
+    new_password: &Secret<String>,
+    new_password_check: &Secret<String>,
+) -> Result<(), &'static str> {
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        return Err("You entered two different new passwords - the field values must match.");
+    }
+    validate_new_password(new_password)
+}
+
+// Rules every new password must satisfy, wherever it is set from
+pub fn validate_new_password(password: &Secret<String>) -> Result<(), &'static str> {
+    if password.expose_secret().len() <= 12 {
+        return Err("New password should be greater than 12 characters.");
+    }
+    Ok(())
+}
+
+pub fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
     let salt = SaltString::generate(&mut rand::thread_rng());
     let password_hash = Argon2::new(
         Algorithm::Argon2id,
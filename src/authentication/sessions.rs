@@ -0,0 +1,112 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::session_state::SESSION_TTL;
+
+pub struct UserSession {
+    pub session_id: Uuid,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+// Called on every successful login, the returned id is stored in the session state
+#[tracing::instrument(name = "Register session", skip(user_agent, pool))]
+pub async fn register_session(
+    user_id: Uuid,
+    ip_address: &str,
+    user_agent: &str,
+    pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let session_id = Uuid::new_v4();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions
+            (session_id, user_id, ip_address, user_agent, created_at, last_seen_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $5, $6)
+        "#,
+        session_id,
+        user_id,
+        ip_address,
+        user_agent,
+        now,
+        now + SESSION_TTL,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to register a session.")?;
+    Ok(session_id)
+}
+
+// Sessions which have neither been revoked nor expired, the most recently used first
+#[tracing::instrument(name = "List active sessions", skip(pool))]
+pub async fn list_active_sessions(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<UserSession>, anyhow::Error> {
+    let sessions = sqlx::query_as!(
+        UserSession,
+        r#"
+        SELECT session_id, ip_address, user_agent, created_at, last_seen_at
+        FROM user_sessions
+        WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > now()
+        ORDER BY last_seen_at DESC
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to list the sessions of a user.")?;
+    Ok(sessions)
+}
+
+// Revoked sessions are rejected by `reject_anonymous_users` on their next request
+#[tracing::instrument(name = "Revoke session", skip(pool))]
+pub async fn revoke_session(
+    user_id: Uuid,
+    session_id: Uuid,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE user_sessions
+        SET revoked_at = now()
+        WHERE user_id = $1 AND session_id = $2 AND revoked_at IS NULL
+        "#,
+        user_id,
+        session_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke a session.")?;
+    Ok(())
+}
+
+// All the sessions of the user but `except`, typically the one performing the request
+#[tracing::instrument(name = "Revoke user sessions", skip(executor))]
+pub async fn revoke_sessions(
+    user_id: Uuid,
+    except: Option<Uuid>,
+    executor: impl PgExecutor<'_>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE user_sessions
+        SET revoked_at = now()
+        WHERE
+            user_id = $1 AND
+            revoked_at IS NULL AND
+            session_id IS DISTINCT FROM $2
+        "#,
+        user_id,
+        except,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to revoke the sessions of a user.")?;
+    Ok(())
+}
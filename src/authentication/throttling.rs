@@ -0,0 +1,329 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions, aio::ConnectionManager};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    configuration::LoginThrottlingSettings,
+};
+
+pub enum ThrottleDecision {
+    // the credentials can be checked once the delay has elapsed
+    Allowed { delay: Duration },
+    LockedOut { retry_after: Duration },
+}
+
+// Failed login attempts are counted per username and per IP address, in Redis or in Postgres
+// when there is no Redis, with counters expiring a while after the last failure.
+// Each attempt is counted as a failure before the credentials are checked, and refunded when
+// they are valid: concurrent attempts can't all get past the limits
+#[derive(Clone)]
+pub struct LoginThrottle {
+    counters: Counters,
+    settings: LoginThrottlingSettings,
+}
+
+#[derive(Clone)]
+enum Counters {
+    Redis(ConnectionManager),
+    // expired counters are deleted by the cleanup worker
+    Postgres(PgPool),
+}
+
+impl LoginThrottle {
+    pub async fn redis(
+        redis_uri: &Secret<String>,
+        settings: LoginThrottlingSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(redis_uri.expose_secret().as_str())?;
+        let redis = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(Self {
+            counters: Counters::Redis(redis),
+            settings,
+        })
+    }
+
+    pub fn postgres(pool: PgPool, settings: LoginThrottlingSettings) -> Self {
+        Self {
+            counters: Counters::Postgres(pool),
+            settings,
+        }
+    }
+
+    // Locked out attempts are not counted, they don't push the end of the lockout back
+    #[tracing::instrument(name = "Reserve a login attempt", skip(self))]
+    pub async fn reserve_attempt(
+        &self,
+        username: &str,
+        ip: &str,
+    ) -> Result<ThrottleDecision, anyhow::Error> {
+        let username_key = self.username_key(username);
+        let ip_key = self.ip_key(ip);
+        let window = self.settings.window_seconds as i64;
+
+        let Some(username_failures) = self
+            .counters
+            .reserve(
+                &username_key,
+                self.settings.max_attempts_per_username,
+                window,
+            )
+            .await?
+        else {
+            return self.locked_out(&username_key).await;
+        };
+        if self
+            .counters
+            .reserve(&ip_key, self.settings.max_attempts_per_ip, window)
+            .await?
+            .is_none()
+        {
+            self.counters.refund(&username_key).await?;
+            return self.locked_out(&ip_key).await;
+        }
+
+        // the failures before this attempt
+        Ok(ThrottleDecision::Allowed {
+            delay: delay(username_failures - 1, &self.settings),
+        })
+    }
+
+    // The credentials were valid, but the login isn't complete yet (e.g. a second factor is needed)
+    #[tracing::instrument(name = "Refund a login attempt", skip(self))]
+    pub async fn refund_attempt(&self, username: &str, ip: &str) -> Result<(), anyhow::Error> {
+        self.counters.refund(&self.username_key(username)).await?;
+        self.counters.refund(&self.ip_key(ip)).await
+    }
+
+    // The failures of the username are forgiven, but only the attempt is refunded to the IP
+    // address: an attacker could otherwise reset its counter by logging in to their own account
+    #[tracing::instrument(name = "Reset login throttling", skip(self))]
+    pub async fn reset(&self, username: &str, ip: &str) -> Result<(), anyhow::Error> {
+        self.counters.delete(&self.username_key(username)).await?;
+        self.counters.refund(&self.ip_key(ip)).await
+    }
+
+    async fn locked_out(&self, key: &str) -> Result<ThrottleDecision, anyhow::Error> {
+        let ttl = self.counters.ttl_seconds(key).await?;
+        Ok(ThrottleDecision::LockedOut {
+            retry_after: Duration::from_secs(ttl.max(1) as u64),
+        })
+    }
+
+    fn username_key(&self, username: &str) -> String {
+        format!("{}:username:{}", self.settings.key_prefix, username)
+    }
+
+    fn ip_key(&self, ip: &str) -> String {
+        format!("{}:ip:{}", self.settings.key_prefix, ip)
+    }
+}
+
+impl Counters {
+    // Increments the counter unless it already reached the limit, returning its new value.
+    // Every increment pushes the expiry of the counter back by a window
+    async fn reserve(
+        &self,
+        key: &str,
+        limit: u64,
+        window_seconds: i64,
+    ) -> Result<Option<u64>, anyhow::Error> {
+        match self {
+            Counters::Redis(redis) => {
+                let mut redis = redis.clone();
+                // a new counter gets an expiry at once, so that it can't be left without one
+                let (count,): (u64,) = redis::pipe()
+                    .atomic()
+                    .set_options(
+                        key,
+                        0,
+                        SetOptions::default()
+                            .conditional_set(ExistenceCheck::NX)
+                            .with_expiration(SetExpiry::EX(window_seconds as u64)),
+                    )
+                    .ignore()
+                    .incr(key, 1)
+                    .query_async(&mut redis)
+                    .await?;
+                if count > limit {
+                    let _: i64 = redis.decr(key, 1).await?;
+                    return Ok(None);
+                }
+                let _: bool = redis.expire(key, window_seconds).await?;
+                Ok(Some(count))
+            }
+            Counters::Postgres(pool) => {
+                let count = sqlx::query_scalar!(
+                    r#"
+                    INSERT INTO login_failure_counters AS c (counter_key, failures, expires_at)
+                    VALUES ($1, 1, now() + $2 * INTERVAL '1 second')
+                    ON CONFLICT (counter_key) DO UPDATE SET
+                        failures = CASE WHEN c.expires_at > now() THEN c.failures + 1 ELSE 1 END,
+                        expires_at = EXCLUDED.expires_at
+                    WHERE c.expires_at <= now() OR c.failures < $3
+                    RETURNING failures
+                    "#,
+                    key,
+                    window_seconds as f64,
+                    limit as i64
+                )
+                .fetch_optional(pool)
+                .await?;
+                Ok(count.map(|count| count as u64))
+            }
+        }
+    }
+
+    async fn refund(&self, key: &str) -> Result<(), anyhow::Error> {
+        match self {
+            Counters::Redis(redis) => {
+                let mut redis = redis.clone();
+                let count: i64 = redis.decr(key, 1).await?;
+                // the counter expired in the meantime, and was recreated by the decrement
+                if count < 0 {
+                    let _: u64 = redis.del(key).await?;
+                }
+            }
+            Counters::Postgres(pool) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE login_failure_counters SET failures = failures - 1
+                    WHERE counter_key = $1 AND failures > 0
+                    "#,
+                    key
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn ttl_seconds(&self, key: &str) -> Result<i64, anyhow::Error> {
+        Ok(match self {
+            Counters::Redis(redis) => redis.clone().ttl(key).await?,
+            Counters::Postgres(pool) => sqlx::query_scalar!(
+                r#"
+                SELECT EXTRACT(EPOCH FROM expires_at - now())::int8 as "ttl!"
+                FROM login_failure_counters WHERE counter_key = $1
+                "#,
+                key
+            )
+            .fetch_optional(pool)
+            .await?
+            .unwrap_or(0),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), anyhow::Error> {
+        match self {
+            Counters::Redis(redis) => {
+                let _: u64 = redis.clone().del(key).await?;
+            }
+            Counters::Postgres(pool) => {
+                sqlx::query!(
+                    "DELETE FROM login_failure_counters WHERE counter_key = $1",
+                    key
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_expired_login_failures(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let deleted = sqlx::query!("DELETE FROM login_failure_counters WHERE expires_at <= now()")
+        .execute(pool)
+        .await?;
+    Ok(deleted.rows_affected())
+}
+
+// Delay before checking the credentials of a username which already failed `failures` times
+fn delay(failures: u64, settings: &LoginThrottlingSettings) -> Duration {
+    if failures < settings.free_attempts {
+        return Duration::ZERO;
+    }
+    let exponent = (failures - settings.free_attempts).min(32) as u32;
+    let delay = settings
+        .base_delay_milliseconds
+        .saturating_mul(2u64.pow(exponent))
+        .min(settings.max_delay_milliseconds);
+    Duration::from_millis(delay)
+}
+
+pub enum FailedLoginReason {
+    InvalidCredentials,
+    InvalidTwoFactorCode,
+    LockedOut,
+}
+
+impl FailedLoginReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FailedLoginReason::InvalidCredentials => "invalid_credentials",
+            FailedLoginReason::InvalidTwoFactorCode => "invalid_two_factor_code",
+            FailedLoginReason::LockedOut => "locked_out",
+        }
+    }
+}
+
+// Kept in the audit log for later review, unlike the counters which expire
+#[tracing::instrument(name = "Record failed login attempt", skip(pool, reason))]
+pub async fn record_failed_login(
+    username: &str,
+    ip: &str,
+    reason: FailedLoginReason,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    metrics::counter!("login_failures_total", "reason" => reason.as_str()).increment(1);
+    AuditEvent::new(Actor::Anonymous, AuditAction::LoginFailed)
+        .target(username)
+        .ip_address(ip)
+        .details(reason.as_str())
+        .record(pool)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::delay;
+    use crate::configuration::LoginThrottlingSettings;
+
+    fn settings() -> LoginThrottlingSettings {
+        LoginThrottlingSettings {
+            window_seconds: 900,
+            free_attempts: 3,
+            base_delay_milliseconds: 500,
+            max_delay_milliseconds: 8000,
+            max_attempts_per_username: 10,
+            max_attempts_per_ip: 100,
+            key_prefix: "login_failures".into(),
+        }
+    }
+
+    #[test]
+    fn the_first_attempts_are_not_delayed() {
+        for failures in 0..3 {
+            assert_eq!(delay(failures, &settings()), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn delays_double_with_each_failure_up_to_the_max() {
+        let delays: Vec<_> = (3..10)
+            .map(|failures| delay(failures, &settings()).as_millis())
+            .collect();
+        assert_eq!(delays, vec![500, 1000, 2000, 4000, 8000, 8000, 8000]);
+        assert_eq!(delay(u64::MAX, &settings()), Duration::from_millis(8000));
+    }
+}
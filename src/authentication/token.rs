@@ -0,0 +1,49 @@
+use hmac::{Hmac, Mac};
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+// 32 random alphanumeric characters => 62^32 possible tokens
+pub fn generate_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    let token = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect();
+    Secret::new(token)
+}
+
+// Tokens sent by email are never stored as-is: we keep their HMAC-SHA256 signature,
+// so that a leaked database doesn't allow to use them
+pub fn sign_token(token: &str, hmac_secret: &Secret<String>) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes()).unwrap();
+    mac.update(token.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::{ExposeSecret, Secret};
+
+    use crate::authentication::{generate_token, sign_token};
+
+    #[test]
+    fn generated_tokens_are_unique() {
+        assert_ne!(
+            generate_token().expose_secret(),
+            generate_token().expose_secret()
+        );
+    }
+
+    #[test]
+    fn signatures_depend_on_the_secret() {
+        let token = generate_token();
+        let a = sign_token(token.expose_secret(), &Secret::new("a-secret".into()));
+        let b = sign_token(token.expose_secret(), &Secret::new("another-secret".into()));
+        assert_eq!(
+            a,
+            sign_token(token.expose_secret(), &Secret::new("a-secret".into()))
+        );
+        assert_ne!(a, b);
+    }
+}
@@ -0,0 +1,316 @@
+use anyhow::Context;
+use chrono::Utc;
+use rand::{Rng, RngCore, distributions::Alphanumeric, thread_rng};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgExecutor, PgPool};
+use totp_rs::{Algorithm, TOTP};
+use uuid::Uuid;
+
+use crate::authentication::sign_token;
+
+const TOTP_ISSUER: &str = "Newsletter";
+const RECOVERY_CODES_COUNT: usize = 10;
+
+// 160 bits, as recommended by RFC 4226, base32 encoded as expected by authenticator apps
+pub fn generate_totp_secret() -> Secret<String> {
+    let mut bytes = vec![0u8; 20];
+    thread_rng().fill_bytes(&mut bytes);
+    Secret::new(totp_rs::Secret::Raw(bytes).to_encoded().to_string())
+}
+
+// Standard parameters (SHA1, 6 digits, 30s steps), the only ones most authenticator apps support.
+// A code of the previous or next step is accepted too, to allow for clock drift
+fn totp(secret: &Secret<String>, username: &str) -> Result<TOTP, anyhow::Error> {
+    let secret = totp_rs::Secret::Encoded(secret.expose_secret().clone())
+        .to_bytes()
+        .context("Invalid TOTP secret")?;
+    Ok(TOTP::new_unchecked(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(TOTP_ISSUER.into()),
+        username.into(),
+    ))
+}
+
+// URI to scan (as a QR code) or to copy in an authenticator app
+pub fn totp_uri(secret: &Secret<String>, username: &str) -> Result<String, anyhow::Error> {
+    Ok(totp(secret, username)?.get_url())
+}
+
+pub fn totp_qr_code_svg(uri: &str) -> Result<String, anyhow::Error> {
+    let code = qrcode::QrCode::new(uri).context("Failed to encode a QR code")?;
+    Ok(code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build())
+}
+
+// Returns the time step of the code, if it's valid now
+pub fn totp_code_step(secret: &Secret<String>, code: &str) -> Result<Option<i64>, anyhow::Error> {
+    // the username is only part of the URI, it doesn't change the codes
+    let totp = totp(secret, "")?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("The system clock is set before the UNIX epoch")?
+        .as_secs();
+    let current_step = (now / totp.step) as i64;
+    let skew = totp.skew as i64;
+    // a single step is checked at a time, to find out which one the code belongs to
+    let exact = TOTP { skew: 0, ..totp };
+    Ok((current_step - skew..=current_step + skew)
+        .find(|step| exact.check(code.trim(), *step as u64 * exact.step)))
+}
+
+// Each code can only be used once: codes of the same or an earlier step than the last one
+// accepted are rejected, even if they are still valid
+#[tracing::instrument(name = "Verify a TOTP code", skip(secret, code, pool))]
+pub async fn verify_totp_code(
+    user_id: Uuid,
+    secret: &Secret<String>,
+    code: &str,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let Some(step) = totp_code_step(secret, code)? else {
+        return Ok(false);
+    };
+    let result = sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_last_used_step = $2
+        WHERE user_id = $1 AND (totp_last_used_step IS NULL OR totp_last_used_step < $2)
+        "#,
+        user_id,
+        step,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the last TOTP code used by a user.")?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// Long enough not to be guessed, short enough to be typed from a printed copy
+fn generate_recovery_code() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .take(12)
+        .collect()
+}
+
+#[tracing::instrument(name = "Get TOTP secret", skip(pool))]
+pub async fn get_totp_secret(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Secret<String>>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve the TOTP secret of a user.")?;
+
+    Ok(row.and_then(|r| r.totp_secret).map(Secret::new))
+}
+
+// Returns the recovery codes, which can only be shown once since only their signature is stored.
+// `code_step` is the step of the code entered to enroll, which can't be used again
+#[tracing::instrument(
+    name = "Enable two-factor authentication",
+    skip(secret, hmac_secret, pool)
+)]
+pub async fn enable_two_factor(
+    user_id: Uuid,
+    secret: &Secret<String>,
+    code_step: i64,
+    hmac_secret: &Secret<String>,
+    pool: &PgPool,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = $1, totp_last_used_step = $3
+        WHERE user_id = $2
+        "#,
+        secret.expose_secret(),
+        user_id,
+        code_step,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the TOTP secret of a user.")?;
+    delete_recovery_codes(user_id, &mut *transaction).await?;
+
+    let recovery_codes: Vec<String> = std::iter::repeat_with(generate_recovery_code)
+        .take(RECOVERY_CODES_COUNT)
+        .collect();
+    let now = Utc::now();
+    for code in &recovery_codes {
+        sqlx::query!(
+            r#"
+            INSERT INTO recovery_codes (code_signature, user_id, created_at)
+            VALUES ($1, $2, $3)
+            "#,
+            sign_token(code, hmac_secret),
+            user_id,
+            now,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store a recovery code.")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable two-factor authentication.")?;
+    Ok(recovery_codes)
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable_two_factor(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = NULL, totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the TOTP secret of a user.")?;
+    delete_recovery_codes(user_id, &mut *transaction).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable two-factor authentication.")?;
+    Ok(())
+}
+
+async fn delete_recovery_codes(
+    user_id: Uuid,
+    executor: impl PgExecutor<'_>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM recovery_codes
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to delete the recovery codes of a user.")?;
+    Ok(())
+}
+
+// Each recovery code can only be used once
+#[tracing::instrument(name = "Use a recovery code", skip(code, hmac_secret, pool))]
+pub async fn use_recovery_code(
+    user_id: Uuid,
+    code: &str,
+    hmac_secret: &Secret<String>,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = now()
+        WHERE user_id = $1 AND code_signature = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        sign_token(&code.trim().to_ascii_lowercase(), hmac_secret),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to use a recovery code.")?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(
+    name = "Check whether two-factor authentication is required",
+    skip(pool)
+)]
+pub async fn is_two_factor_required(pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!("SELECT two_factor_required FROM admin_settings")
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve the admin settings.")?;
+    Ok(row.two_factor_required)
+}
+
+#[tracing::instrument(name = "Require two-factor authentication", skip(pool))]
+pub async fn set_two_factor_required(required: bool, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE admin_settings SET two_factor_required = $1",
+        required
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the admin settings.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::{generate_totp_secret, totp, totp_code_step, totp_uri};
+
+    #[test]
+    fn codes_of_the_previous_current_and_next_steps_are_accepted() {
+        let secret = generate_totp_secret();
+        let totp = totp(&secret, "").unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        for offset in [-1, 0, 1] {
+            let time = (now as i64 + offset * totp.step as i64) as u64;
+            let code = totp.generate(time);
+            assert_eq!(
+                totp_code_step(&secret, &code).unwrap(),
+                Some((time / totp.step) as i64)
+            );
+        }
+    }
+
+    #[test]
+    fn codes_that_are_not_6_digits_are_rejected() {
+        let secret = generate_totp_secret();
+        for code in ["", "12345", "1234567", "abcdef"] {
+            assert_eq!(totp_code_step(&secret, code).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn the_uri_can_be_added_to_an_authenticator_app() {
+        let secret = Secret::new("JBSWY3DPEHPK3PXP".to_string());
+        let uri = totp_uri(&secret, "admin").unwrap();
+        assert_eq!(
+            uri,
+            "otpauth://totp/Newsletter:admin?secret=JBSWY3DPEHPK3PXP&issuer=Newsletter"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::authentication::Role;
+use crate::authentication::password::compute_password_hash;
+use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+use crate::telemetry::spawn_blocking_with_tracing;
+
+pub struct User {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: Role,
+}
+
+#[derive(thiserror::Error)]
+pub enum CreateUserError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for CreateUserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(name = "Create user", skip(password, pool))]
+pub async fn create_user(
+    username: &str,
+    password: Secret<String>,
+    role: Role,
+    email: Option<&SubscriberEmail>,
+    pool: &PgPool,
+) -> Result<Uuid, CreateUserError> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    insert_user(username, password, role, email, &mut connection).await
+}
+
+// Takes a connection rather than a pool, so that it can be part of a larger transaction
+#[tracing::instrument(name = "Insert user", skip(password, connection))]
+pub async fn insert_user(
+    username: &str,
+    password: Secret<String>,
+    role: Role,
+    email: Option<&SubscriberEmail>,
+    connection: &mut PgConnection,
+) -> Result<Uuid, CreateUserError> {
+    if username.trim().is_empty() {
+        return Err(CreateUserError::ValidationError(
+            "The username cannot be empty.".into(),
+        ));
+    }
+    let existing_user = sqlx::query!(
+        r#"
+        SELECT username, email
+        FROM users
+        WHERE username = $1 OR email = $2
+        "#,
+        username,
+        email.map(|e| e.as_ref().as_str()),
+    )
+    .fetch_optional(&mut *connection)
+    .await
+    .context("Failed to perform a query to look for an existing user.")?;
+    if let Some(existing_user) = existing_user {
+        let e = if existing_user.username == username {
+            format!("A user named {} already exists.", username)
+        } else {
+            "A user with this email address already exists.".to_string()
+        };
+        return Err(CreateUserError::ValidationError(e));
+    }
+
+    // move compute heavy task off to separate thread to prevent blocking the executor
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")?
+        .context("Failed to hash password")?;
+
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role, email)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        role.as_str(),
+        email.map(|e| e.as_ref().as_str()),
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to store the new user in the database.")?;
+
+    Ok(user_id)
+}
+
+#[tracing::instrument(name = "Get user id", skip(pool))]
+pub async fn get_user_id(username: &str, pool: &PgPool) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id
+        FROM users
+        WHERE username = $1
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a user id.")?;
+
+    Ok(row.map(|r| r.user_id))
+}
+
+#[tracing::instrument(name = "List users", skip(pool))]
+pub async fn list_users(pool: &PgPool) -> Result<Vec<User>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT user_id, username, role
+        FROM users
+        ORDER BY username
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to list users.")?;
+
+    rows.into_iter()
+        .map(|r| {
+            Ok(User {
+                user_id: r.user_id,
+                username: r.username,
+                role: Role::try_from(r.role).map_err(anyhow::Error::msg)?,
+            })
+        })
+        .collect()
+}
+
+#[tracing::instrument(name = "Set user role", skip(pool))]
+pub async fn set_role(user_id: Uuid, role: Role, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET role = $1
+        WHERE user_id = $2
+        "#,
+        role.as_str(),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change the role of a user in the database.")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Delete user", skip(pool))]
+pub async fn delete_user(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    // saved idempotent responses reference the user
+    sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the user's idempotency keys.")?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM users
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the user from the database.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a user.")?;
+
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use std::io::IsTerminal;
+
+use clap::{Parser, Subcommand};
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use crate::audit::{Actor, AuditAction, AuditEvent};
+use crate::authentication::{
+    Role, change_password, create_user, delete_user, get_user_id, list_users, revoke_sessions,
+    set_role, validate_new_password,
+};
+use crate::domain::SubscriberEmail;
+
+// Each mode only initializes what it needs, so web and worker processes can be scaled independently
+#[derive(Parser)]
+#[command(
+    name = "newsletter",
+    version,
+    about = "Newsletter API and background workers"
+)]
+pub struct Cli {
+    // defaults to `all` when no subcommand is given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP API
+    Serve,
+    /// Run a background worker
+    Worker {
+        #[command(subcommand)]
+        worker: Worker,
+    },
+    /// Run the HTTP API and all background workers in a single process
+    All,
+    /// Manage admin users
+    Admin {
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+    /// Apply pending database migrations
+    Migrate,
+    /// Check the configuration and report all its problems, without starting anything
+    CheckConfig,
+}
+
+#[derive(Subcommand)]
+pub enum Worker {
+    /// Deliver queued newsletter issues to subscribers
+    Delivery,
+    /// Delete expired idempotency keys, and expired sessions when they are stored in Postgres
+    Cleanup,
+}
+
+// Passwords are never taken as arguments (they would end up in the shell history):
+// they are prompted for on a terminal, or read from the first line of stdin otherwise
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// Create a new admin user
+    CreateUser {
+        #[arg(long)]
+        username: String,
+        /// One of `owner`, `editor` or `viewer`
+        #[arg(long, value_parser = parse_role)]
+        role: Role,
+        /// Used to send password reset links
+        #[arg(long, value_parser = parse_email)]
+        email: Option<SubscriberEmail>,
+    },
+    /// Set a new password for an existing admin user
+    ResetPassword {
+        #[arg(long)]
+        username: String,
+    },
+    /// Change the role of an existing admin user
+    SetRole {
+        #[arg(long)]
+        username: String,
+        /// One of `owner`, `editor` or `viewer`
+        #[arg(long, value_parser = parse_role)]
+        role: Role,
+    },
+    /// List all admin users
+    ListUsers,
+    /// Delete an admin user
+    DeleteUser {
+        #[arg(long)]
+        username: String,
+    },
+}
+
+pub async fn run_admin_command(command: AdminCommand, pool: &PgPool) -> Result<(), anyhow::Error> {
+    match command {
+        AdminCommand::CreateUser {
+            username,
+            role,
+            email,
+        } => {
+            let password = read_new_password()?;
+            let user_id = create_user(&username, password, role, email.as_ref(), pool).await?;
+            AuditEvent::new(Actor::CommandLine, AuditAction::UserCreated)
+                .target(&username)
+                .details(format!("as {}", role))
+                .record(pool)
+                .await?;
+            println!("Created {role} {username} ({user_id})");
+        }
+        AdminCommand::ResetPassword { username } => {
+            let user_id = find_user(&username, pool).await?;
+            let password = read_new_password()?;
+            change_password(user_id, password, pool).await?;
+            revoke_sessions(user_id, None, pool).await?;
+            AuditEvent::new(Actor::CommandLine, AuditAction::PasswordReset)
+                .target(&username)
+                .record(pool)
+                .await?;
+            println!("Changed the password of {username}");
+        }
+        AdminCommand::SetRole { username, role } => {
+            let user_id = find_user(&username, pool).await?;
+            set_role(user_id, role, pool).await?;
+            AuditEvent::new(Actor::CommandLine, AuditAction::RoleChanged)
+                .target(&username)
+                .details(format!("to {}", role))
+                .record(pool)
+                .await?;
+            println!("{username} is now {role}");
+        }
+        AdminCommand::ListUsers => {
+            for user in list_users(pool).await? {
+                println!("{}\t{}\t{}", user.user_id, user.username, user.role);
+            }
+        }
+        AdminCommand::DeleteUser { username } => {
+            let user_id = find_user(&username, pool).await?;
+            delete_user(user_id, pool).await?;
+            AuditEvent::new(Actor::CommandLine, AuditAction::UserDeleted)
+                .target(&username)
+                .record(pool)
+                .await?;
+            println!("Deleted user {username}");
+        }
+    }
+    Ok(())
+}
+
+pub async fn run_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::migrate!("./migrations").run(pool).await?;
+    println!("Database migrations applied");
+    Ok(())
+}
+
+async fn find_user(username: &str, pool: &PgPool) -> Result<uuid::Uuid, anyhow::Error> {
+    get_user_id(username, pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("There is no user named {}", username))
+}
+
+fn parse_role(s: &str) -> Result<Role, String> {
+    Role::try_from(s.to_owned())
+}
+
+fn parse_email(s: &str) -> Result<SubscriberEmail, String> {
+    SubscriberEmail::parse(s.to_owned())
+}
+
+fn read_new_password() -> Result<Secret<String>, anyhow::Error> {
+    let password = if std::io::stdin().is_terminal() {
+        let password = rpassword::prompt_password("New password: ")?;
+        let password_check = rpassword::prompt_password("Confirm new password: ")?;
+        if password != password_check {
+            anyhow::bail!("You entered two different new passwords - the field values must match.");
+        }
+        password
+    } else {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_owned()
+    };
+
+    let password = Secret::new(password);
+    validate_new_password(&password).map_err(|e| anyhow::anyhow!(e))?;
+    Ok(password)
+}
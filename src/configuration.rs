@@ -1,8 +1,17 @@
+use std::collections::HashMap;
+
+use ipnet::IpNet;
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+    deserialize_vec_from_string_or_vec,
+};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+    domain::SubscriberEmail, email_client::EmailClient, idempotency::IdempotentEndpoint,
+    routes::error_chain_fmt,
+};
 
 #[derive(Clone, serde::Deserialize)]
 pub struct Settings {
@@ -10,7 +19,154 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
-    pub idempotent_time_interval: f64,
+    pub session_store: SessionStoreSettings,
+    pub idempotency: IdempotencySettings,
+    pub delivery_worker: DeliveryWorkerSettings,
+    pub login_throttling: LoginThrottlingSettings,
+    pub telemetry: TelemetrySettings,
+    pub health: HealthSettings,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct SessionStoreSettings {
+    pub backend: SessionStoreBackend,
+    // how often the cleanup worker deletes expired sessions, when they are stored in Postgres
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_interval_seconds: u64,
+}
+
+impl SessionStoreSettings {
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_seconds)
+    }
+}
+
+// Redis is only needed with the `redis` backend: failed logins are otherwise counted in Postgres
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreBackend {
+    Redis,
+    Postgres,
+    // sessions are lost on restart and not shared between instances
+    Memory,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct HealthSettings {
+    // each dependency check of the readiness probe gives up after this long
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    // most requests don't need the email provider: it's only checked when asked to
+    pub check_email_provider: bool,
+    // workers which haven't reported for longer are stale
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub heartbeat_max_age_seconds: u64,
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct TelemetrySettings {
+    pub service_name: String,
+    // base URL of an OpenTelemetry collector accepting OTLP over HTTP, e.g. http://localhost:4318:
+    // spans are only exported when it's set
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    // share of the traces started here which are exported, between 0 and 1;
+    // traces started by a caller follow its sampling decision
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub sampling_ratio: f64,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct IdempotencySettings {
+    // how long a saved response is replayed to retries before the cleaner deletes it
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retention_seconds: u64,
+    // overrides the retention of some endpoints
+    #[serde(default)]
+    pub endpoint_retention_seconds: HashMap<IdempotentEndpoint, u64>,
+    pub anonymous_quota: AnonymousQuotaSettings,
+    // how long the cleaner waits once no expired key is left, or after a failed run
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_idle_interval_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_error_backoff_milliseconds: u64,
+}
+
+// Keys stored for requests without a session, past which they are processed without a key
+#[derive(Clone, serde::Deserialize)]
+pub struct AnonymousQuotaSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub per_client: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub total: u64,
+}
+
+impl IdempotencySettings {
+    pub fn retention(&self, endpoint: IdempotentEndpoint) -> std::time::Duration {
+        let seconds = self
+            .endpoint_retention_seconds
+            .get(&endpoint)
+            .copied()
+            .unwrap_or(self.retention_seconds);
+        std::time::Duration::from_secs(seconds)
+    }
+
+    pub fn cleanup_idle_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.cleanup_idle_interval_milliseconds)
+    }
+
+    pub fn cleanup_error_backoff(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.cleanup_error_backoff_milliseconds)
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct LoginThrottlingSettings {
+    // failed attempts are forgotten this long after the last one,
+    // which is also how long a lockout lasts
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+    // failed attempts for a username before its login attempts start being delayed
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub free_attempts: u64,
+    // doubled with every further failed attempt, up to the max delay
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_username: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_ip: u64,
+    // prepended to the keys of the counters, e.g. to share a Redis between several instances
+    pub key_prefix: String,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct DeliveryWorkerSettings {
+    // number of concurrent delivery workers spawned in a single process
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pool_size: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_poll_interval_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub error_backoff_milliseconds: u64,
+}
+
+impl DeliveryWorkerSettings {
+    pub fn idle_poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.idle_poll_interval_milliseconds)
+    }
+
+    pub fn error_backoff(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.error_backoff_milliseconds)
+    }
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -30,15 +186,19 @@ impl EmailClientSettings {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
 
-    pub fn client(self) -> EmailClient {
-        let sender_email = self.sender().expect("Invalid sender email address.");
+    pub fn client(self) -> Result<EmailClient, ConfigurationError> {
+        let sender_email = self.sender().map_err(|e| {
+            ConfigurationError::Invalid(vec![format!(
+                "`email_client.sender_email` is invalid: {e}"
+            )])
+        })?;
         let timeout = self.timeout();
-        EmailClient::new(
+        Ok(EmailClient::new(
             self.base_url,
             sender_email,
             self.authorization_token,
             timeout,
-        )
+        ))
     }
 }
 
@@ -49,6 +209,41 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_timeout_seconds: u64,
+    // applied to both the session and the flash message cookies
+    pub cookie_same_site: CookieSameSite,
+    // `/metrics` is served on this port instead of the public one when set
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub metrics_port: Option<u16>,
+    // reverse proxies in front of the app: the client address is read from the X-Forwarded-For
+    // header of their requests only, since anyone else could make it up
+    #[serde(default, deserialize_with = "deserialize_vec_from_string_or_vec")]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<CookieSameSite> for actix_web::cookie::SameSite {
+    fn from(value: CookieSameSite) -> Self {
+        match value {
+            CookieSameSite::Strict => actix_web::cookie::SameSite::Strict,
+            CookieSameSite::Lax => actix_web::cookie::SameSite::Lax,
+            CookieSameSite::None => actix_web::cookie::SameSite::None,
+        }
+    }
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -83,7 +278,58 @@ impl DatabaseSettings {
     }
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+#[derive(thiserror::Error)]
+pub enum ConfigurationError {
+    #[error("{0}")]
+    UnknownEnvironment(String),
+    #[error("Failed to read `{path}`, the file named by {variable}")]
+    SecretFile {
+        variable: String,
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Both {0} and {0}_FILE are set, only one of them can be")]
+    ConflictingSecret(String),
+    #[error("Failed to read the configuration")]
+    Read(#[from] config::ConfigError),
+    #[error("Invalid configuration:\n{}", .0.iter().map(|p| format!("  - {p}")).collect::<Vec<_>>().join("\n"))]
+    Invalid(Vec<String>),
+}
+
+impl std::fmt::Debug for ConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+// The configuration is validated once read, so that a bad value fails the startup (or
+// `newsletter check-config`) rather than the first request using it
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    let settings = read_configuration()?.try_deserialize::<Settings>()?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+// For the one-off commands which only connect to the database (`admin` and `migrate`): the other
+// settings aren't validated, they may be invalid or incomplete
+pub fn get_database_configuration() -> Result<DatabaseSettings, ConfigurationError> {
+    database_settings(&read_configuration()?)
+}
+
+fn database_settings(
+    configuration: &config::Config,
+) -> Result<DatabaseSettings, ConfigurationError> {
+    let database: DatabaseSettings = configuration.get("database")?;
+    let mut problems = Vec::new();
+    database.validate(&mut problems);
+    match problems.is_empty() {
+        true => Ok(database),
+        false => Err(ConfigurationError::Invalid(problems)),
+    }
+}
+
+fn read_configuration() -> Result<config::Config, ConfigurationError> {
     let base_path = std::env::current_dir().expect("Failed to determine the current directory");
     let configuration_directory = base_path.join("configuration");
 
@@ -91,7 +337,18 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let environment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".into())
         .try_into() // convert from string to our custom type
-        .expect("Failed to parse APP_ENVIRONMENT.");
+        .map_err(ConfigurationError::UnknownEnvironment)?;
+    let environment_file = configuration_directory.join(environment.as_str());
+    if !["yaml", "yml"]
+        .iter()
+        .any(|extension| environment_file.with_extension(extension).exists())
+    {
+        return Err(ConfigurationError::UnknownEnvironment(format!(
+            "The {} environment has no configuration file, create configuration/{}.yaml",
+            environment.as_str(),
+            environment.as_str()
+        )));
+    }
 
     //// Print environment variables for debugging
     // for (key, value) in std::env::vars() {
@@ -103,30 +360,251 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     // read the default configuration file
     let settings = config::Config::builder()
         .add_source(config::File::from(configuration_directory.join("base")).required(true))
-        .add_source(
-            config::File::from(configuration_directory.join(environment.as_str())).required(true),
-        )
+        .add_source(config::File::from(environment_file).required(true))
+        // git-ignored, for settings of a single machine such as a local database password
+        .add_source(config::File::from(configuration_directory.join("override")).required(false))
         .add_source(
             config::Environment::with_prefix("app")
                 .separator("__")
-                .prefix_separator("_"),
+                .prefix_separator("_")
+                .source(Some(read_secret_files(std::env::vars())?)),
         ) // Add in settings from env variables, E.g.`APP_APPLICATION__PORT=5001 would set`Settings.application.port`
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    Ok(settings)
 }
 
-pub enum Environment {
-    Local,
-    Production,
+// Secrets mounted as files (Docker or Kubernetes secrets) are named by `*_FILE` variables, e.g.
+// `APP_APPLICATION__HMAC_SECRET_FILE=/run/secrets/hmac_secret` stands for
+// `APP_APPLICATION__HMAC_SECRET` set to the content of that file
+fn read_secret_files(
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<config::Map<String, String>, ConfigurationError> {
+    let (files, vars): (Vec<_>, Vec<_>) = vars
+        .filter(|(name, _)| name.to_lowercase().starts_with("app_"))
+        .partition(|(name, _)| name.to_lowercase().ends_with("_file"));
+    let mut vars: config::Map<_, _> = vars.into_iter().collect();
+    for (variable, path) in files {
+        let name = variable[..variable.len() - "_FILE".len()].to_string();
+        // the names are matched case-insensitively, like the `APP_` prefix
+        if vars.keys().any(|var| var.eq_ignore_ascii_case(&name)) {
+            return Err(ConfigurationError::ConflictingSecret(name));
+        }
+        let secret =
+            std::fs::read_to_string(&path).map_err(|source| ConfigurationError::SecretFile {
+                variable,
+                path,
+                source,
+            })?;
+        // files usually end with a newline, which isn't part of the secret
+        vars.insert(name, secret.trim_end_matches(['\n', '\r']).to_string());
+    }
+    Ok(vars)
 }
 
-impl Environment {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Environment::Local => "local",
-            Environment::Production => "production",
+impl Settings {
+    // Checks what deserialization can't, reporting all the problems at once
+    pub fn validate(&self) -> Result<(), ConfigurationError> {
+        let mut problems = Vec::new();
+        self.application.validate(&mut problems);
+        self.database.validate(&mut problems);
+        self.email_client.validate(&mut problems);
+        // only used by the `redis` backend
+        if self.session_store.backend == SessionStoreBackend::Redis
+            && redis::Client::open(self.redis_uri.expose_secret().as_str()).is_err()
+        {
+            // the URI isn't echoed, it may contain a password
+            problems.push("`redis_uri` is not a valid Redis URL".into());
+        }
+        check_positive(
+            &mut problems,
+            "session_store.cleanup_interval_seconds",
+            self.session_store.cleanup_interval_seconds,
+        );
+        check_positive(
+            &mut problems,
+            "idempotency.retention_seconds",
+            self.idempotency.retention_seconds,
+        );
+        check_positive(
+            &mut problems,
+            "idempotency.cleanup_idle_interval_milliseconds",
+            self.idempotency.cleanup_idle_interval_milliseconds,
+        );
+        check_positive(
+            &mut problems,
+            "idempotency.cleanup_error_backoff_milliseconds",
+            self.idempotency.cleanup_error_backoff_milliseconds,
+        );
+        self.delivery_worker.validate(&mut problems);
+        self.login_throttling.validate(&mut problems);
+        self.telemetry.validate(&mut problems);
+        check_positive(
+            &mut problems,
+            "health.timeout_milliseconds",
+            self.health.timeout_milliseconds,
+        );
+        check_positive(
+            &mut problems,
+            "health.heartbeat_max_age_seconds",
+            self.health.heartbeat_max_age_seconds,
+        );
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigurationError::Invalid(problems)),
+        }
+    }
+}
+
+impl ApplicationSettings {
+    fn validate(&self, problems: &mut Vec<String>) {
+        // the cookie signing key is derived from it
+        let secret_length = self.hmac_secret.expose_secret().len();
+        if secret_length < 64 {
+            problems.push(format!(
+                "`application.hmac_secret` must be at least 64 bytes long, it is {secret_length}"
+            ));
+        }
+        // links in emails are built by appending paths to it
+        match reqwest::Url::parse(&self.base_url) {
+            Ok(url)
+                if ["http", "https"].contains(&url.scheme())
+                    && url.host_str().is_some()
+                    && url.path() == "/"
+                    && url.query().is_none()
+                    && url.fragment().is_none()
+                    && !self.base_url.ends_with('/') => {}
+            _ => problems.push(format!(
+                "`application.base_url` must be an http(s) URL without a path or a trailing slash, \
+                such as https://newsletter.example.com, got `{}`",
+                self.base_url
+            )),
+        }
+        check_positive(
+            problems,
+            "application.shutdown_timeout_seconds",
+            self.shutdown_timeout_seconds,
+        );
+        if self
+            .metrics_port
+            .is_some_and(|port| port != 0 && port == self.port)
+        {
+            problems.push("`application.metrics_port` must differ from `application.port`".into());
+        }
+    }
+}
+
+impl DatabaseSettings {
+    fn validate(&self, problems: &mut Vec<String>) {
+        check_not_empty(problems, "database.host", &self.host);
+        check_not_empty(problems, "database.database_name", &self.database_name);
+        check_positive(problems, "database.port", self.port.into());
+    }
+}
+
+impl EmailClientSettings {
+    fn validate(&self, problems: &mut Vec<String>) {
+        if let Err(e) = self.sender() {
+            problems.push(format!("`email_client.sender_email` is invalid: {e}"));
+        }
+        check_http_url(problems, "email_client.base_url", &self.base_url);
+        check_not_empty(
+            problems,
+            "email_client.authorization_token",
+            self.authorization_token.expose_secret(),
+        );
+        check_positive(
+            problems,
+            "email_client.timeout_milliseconds",
+            self.timeout_milliseconds,
+        );
+    }
+}
+
+impl DeliveryWorkerSettings {
+    fn validate(&self, problems: &mut Vec<String>) {
+        check_positive(problems, "delivery_worker.pool_size", self.pool_size as u64);
+        check_positive(
+            problems,
+            "delivery_worker.idle_poll_interval_milliseconds",
+            self.idle_poll_interval_milliseconds,
+        );
+        check_positive(
+            problems,
+            "delivery_worker.error_backoff_milliseconds",
+            self.error_backoff_milliseconds,
+        );
+    }
+}
+
+impl LoginThrottlingSettings {
+    fn validate(&self, problems: &mut Vec<String>) {
+        check_positive(
+            problems,
+            "login_throttling.window_seconds",
+            self.window_seconds,
+        );
+        check_positive(
+            problems,
+            "login_throttling.max_attempts_per_username",
+            self.max_attempts_per_username,
+        );
+        check_positive(
+            problems,
+            "login_throttling.max_attempts_per_ip",
+            self.max_attempts_per_ip,
+        );
+        check_not_empty(problems, "login_throttling.key_prefix", &self.key_prefix);
+        if self.max_delay_milliseconds < self.base_delay_milliseconds {
+            problems.push(
+                "`login_throttling.max_delay_milliseconds` must not be less than \
+                `login_throttling.base_delay_milliseconds`"
+                    .into(),
+            );
+        }
+    }
+}
+
+impl TelemetrySettings {
+    fn validate(&self, problems: &mut Vec<String>) {
+        check_not_empty(problems, "telemetry.service_name", &self.service_name);
+        if let Some(endpoint) = &self.otlp_endpoint {
+            check_http_url(problems, "telemetry.otlp_endpoint", endpoint);
         }
+        if !(0.0..=1.0).contains(&self.sampling_ratio) {
+            problems.push(format!(
+                "`telemetry.sampling_ratio` must be between 0 and 1, got {}",
+                self.sampling_ratio
+            ));
+        }
+    }
+}
+
+fn check_positive(problems: &mut Vec<String>, field: &str, value: u64) {
+    if value == 0 {
+        problems.push(format!("`{field}` must be greater than 0"));
+    }
+}
+
+fn check_not_empty(problems: &mut Vec<String>, field: &str, value: &str) {
+    if value.trim().is_empty() {
+        problems.push(format!("`{field}` must not be empty"));
+    }
+}
+
+fn check_http_url(problems: &mut Vec<String>, field: &str, value: &str) {
+    match reqwest::Url::parse(value) {
+        Ok(url) if ["http", "https"].contains(&url.scheme()) && url.host_str().is_some() => {}
+        _ => problems.push(format!("`{field}` must be an http(s) URL, got `{value}`")),
+    }
+}
+
+// Any environment with its own file in `configuration/`, such as `local`, `staging` or `production`
+pub struct Environment(String);
+
+impl Environment {
+    pub fn as_str(&self) -> &str {
+        &self.0
     }
 }
 
@@ -134,13 +612,231 @@ impl TryFrom<String> for Environment {
     type Error = String;
 
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        match s.to_lowercase().as_str() {
-            "local" => Ok(Self::Local),
-            "production" => Ok(Self::Production),
-            other => Err(format!(
-                "{} is not a supported environment. Use either 'local' or 'production'.",
-                other
-            )),
+        let name = s.to_lowercase();
+        // the name is used as a file name, next to files which aren't environments
+        let is_file_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_file_name || ["base", "override"].contains(&name.as_str()) {
+            return Err(format!(
+                "{} is not a valid environment name. Use letters, digits, '-' and '_', \
+                other than 'base' and 'override'.",
+                s
+            ));
+        }
+        Ok(Self(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use secrecy::Secret;
+    use tempfile::NamedTempFile;
+
+    use super::{
+        ConfigurationError, Environment, Settings, database_settings, get_configuration,
+        read_secret_files,
+    };
+
+    fn problems(settings: &Settings) -> Vec<String> {
+        match settings.validate() {
+            Ok(()) => vec![],
+            Err(ConfigurationError::Invalid(problems)) => problems,
+            Err(e) => panic!("Unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn the_local_configuration_is_valid() {
+        let settings = get_configuration().unwrap();
+        assert_eq!(problems(&settings), Vec::<String>::new());
+    }
+
+    #[test]
+    fn all_the_problems_are_reported_at_once() {
+        let mut settings = get_configuration().unwrap();
+        settings.email_client.sender_email = "not-an-email".into();
+        settings.application.hmac_secret = Secret::new("short".into());
+        settings.email_client.timeout_milliseconds = 0;
+        settings.telemetry.sampling_ratio = 2.0;
+
+        let problems = problems(&settings);
+
+        assert_eq!(problems.len(), 4, "{problems:?}");
+        assert!(problems[0].contains("application.hmac_secret"));
+        assert!(
+            problems
+                .iter()
+                .any(|p| p.contains("email_client.sender_email"))
+        );
+        assert!(
+            problems
+                .iter()
+                .any(|p| p.contains("email_client.timeout_milliseconds"))
+        );
+        assert!(
+            problems
+                .iter()
+                .any(|p| p.contains("telemetry.sampling_ratio"))
+        );
+    }
+
+    #[test]
+    fn only_the_database_settings_are_needed_to_connect_to_the_database() {
+        let configuration = |host: &str| {
+            config::Config::builder()
+                .add_source(config::File::from_str(
+                    &format!(
+                        r#"
+                        database:
+                          host: "{host}"
+                          port: 5432
+                          username: "postgres"
+                          password: "password"
+                          database_name: "newsletter"
+                          require_ssl: false
+                        email_client:
+                          sender_email: "not-an-email"
+                        "#
+                    ),
+                    config::FileFormat::Yaml,
+                ))
+                .build()
+                .unwrap()
+        };
+
+        assert!(database_settings(&configuration("localhost")).is_ok());
+        match database_settings(&configuration("")) {
+            Err(ConfigurationError::Invalid(problems)) => {
+                assert_eq!(problems.len(), 1);
+                assert!(problems[0].contains("database.host"));
+            }
+            _ => panic!("The empty host was accepted"),
+        }
+    }
+
+    #[test]
+    fn an_email_client_is_not_built_from_an_invalid_sender() {
+        let mut settings = get_configuration().unwrap();
+        settings.email_client.sender_email = "not-an-email".into();
+
+        match settings.email_client.client() {
+            Err(ConfigurationError::Invalid(problems)) => {
+                assert!(problems[0].contains("email_client.sender_email"))
+            }
+            _ => panic!("The invalid sender was accepted"),
+        }
+    }
+
+    #[test]
+    fn base_url_must_be_an_origin_links_can_be_appended_to() {
+        let mut settings = get_configuration().unwrap();
+        for base_url in [
+            "127.0.0.1",
+            "ftp://example.com",
+            "https://example.com/",
+            "https://example.com/newsletter",
+        ] {
+            settings.application.base_url = base_url.into();
+            let problems = problems(&settings);
+            assert_eq!(problems.len(), 1, "{base_url}");
+            assert!(problems[0].contains("application.base_url"));
+        }
+        for base_url in ["http://127.0.0.1:8000", "https://example.com"] {
+            settings.application.base_url = base_url.into();
+            assert!(problems(&settings).is_empty(), "{base_url}");
+        }
+    }
+
+    #[test]
+    fn the_secrets_are_not_echoed() {
+        let mut settings = get_configuration().unwrap();
+        settings.application.hmac_secret = Secret::new("my-short-secret".into());
+        settings.redis_uri = Secret::new("not-redis://:my-password@host".into());
+
+        let message = settings.validate().unwrap_err().to_string();
+
+        assert!(message.contains("redis_uri"));
+        assert!(!message.contains("my-short-secret"));
+        assert!(!message.contains("my-password"));
+    }
+
+    // deleted once dropped
+    fn secret_file(content: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn secret_files_stand_for_the_variable_without_the_suffix() {
+        let file = secret_file("my-secret\n");
+
+        let vars = read_secret_files(vars(&[
+            (
+                "APP_APPLICATION__HMAC_SECRET_FILE",
+                file.path().to_str().unwrap(),
+            ),
+            ("APP_APPLICATION__PORT", "8000"),
+            ("HOME", "/root"),
+        ]))
+        .unwrap();
+
+        assert_eq!(vars.len(), 2);
+        assert_eq!(vars["APP_APPLICATION__HMAC_SECRET"], "my-secret");
+        assert_eq!(vars["APP_APPLICATION__PORT"], "8000");
+    }
+
+    #[test]
+    fn a_secret_cannot_be_set_both_directly_and_from_a_file() {
+        let file = secret_file("my-secret");
+        let path = file.path().to_str().unwrap();
+
+        for (file_variable, variable) in [
+            ("APP_DATABASE__PASSWORD_FILE", "APP_DATABASE__PASSWORD"),
+            ("app_database__password_file", "APP_DATABASE__PASSWORD"),
+            ("APP_DATABASE__PASSWORD_FILE", "app_database__password"),
+        ] {
+            let error = read_secret_files(vars(&[(file_variable, path), (variable, "password")]))
+                .unwrap_err();
+
+            assert!(
+                matches!(error, ConfigurationError::ConflictingSecret(_)),
+                "{file_variable} and {variable}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_missing_secret_file_is_an_error() {
+        let error = read_secret_files(vars(&[(
+            "APP_EMAIL_CLIENT__AUTHORIZATION_TOKEN_FILE",
+            "/run/secrets/does-not-exist",
+        )]))
+        .unwrap_err();
+
+        assert!(matches!(error, ConfigurationError::SecretFile { .. }));
+        assert!(error.to_string().contains("/run/secrets/does-not-exist"));
+    }
+
+    #[test]
+    fn environments_are_named_after_their_file() {
+        for name in ["local", "Staging", "test", "eu-west_2"] {
+            let environment = Environment::try_from(name.to_string()).unwrap();
+            assert_eq!(environment.as_str(), name.to_lowercase());
+        }
+        for name in ["", "../secrets", "base", "override", "prod.old"] {
+            assert!(Environment::try_from(name.to_string()).is_err(), "{name}");
         }
     }
 }
@@ -32,6 +32,41 @@ impl EmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
+    ) -> Result<(), reqwest::Error> {
+        let outcome = self
+            .post_email(recipient, subject, html_content, text_content)
+            .await;
+        // providers are told apart by their host
+        let provider = reqwest::Url::parse(&self.base_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| self.base_url.clone());
+        match outcome {
+            Ok(()) => metrics::counter!("emails_sent_total", "provider" => provider).increment(1),
+            Err(_) => metrics::counter!("emails_failed_total", "provider" => provider).increment(1),
+        }
+        outcome
+    }
+
+    // Any answer will do, the provider is reachable
+    pub async fn check_reachable(
+        &self,
+        timeout: std::time::Duration,
+    ) -> Result<(), reqwest::Error> {
+        self.http_client
+            .get(&self.base_url)
+            .timeout(timeout)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn post_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
     ) -> Result<(), reqwest::Error> {
         let url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
@@ -0,0 +1,154 @@
+use anyhow::Context;
+use askama::Template;
+use sqlx::{PgExecutor, PgPool};
+
+// Layout wrapping every email sent to subscribers and users, managed by owners
+#[derive(Clone, Debug)]
+pub struct EmailLayout {
+    // HTML, trusted like the content of the issues
+    pub header: String,
+    pub footer: String,
+    pub styles: String,
+    pub postal_address: String,
+}
+
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(Template)]
+#[template(path = "emails/layout.html")]
+struct HtmlEmail<'a> {
+    layout: &'a EmailLayout,
+    content: &'a str,
+    unsubscribe_link: Option<&'a str>,
+}
+
+#[derive(Template)]
+#[template(path = "emails/layout.txt")]
+struct TextEmail<'a> {
+    layout: &'a EmailLayout,
+    content: &'a str,
+    unsubscribe_link: Option<&'a str>,
+}
+
+impl EmailLayout {
+    // Only issues have an unsubscribe link: transactional emails are sent whatever the subscription
+    pub fn render(
+        &self,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_link: Option<&str>,
+    ) -> Result<RenderedEmail, anyhow::Error> {
+        let html = HtmlEmail {
+            layout: self,
+            content: html_content,
+            unsubscribe_link,
+        }
+        .render()
+        .context("Failed to render the HTML body of an email.")?;
+        // Gmail and Outlook ignore most of the `<style>` elements: rules are copied into the `style`
+        // attribute of every element they match
+        let html = css_inline::inline(&html).context("Failed to inline the styles of an email.")?;
+        let text = TextEmail {
+            layout: self,
+            content: text_content,
+            unsubscribe_link,
+        }
+        .render()
+        .context("Failed to render the plain text body of an email.")?;
+        Ok(RenderedEmail { html, text })
+    }
+}
+
+#[tracing::instrument(name = "Get email layout", skip(pool))]
+pub async fn get_email_layout(pool: &PgPool) -> Result<EmailLayout, anyhow::Error> {
+    let layout = sqlx::query_as!(
+        EmailLayout,
+        "SELECT header, footer, styles, postal_address FROM email_layout"
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve the email layout.")?;
+    Ok(layout)
+}
+
+#[tracing::instrument(name = "Update email layout", skip(layout, executor))]
+pub async fn update_email_layout(
+    layout: &EmailLayout,
+    executor: impl PgExecutor<'_>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE email_layout
+        SET header = $1, footer = $2, styles = $3, postal_address = $4
+        "#,
+        layout.header,
+        layout.footer,
+        layout.styles,
+        layout.postal_address,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to update the email layout.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmailLayout;
+
+    fn layout() -> EmailLayout {
+        EmailLayout {
+            header: "<h1>Header</h1>".into(),
+            footer: "<p>Footer</p>".into(),
+            styles: "h1 { color: red; }".into(),
+            postal_address: "1 Main Street, Springfield".into(),
+        }
+    }
+
+    #[test]
+    fn styles_are_inlined() {
+        let email = layout().render("<p>Hello</p>", "Hello", None).unwrap();
+        assert!(
+            email
+                .html
+                .contains(r#"<h1 style="color: red;">Header</h1>"#)
+        );
+        assert!(!email.html.contains("<style>"));
+    }
+
+    #[test]
+    fn the_content_is_wrapped_in_the_layout() {
+        let email = layout()
+            .render(
+                "<p>Hello</p>",
+                "Hello",
+                Some("https://example.com/unsubscribe"),
+            )
+            .unwrap();
+        for part in [
+            "<p>Hello</p>",
+            "<p>Footer</p>",
+            "1 Main Street, Springfield",
+        ] {
+            assert!(email.html.contains(part));
+        }
+        assert!(
+            email
+                .html
+                .contains(r#"href="https://example.com/unsubscribe""#)
+        );
+        assert!(email.text.starts_with("Hello"));
+        assert!(email.text.contains("1 Main Street, Springfield"));
+        assert!(email.text.contains("https://example.com/unsubscribe"));
+    }
+
+    #[test]
+    fn transactional_emails_have_no_unsubscribe_link() {
+        let email = layout().render("<p>Hello</p>", "Hello", None).unwrap();
+        assert!(!email.html.contains("Unsubscribe"));
+        assert!(!email.text.contains("Unsubscribe"));
+    }
+}
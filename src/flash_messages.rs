@@ -0,0 +1,56 @@
+use actix_web::{
+    HttpRequest,
+    cookie::{Cookie, SameSite},
+    dev::ResponseHead,
+    http::header::{HeaderValue, SET_COOKIE},
+};
+use actix_web_flash_messages::{
+    FlashMessage,
+    storage::{CookieMessageStore, FlashMessageStore, LoadError, StoreError},
+};
+
+const FLASH_COOKIE_NAME: &str = "_flash";
+
+// CookieMessageStore always sets `SameSite=Lax` on its cookie and can't be configured otherwise:
+// this store rewrites the attribute, so that flash messages follow the same policy as the session
+pub struct SameSiteCookieMessageStore {
+    store: CookieMessageStore,
+    same_site: SameSite,
+}
+
+impl SameSiteCookieMessageStore {
+    pub fn new(store: CookieMessageStore, same_site: SameSite) -> Self {
+        Self { store, same_site }
+    }
+}
+
+impl FlashMessageStore for SameSiteCookieMessageStore {
+    fn load(&self, request: &HttpRequest) -> Result<Vec<FlashMessage>, LoadError> {
+        self.store.load(request)
+    }
+
+    fn store(
+        &self,
+        messages: &[FlashMessage],
+        request: HttpRequest,
+        response: &mut ResponseHead,
+    ) -> Result<(), StoreError> {
+        self.store.store(messages, request, response)?;
+
+        let set_cookies: Vec<HeaderValue> =
+            response.headers().get_all(SET_COOKIE).cloned().collect();
+        response.headers_mut().remove(SET_COOKIE);
+        for value in set_cookies {
+            let value = match value.to_str().map(Cookie::parse) {
+                Ok(Ok(mut cookie)) if cookie.name() == FLASH_COOKIE_NAME => {
+                    cookie.set_same_site(self.same_site);
+                    HeaderValue::from_str(&cookie.to_string())
+                        .map_err(|e| StoreError::GenericError(e.into()))?
+                }
+                _ => value,
+            };
+            response.headers_mut().append(SET_COOKIE, value);
+        }
+        Ok(())
+    }
+}
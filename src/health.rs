@@ -0,0 +1,116 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::configuration::HealthSettings;
+
+// How often the background loops report that they are still running
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug)]
+pub enum WorkerKind {
+    DeliveryWorker,
+    IdempotencyCleaner,
+    SessionCleaner,
+}
+
+impl WorkerKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkerKind::DeliveryWorker => "delivery_worker",
+            WorkerKind::IdempotencyCleaner => "idempotency_cleaner",
+            WorkerKind::SessionCleaner => "session_cleaner",
+        }
+    }
+}
+
+// Called on every iteration of a worker loop, only recorded once per interval
+pub struct Heartbeat {
+    worker: WorkerKind,
+    last: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new(worker: WorkerKind) -> Self {
+        Self { worker, last: None }
+    }
+
+    // A failure doesn't stop the worker, which is then reported as stale
+    pub async fn beat(&mut self, pool: &PgPool) {
+        if self
+            .last
+            .is_some_and(|last| last.elapsed() < HEARTBEAT_INTERVAL)
+        {
+            return;
+        }
+        self.last = Some(Instant::now());
+        if let Err(e) = record_heartbeat(pool, self.worker).await {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                worker = self.worker.as_str(),
+                "Failed to record a heartbeat"
+            );
+        }
+    }
+}
+
+#[tracing::instrument(skip(pool), level = "debug")]
+pub async fn record_heartbeat(pool: &PgPool, worker: WorkerKind) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO worker_heartbeats (worker, last_seen_at)
+        VALUES ($1, now())
+        ON CONFLICT (worker) DO UPDATE SET last_seen_at = EXCLUDED.last_seen_at
+        "#,
+        worker.as_str()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Latest heartbeat of each kind of worker, across all the instances running it
+pub async fn last_heartbeats(pool: &PgPool) -> Result<HashMap<String, DateTime<Utc>>, sqlx::Error> {
+    let heartbeats = sqlx::query!("SELECT worker, last_seen_at FROM worker_heartbeats")
+        .fetch_all(pool)
+        .await?;
+    Ok(heartbeats
+        .into_iter()
+        .map(|r| (r.worker, r.last_seen_at))
+        .collect())
+}
+
+// Dependencies of the API which are not already shared with the handlers
+pub struct ReadinessProbe {
+    // only when the sessions are stored in Redis
+    pub redis: Option<redis::Client>,
+    pub settings: HealthSettings,
+}
+
+impl ReadinessProbe {
+    // Doesn't connect: Redis may well be down when the API starts
+    pub fn new(
+        redis_uri: Option<&Secret<String>>,
+        settings: HealthSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let redis = redis_uri
+            .map(|uri| redis::Client::open(uri.expose_secret().as_str()))
+            .transpose()?;
+        Ok(Self { redis, settings })
+    }
+
+    // A new connection each time, so that a broken one isn't mistaken for a healthy Redis
+    pub async fn ping_redis(redis: &redis::Client) -> Result<(), redis::RedisError> {
+        let mut connection = redis.get_multiplexed_async_connection().await?;
+        redis::cmd("PING")
+            .query_async::<String>(&mut connection)
+            .await?;
+        Ok(())
+    }
+}
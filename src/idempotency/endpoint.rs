@@ -0,0 +1,31 @@
+// Endpoints processing requests once per idempotency key: a key is only reused within an endpoint,
+// which also has its own retention
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdempotentEndpoint {
+    PublishNewsletter,
+    CreateIssue,
+    PublishIssue,
+    CreateSubscriber,
+    Subscribe,
+}
+
+impl IdempotentEndpoint {
+    pub const ALL: [IdempotentEndpoint; 5] = [
+        IdempotentEndpoint::PublishNewsletter,
+        IdempotentEndpoint::CreateIssue,
+        IdempotentEndpoint::PublishIssue,
+        IdempotentEndpoint::CreateSubscriber,
+        IdempotentEndpoint::Subscribe,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdempotentEndpoint::PublishNewsletter => "publish_newsletter",
+            IdempotentEndpoint::CreateIssue => "create_issue",
+            IdempotentEndpoint::PublishIssue => "publish_issue",
+            IdempotentEndpoint::CreateSubscriber => "create_subscriber",
+            IdempotentEndpoint::Subscribe => "subscribe",
+        }
+    }
+}
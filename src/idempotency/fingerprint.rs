@@ -0,0 +1,121 @@
+use actix_web::HttpRequest;
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+use crate::utils::{client_ip, user_agent};
+
+// Hash of the method, path and body of a request, stored along with its idempotency key
+#[derive(Debug, PartialEq)]
+pub struct RequestFingerprint(String);
+
+impl RequestFingerprint {
+    // The body is the payload as the handler deserialized it: retries that only differ in formatting,
+    // in the order of the fields or in fields the handler ignores, like the CSRF token, are the same request
+    pub fn new(
+        request: &HttpRequest,
+        payload: &impl serde::Serialize,
+    ) -> Result<Self, anyhow::Error> {
+        let body = serde_json::to_vec(payload).context("Failed to serialize the payload")?;
+        Ok(Self(hash(&[
+            request.method().as_str().as_bytes(),
+            request.path().as_bytes(),
+            &body,
+        ])))
+    }
+}
+
+impl AsRef<str> for RequestFingerprint {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// Hash of the IP address and user agent of an anonymous client, which scopes its idempotency keys:
+// a key guessed by someone else doesn't replay the response saved for another client
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientFingerprint(String);
+
+impl ClientFingerprint {
+    pub fn new(request: &HttpRequest) -> Self {
+        Self(hash(&[
+            client_ip(request).as_bytes(),
+            user_agent(request).as_bytes(),
+        ]))
+    }
+}
+
+impl AsRef<str> for ClientFingerprint {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn hash(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    // each part is prefixed by its length, so that parts can't spill into one another
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::Method, test::TestRequest};
+
+    use super::{ClientFingerprint, RequestFingerprint, hash};
+
+    fn fingerprint(method: Method, uri: &str, payload: serde_json::Value) -> RequestFingerprint {
+        let request = TestRequest::default()
+            .method(method)
+            .uri(uri)
+            .to_http_request();
+        RequestFingerprint::new(&request, &payload).unwrap()
+    }
+
+    #[test]
+    fn the_same_request_has_the_same_fingerprint() {
+        let payload = serde_json::json!({"title": "Issue"});
+        assert_eq!(
+            fingerprint(Method::POST, "/api/v1/issues", payload.clone()),
+            fingerprint(Method::POST, "/api/v1/issues", payload)
+        );
+    }
+
+    #[test]
+    fn the_method_path_and_body_are_fingerprinted() {
+        let reference = fingerprint(Method::POST, "/api/v1/issues", serde_json::json!({"a": 1}));
+        for other in [
+            fingerprint(Method::PATCH, "/api/v1/issues", serde_json::json!({"a": 1})),
+            fingerprint(
+                Method::POST,
+                "/api/v1/subscribers",
+                serde_json::json!({"a": 1}),
+            ),
+            fingerprint(Method::POST, "/api/v1/issues", serde_json::json!({"a": 2})),
+        ] {
+            assert_ne!(reference, other);
+        }
+    }
+
+    #[test]
+    fn parts_cannot_be_shifted_into_one_another() {
+        assert_ne!(hash(&[b"POST", b"/a", b"b"]), hash(&[b"POST", b"/ab", b""]));
+    }
+
+    #[test]
+    fn clients_are_told_apart_by_ip_address_and_user_agent() {
+        let client = |ip: &str, user_agent: &str| {
+            ClientFingerprint::new(
+                &TestRequest::default()
+                    .peer_addr(format!("{ip}:4242").parse().unwrap())
+                    .insert_header(("User-Agent", user_agent))
+                    .to_http_request(),
+            )
+        };
+        assert_eq!(client("10.0.0.1", "curl"), client("10.0.0.1", "curl"));
+        assert_ne!(client("10.0.0.1", "curl"), client("10.0.0.2", "curl"));
+        assert_ne!(client("10.0.0.1", "curl"), client("10.0.0.1", "firefox"));
+    }
+}
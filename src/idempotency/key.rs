@@ -1,6 +1,25 @@
+use actix_web::HttpRequest;
+
 #[derive(Debug)]
 pub struct IdempotencyKey(String);
 
+impl IdempotencyKey {
+    // The IETF idempotency-key draft sends the key as a quoted string, bare keys are accepted too
+    pub fn from_header(request: &HttpRequest) -> Result<Option<Self>, anyhow::Error> {
+        let Some(value) = request.headers().get("Idempotency-Key") else {
+            return Ok(None);
+        };
+        let value = value
+            .to_str()
+            .map_err(|_| anyhow::anyhow!("The idempotency key must be ASCII"))?;
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        Self::try_from(value.to_string()).map(Some)
+    }
+}
+
 impl TryFrom<String> for IdempotencyKey {
     type Error = anyhow::Error;
 
@@ -1,6 +1,12 @@
+mod endpoint;
+mod fingerprint;
 mod key;
 mod persistence;
+mod scope;
+pub use endpoint::IdempotentEndpoint;
+pub use fingerprint::{ClientFingerprint, RequestFingerprint};
 pub use key::IdempotencyKey;
 pub use persistence::get_saved_response;
 pub use persistence::save_response;
-pub use persistence::{NextAction, try_processing};
+pub use persistence::{NextAction, try_processing, try_processing_within_quota};
+pub use scope::IdempotencyScope;
@@ -1,8 +1,13 @@
 use actix_web::{HttpResponse, body::to_bytes, http::StatusCode};
+use anyhow::Context;
 use sqlx::{PgPool, Postgres, Transaction};
-use uuid::Uuid;
 
-use crate::idempotency::IdempotencyKey;
+use crate::{
+    configuration::AnonymousQuotaSettings,
+    idempotency::{
+        ClientFingerprint, IdempotencyKey, IdempotencyScope, IdempotentEndpoint, RequestFingerprint,
+    },
+};
 
 #[derive(Debug, sqlx::Type)]
 #[sqlx(type_name = "header_pair")]
@@ -14,12 +19,16 @@ struct HeaderPairRecord {
 pub enum NextAction {
     StartProcessing(Transaction<'static, Postgres>),
     ReturnSavedResponse(HttpResponse),
+    // the key has already been used for a request with another method, path or body
+    RejectReusedKey,
 }
 
 pub async fn try_processing(
     pool: &PgPool,
+    endpoint: IdempotentEndpoint,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    fingerprint: &RequestFingerprint,
+    scope: &IdempotencyScope,
 ) -> Result<NextAction, anyhow::Error> {
     let mut transaction = pool.begin().await?;
 
@@ -28,14 +37,20 @@ pub async fn try_processing(
         r#"
         INSERT INTO idempotency (
             user_id,
+            client_fingerprint,
+            endpoint,
             idempotency_key,
+            request_fingerprint,
             created_at
         )
-        VALUES ($1, $2, now())
+        VALUES ($1, $2, $3, $4, $5, now())
         ON CONFLICT DO NOTHING
         "#,
-        user_id,
-        idempotency_key.as_ref()
+        scope.user_id(),
+        scope.client_fingerprint(),
+        endpoint.as_str(),
+        idempotency_key.as_ref(),
+        fingerprint.as_ref()
     )
     .execute(&mut *transaction)
     .await?
@@ -44,19 +59,153 @@ pub async fn try_processing(
     if n_inserted_rows > 0 {
         Ok(NextAction::StartProcessing(transaction))
     } else {
-        let saved_response = get_saved_response(pool, idempotency_key, user_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
-        Ok(NextAction::ReturnSavedResponse(saved_response))
+        let saved_fingerprint = sqlx::query_scalar!(
+            r#"
+            SELECT request_fingerprint
+            FROM idempotency
+            WHERE
+                (user_id = $1 OR client_fingerprint = $2) AND
+                endpoint = $3 AND
+                idempotency_key = $4
+            "#,
+            scope.user_id(),
+            scope.client_fingerprint(),
+            endpoint.as_str(),
+            idempotency_key.as_ref()
+        )
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+        replay_or_reject(
+            pool,
+            endpoint,
+            idempotency_key,
+            fingerprint,
+            scope,
+            saved_fingerprint,
+        )
+        .await
     }
 
     // Transaction is auto rolled back if not commit, its a RAII style object
 }
 
+// Anyone can store keys without logging in: past their quota, clients are processed as if they
+// had not sent a key (`None` is returned), which bounds the storage used by a client and by all
+// of them together.
+// The keys are committed one at a time before the requests are processed, so that concurrent
+// requests can't get past the quotas: the key of a request that failed has no response, and is
+// processed again when retried
+pub async fn try_processing_within_quota(
+    pool: &PgPool,
+    endpoint: IdempotentEndpoint,
+    idempotency_key: &IdempotencyKey,
+    fingerprint: &RequestFingerprint,
+    client: &ClientFingerprint,
+    quota: &AnonymousQuotaSettings,
+) -> Result<Option<NextAction>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    // only held while the key is inserted
+    sqlx::query!("SELECT pg_advisory_xact_lock(hashtext('anonymous_idempotency_keys'))")
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to lock the idempotency keys of anonymous clients")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency (
+            client_fingerprint,
+            endpoint,
+            idempotency_key,
+            request_fingerprint,
+            created_at
+        )
+        SELECT $1, $2, $3, $4, now()
+        WHERE
+            (SELECT COUNT(*) FROM idempotency WHERE client_fingerprint = $1) < $5 AND
+            (SELECT COUNT(*) FROM idempotency WHERE client_fingerprint IS NOT NULL) < $6
+        ON CONFLICT DO NOTHING
+        "#,
+        client.as_ref(),
+        endpoint.as_str(),
+        idempotency_key.as_ref(),
+        fingerprint.as_ref(),
+        quota.per_client as i64,
+        quota.total as i64
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    // waits for a request with the same key to be processed
+    let mut transaction = pool.begin().await?;
+    let saved = sqlx::query!(
+        r#"
+        SELECT request_fingerprint, response_status_code
+        FROM idempotency
+        WHERE
+            client_fingerprint = $1 AND
+            endpoint = $2 AND
+            idempotency_key = $3
+        FOR UPDATE
+        "#,
+        client.as_ref(),
+        endpoint.as_str(),
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let Some(saved) = saved else {
+        // not stored for lack of room
+        metrics::counter!("idempotency_anonymous_quota_exceeded_total").increment(1);
+        return Ok(None);
+    };
+    let reused = saved
+        .request_fingerprint
+        .as_ref()
+        .is_some_and(|f| f != fingerprint.as_ref());
+    match saved.response_status_code {
+        None if !reused => Ok(Some(NextAction::StartProcessing(transaction))),
+        _ => {
+            let scope = IdempotencyScope::Anonymous(client.clone());
+            replay_or_reject(
+                pool,
+                endpoint,
+                idempotency_key,
+                fingerprint,
+                &scope,
+                saved.request_fingerprint,
+            )
+            .await
+            .map(Some)
+        }
+    }
+}
+
+async fn replay_or_reject(
+    pool: &PgPool,
+    endpoint: IdempotentEndpoint,
+    idempotency_key: &IdempotencyKey,
+    fingerprint: &RequestFingerprint,
+    scope: &IdempotencyScope,
+    saved_fingerprint: Option<String>,
+) -> Result<NextAction, anyhow::Error> {
+    if saved_fingerprint.is_some_and(|f| f != fingerprint.as_ref()) {
+        metrics::counter!("idempotency_rejected_reuses_total", "endpoint" => endpoint.as_str())
+            .increment(1);
+        return Ok(NextAction::RejectReusedKey);
+    }
+    let saved_response = get_saved_response(pool, endpoint, idempotency_key, scope)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+    metrics::counter!("idempotency_replays_total", "endpoint" => endpoint.as_str()).increment(1);
+    Ok(NextAction::ReturnSavedResponse(saved_response))
+}
+
 pub async fn get_saved_response(
     pool: &PgPool,
+    endpoint: IdempotentEndpoint,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
 ) -> Result<Option<HttpResponse>, anyhow::Error> {
     let saved_response = sqlx::query!(
         r#"
@@ -66,10 +215,13 @@ pub async fn get_saved_response(
             response_body as "response_body!"
         FROM idempotency
         WHERE
-            user_id = $1 AND
-            idempotency_key = $2
+            (user_id = $1 OR client_fingerprint = $2) AND
+            endpoint = $3 AND
+            idempotency_key = $4
         "#,
-        user_id,
+        scope.user_id(),
+        scope.client_fingerprint(),
+        endpoint.as_str(),
         idempotency_key.as_ref()
     )
     .fetch_optional(pool)
@@ -90,8 +242,9 @@ pub async fn get_saved_response(
 
 pub async fn save_response(
     mut transaction: Transaction<'static, Postgres>,
+    endpoint: IdempotentEndpoint,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
     http_response: HttpResponse,
 ) -> Result<HttpResponse, anyhow::Error> {
     let (response_head, body) = http_response.into_parts();
@@ -111,14 +264,17 @@ pub async fn save_response(
         r#"
         UPDATE idempotency
         SET
-            response_status_code = $3,
-            response_headers = $4,
-            response_body = $5
+            response_status_code = $5,
+            response_headers = $6,
+            response_body = $7
         WHERE
-            user_id = $1 AND
-            idempotency_key = $2
+            (user_id = $1 OR client_fingerprint = $2) AND
+            endpoint = $3 AND
+            idempotency_key = $4
         "#,
-        user_id,
+        scope.user_id(),
+        scope.client_fingerprint(),
+        endpoint.as_str(),
         idempotency_key.as_ref(),
         status_code,
         headers,
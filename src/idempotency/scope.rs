@@ -0,0 +1,28 @@
+use uuid::Uuid;
+
+use crate::idempotency::ClientFingerprint;
+
+// Whose key it is: the keys of different users or clients never collide
+#[derive(Clone, Debug)]
+pub enum IdempotencyScope {
+    User(Uuid),
+    // requests without a session, like subscriptions
+    Anonymous(ClientFingerprint),
+}
+
+impl IdempotencyScope {
+    // Exactly one of them is set, the other one is NULL in the `idempotency` table
+    pub(super) fn user_id(&self) -> Option<Uuid> {
+        match self {
+            IdempotencyScope::User(user_id) => Some(*user_id),
+            IdempotencyScope::Anonymous(_) => None,
+        }
+    }
+
+    pub(super) fn client_fingerprint(&self) -> Option<&str> {
+        match self {
+            IdempotencyScope::User(_) => None,
+            IdempotencyScope::Anonymous(client) => Some(client.as_ref()),
+        }
+    }
+}
@@ -1,16 +1,24 @@
-use std::time::Duration;
-
-use chrono::{DateTime, Utc};
 use sqlx::{PgPool, Postgres, Transaction};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
-use crate::{configuration::Settings, startup::get_connection_pool};
+use crate::{
+    configuration::{IdempotencySettings, Settings},
+    health::{Heartbeat, WorkerKind},
+    idempotency::IdempotentEndpoint,
+    prometheus::record_pool_stats,
+    startup::get_connection_pool,
+};
 
 type PgTransaction = Transaction<'static, Postgres>;
 
-#[allow(dead_code)]
+// Keys are scoped by user, or anonymous client, and endpoint: the same key may be used by someone
+// else, or elsewhere
 struct IdempotentEntry {
+    user_id: Option<Uuid>,
+    client_fingerprint: Option<String>,
+    endpoint: String,
     idempotency_key: String,
-    created_at: DateTime<Utc>,
 }
 
 pub enum IdempotentExecutionOutcome {
@@ -20,71 +28,121 @@ pub enum IdempotentExecutionOutcome {
 
 pub async fn run_idempotency_worker_until_stopped(
     configuration: Settings,
+    shutdown: CancellationToken,
 ) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
 
-    worker_loop(connection_pool, configuration.idempotent_time_interval).await
+    worker_loop(connection_pool, configuration.idempotency, shutdown).await
 }
 
-async fn worker_loop(pool: PgPool, time_interval: f64) -> Result<(), anyhow::Error> {
-    loop {
-        match delete_expired_idempotent_entries(&pool, time_interval).await {
-            Ok(IdempotentExecutionOutcome::EmptyTable) => {
-                // Exponential backoff with jitter would be better
-                tokio::time::sleep(Duration::from_secs(10)).await;
-            }
-            Err(_) => {
-                // Exponential backoff with jitter would be better
-                tokio::time::sleep(Duration::from_secs(1)).await;
-            }
-            Ok(IdempotentExecutionOutcome::TaskCompleted) => {}
+async fn worker_loop(
+    pool: PgPool,
+    settings: IdempotencySettings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let mut heartbeat = Heartbeat::new(WorkerKind::IdempotencyCleaner);
+    while !shutdown.is_cancelled() {
+        heartbeat.beat(&pool).await;
+        let outcome = delete_expired_idempotent_entries(&pool, &settings).await;
+        record_pool_stats("idempotency_worker", &pool);
+        let wait = match outcome {
+            // Exponential backoff with jitter would be better
+            Ok(IdempotentExecutionOutcome::EmptyTable) => settings.cleanup_idle_interval(),
+            // Exponential backoff with jitter would be better
+            Err(_) => settings.cleanup_error_backoff(),
+            Ok(IdempotentExecutionOutcome::TaskCompleted) => continue,
+        };
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(wait) => {}
         }
     }
+    tracing::info!("Idempotency cleaner stopped");
+    Ok(())
 }
 
 #[tracing::instrument(skip_all)]
 pub async fn delete_expired_idempotent_entries(
     pool: &PgPool,
-    time_interval: f64,
+    settings: &IdempotencySettings,
 ) -> Result<IdempotentExecutionOutcome, anyhow::Error> {
-    let (mut transaction, records) = get_idempotent_entries(pool, time_interval).await?;
+    let (mut transaction, records) = get_idempotent_entries(pool, settings).await?;
     if records.is_empty() {
         return Ok(IdempotentExecutionOutcome::EmptyTable);
     }
 
-    let idempotency_keys: Vec<String> = records.iter().map(|r| r.idempotency_key.clone()).collect();
-
-    sqlx::query!(
+    let mut user_ids = Vec::with_capacity(records.len());
+    let mut client_fingerprints = Vec::with_capacity(records.len());
+    let mut endpoints = Vec::with_capacity(records.len());
+    let mut idempotency_keys = Vec::with_capacity(records.len());
+    for r in records {
+        user_ids.push(r.user_id);
+        client_fingerprints.push(r.client_fingerprint);
+        endpoints.push(r.endpoint);
+        idempotency_keys.push(r.idempotency_key);
+    }
+    let purged_endpoints = sqlx::query_scalar!(
         r#"
-        DELETE FROM idempotency
-        WHERE idempotency_key = ANY($1)
+        DELETE FROM idempotency i
+        USING unnest($1::uuid[], $2::text[], $3::text[], $4::text[])
+            AS e(user_id, client_fingerprint, endpoint, idempotency_key)
+        WHERE
+            i.user_id IS NOT DISTINCT FROM e.user_id AND
+            i.client_fingerprint IS NOT DISTINCT FROM e.client_fingerprint AND
+            i.endpoint = e.endpoint AND
+            i.idempotency_key = e.idempotency_key
+        RETURNING i.endpoint
         "#,
+        &user_ids as &[Option<Uuid>],
+        &client_fingerprints as &[Option<String>],
+        &endpoints,
         &idempotency_keys
     )
-    .execute(&mut *transaction)
+    .fetch_all(&mut *transaction)
     .await?;
 
     transaction.commit().await?;
 
+    tracing::info!(
+        count = purged_endpoints.len(),
+        "Purged expired idempotency keys"
+    );
+    for endpoint in purged_endpoints {
+        metrics::counter!("idempotency_entries_purged_total", "endpoint" => endpoint).increment(1);
+    }
     Ok(IdempotentExecutionOutcome::TaskCompleted)
 }
 
 #[tracing::instrument(skip_all)]
 async fn get_idempotent_entries(
     pool: &PgPool,
-    time_interval: f64,
+    settings: &IdempotencySettings,
 ) -> Result<(PgTransaction, Vec<IdempotentEntry>), anyhow::Error> {
+    let (endpoints, retentions): (Vec<&str>, Vec<f64>) = IdempotentEndpoint::ALL
+        .iter()
+        .map(|endpoint| {
+            (
+                endpoint.as_str(),
+                settings.retention(*endpoint).as_secs_f64(),
+            )
+        })
+        .unzip();
     let mut transaction = pool.begin().await?;
+    // keys of unknown endpoints, saved before keys were scoped, expire with the default retention
     let records = sqlx::query_as!(
         IdempotentEntry,
         r#"
-        SELECT idempotency_key, created_at
-        FROM idempotency
-        WHERE created_at + ($1 * INTERVAL '1 second') < now()
-        FOR UPDATE SKIP LOCKED
+        SELECT i.user_id, i.client_fingerprint, i.endpoint, i.idempotency_key
+        FROM idempotency i
+        LEFT JOIN unnest($1::text[], $2::float8[]) AS r(endpoint, retention_seconds)
+            ON r.endpoint = i.endpoint
+        WHERE i.created_at + (COALESCE(r.retention_seconds, $3) * INTERVAL '1 second') < now()
+        FOR UPDATE OF i SKIP LOCKED
         LIMIT 100
         "#,
-        time_interval
+        &endpoints as &[&str],
+        &retentions,
+        settings.retention_seconds as f64
     )
     .fetch_all(&mut *transaction)
     .await?;
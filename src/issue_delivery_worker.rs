@@ -1,12 +1,21 @@
-use std::time::Duration;
+use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use sqlx::{PgPool, Postgres, Transaction};
-use tracing::{Span, field::display};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, Span, field::display};
 use uuid::Uuid;
 
 use crate::{
-    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
+    configuration::{DeliveryWorkerSettings, Settings},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    email_layout::get_email_layout,
+    health::{Heartbeat, WorkerKind},
+    prometheus::record_pool_stats,
     startup::get_connection_pool,
+    telemetry::continue_trace,
 };
 
 type PgTransaction = Transaction<'static, Postgres>;
@@ -17,34 +26,85 @@ struct NewsletterIssue {
     html_content: String,
 }
 
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    enqueued_at: DateTime<Utc>,
+    traceparent: Option<String>,
+}
+
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
 }
 
-// Multiple instances of this app is deployed. Each instance will have this 1 worker loop
-// All the workers are pulling from 1 single database
-pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+// Multiple instances of this app is deployed. Each instance runs a pool of `pool_size` worker loops
+// All the workers (across and within instances) are pulling from 1 single database,
+// `SKIP LOCKED` makes sure two workers never pick up the same task
+// Once `shutdown` is cancelled, workers finish the task they are executing and exit
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
-    let email_client = configuration.email_client.client();
+    let email_client = Arc::new(configuration.email_client.client()?);
+    let base_url = configuration.application.base_url;
+    let settings = configuration.delivery_worker;
+    anyhow::ensure!(
+        settings.pool_size > 0,
+        "The delivery worker pool size must be greater than 0"
+    );
 
-    worker_loop(connection_pool, email_client).await
+    // workers share the pg pool and the email client (both are cheap to clone handles)
+    let mut workers = JoinSet::new();
+    for worker_id in 0..settings.pool_size {
+        workers.spawn(
+            worker_loop(
+                connection_pool.clone(),
+                email_client.clone(),
+                base_url.clone(),
+                settings.clone(),
+                shutdown.clone(),
+            )
+            .instrument(tracing::info_span!("Delivery worker", worker_id)),
+        );
+    }
+
+    // worker loops only exit on shutdown, bubble up the first one that fails
+    while let Some(outcome) = workers.join_next().await {
+        outcome??;
+    }
+
+    Ok(())
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
-    loop {
-        match try_execute_task(&pool, &email_client).await {
-            Ok(ExecutionOutcome::EmptyQueue) => {
-                // Exponential backoff with jitter would be better
-                tokio::time::sleep(Duration::from_secs(10)).await;
-            }
-            Err(_) => {
-                // Exponential backoff with jitter would be better
-                tokio::time::sleep(Duration::from_secs(1)).await;
-            }
-            Ok(ExecutionOutcome::TaskCompleted) => {}
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    base_url: String,
+    settings: DeliveryWorkerSettings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    // a task is never interrupted half-way: the email could be sent but never removed from the queue
+    let mut heartbeat = Heartbeat::new(WorkerKind::DeliveryWorker);
+    while !shutdown.is_cancelled() {
+        heartbeat.beat(&pool).await;
+        let outcome = try_execute_task(&pool, &email_client, &base_url).await;
+        record_pool_stats("delivery_worker", &pool);
+        let wait = match outcome {
+            // Exponential backoff with jitter would be better
+            Ok(ExecutionOutcome::EmptyQueue) => settings.idle_poll_interval(),
+            // Exponential backoff with jitter would be better
+            Err(_) => settings.error_backoff(),
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+        };
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(wait) => {}
         }
     }
+    tracing::info!("Delivery worker stopped");
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -58,37 +118,89 @@ async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyh
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    // for the unsubscribe links
+    base_url: &str,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
     let task = dequeue_task(pool).await?;
     if task.is_none() {
         return Ok(ExecutionOutcome::EmptyQueue);
     }
 
-    let (transaction, issue_id, email) = task.unwrap();
+    let (
+        transaction,
+        DeliveryTask {
+            newsletter_issue_id: issue_id,
+            subscriber_email: email,
+            enqueued_at,
+            traceparent,
+        },
+    ) = task.unwrap();
     Span::current()
         .record("newsletter_issue_id", display(issue_id))
         .record("subscriber_email", display(&email));
 
+    // the delivery is part of the trace of the request which published the issue
+    let delivery_span = tracing::info_span!("Deliver issue");
+    if let Some(traceparent) = &traceparent {
+        continue_trace(&delivery_span, traceparent);
+    }
+    deliver(pool, email_client, base_url, issue_id, &email, enqueued_at)
+        .instrument(delivery_span)
+        .await?;
+
+    delete_task(transaction, issue_id, &email).await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// Errors which won't go away by retrying are logged, and the task is skipped
+async fn deliver(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    issue_id: Uuid,
+    email: &str,
+    enqueued_at: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
     // TODO: Add delivery_status column, num_retries, execute_after columns to issue_queue
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
+    match SubscriberEmail::parse(email.to_string()) {
+        Ok(email) => match get_unsubscribe_token(pool, &email).await? {
+            Some(unsubscribe_token) => {
+                let issue = get_issue(pool, issue_id).await?;
+                let unsubscribe_link = format!(
+                    "{}/subscriptions/unsubscribe?token={}",
+                    base_url, unsubscribe_token
+                );
+                // the layout is applied when sending, so that queued issues follow its latest version
+                let rendered = get_email_layout(pool).await?.render(
                     &issue.html_content,
                     &issue.text_content,
-                )
-                .await
-            {
-                tracing::error!(
-                    error.cause_chain = ?e,
-                    error.message = %e,
-                    "Failed to deliver issue to a confirmed subscriber. Skipping."
+                    Some(&unsubscribe_link),
+                )?;
+                match email_client
+                    .send_email(&email, &issue.title, &rendered.html, &rendered.text)
+                    .await
+                {
+                    Ok(()) => {
+                        let latency = (Utc::now() - enqueued_at).to_std().unwrap_or_default();
+                        metrics::histogram!("issue_delivery_latency_seconds")
+                            .record(latency.as_secs_f64());
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            error.cause_chain = ?e,
+                            error.message = %e,
+                            "Failed to deliver issue to a confirmed subscriber. Skipping."
+                        );
+                    }
+                }
+            }
+            None => {
+                tracing::info!(
+                    "Skipping a subscriber who unsubscribed since the issue was published"
                 );
             }
-        }
+        },
         Err(e) => {
             tracing::error!(
                 error.cause_chain = ?e,
@@ -97,20 +209,18 @@ pub async fn try_execute_task(
             );
         }
     }
-
-    delete_task(transaction, issue_id, &email).await?;
-
-    Ok(ExecutionOutcome::TaskCompleted)
+    Ok(())
 }
 
 #[tracing::instrument(skip_all)]
 async fn dequeue_task(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+) -> Result<Option<(PgTransaction, DeliveryTask)>, anyhow::Error> {
     let mut transaction = pool.begin().await?;
-    let r = sqlx::query!(
+    let r = sqlx::query_as!(
+        DeliveryTask,
         r#"
-        SELECT newsletter_issue_id, subscriber_email
+        SELECT newsletter_issue_id, subscriber_email, enqueued_at, traceparent
         FROM issue_delivery_queue
         FOR UPDATE
         SKIP LOCKED
@@ -120,15 +230,7 @@ async fn dequeue_task(
     .fetch_optional(&mut *transaction)
     .await?;
 
-    if let Some(r) = r {
-        Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        )))
-    } else {
-        Ok(None)
-    }
+    Ok(r.map(|task| (transaction, task)))
 }
 
 #[tracing::instrument(skip_all)]
@@ -154,6 +256,26 @@ async fn delete_task(
     Ok(())
 }
 
+// None once the subscriber has unsubscribed
+#[tracing::instrument(skip_all)]
+async fn get_unsubscribe_token(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT unsubscribe_token
+        FROM subscriptions
+        WHERE email = $1 AND status = 'confirmed'
+        "#,
+        email.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| r.unsubscribe_token))
+}
+
 #[tracing::instrument(skip_all)]
 async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
     let issue = sqlx::query_as!(
@@ -1,12 +1,22 @@
+pub mod audit;
 pub mod authentication;
+pub mod cli;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_layout;
+pub mod flash_messages;
+pub mod health;
 pub mod idempotency;
 pub mod idempotency_cleaner_worker;
 pub mod issue_delivery_worker;
+pub mod prometheus;
 pub mod routes;
+pub mod session_cleaner_worker;
 pub mod session_state;
+pub mod session_store;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
+pub mod templates;
 pub mod utils;
@@ -1,43 +1,246 @@
+use std::collections::HashMap;
 use std::fmt::{Debug, Display};
+use std::future::Future;
+use std::net::TcpListener;
+use std::time::Duration;
 
-use newsletter::configuration::get_configuration;
+use clap::Parser;
+use newsletter::cli::{Cli, Command, Worker, run_admin_command, run_migrations};
+use newsletter::configuration::{
+    SessionStoreBackend, Settings, get_configuration, get_database_configuration,
+};
 use newsletter::idempotency_cleaner_worker::run_idempotency_worker_until_stopped;
 use newsletter::issue_delivery_worker::run_worker_until_stopped;
-use newsletter::startup::Application;
-use newsletter::telemetry::{get_subscriber, init_subscriber};
-use tokio::task::JoinError;
+use newsletter::session_cleaner_worker::run_session_cleaner_until_stopped;
+use newsletter::shutdown::shutdown_signal;
+use newsletter::startup::{Application, get_connection_pool, run_metrics_server};
+use newsletter::telemetry::{flush_traces, get_subscriber, init_subscriber, otlp_tracer};
+use tokio::task::{Id, JoinError, JoinSet};
+use tokio_util::sync::CancellationToken;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let command = Cli::parse().command.unwrap_or(Command::All);
+
+    // one-off commands only log warnings to stderr, so that their output on stdout stays readable
+    // the ones which only connect to the database don't need the rest of the configuration
+    match command {
+        Command::Admin { command } => {
+            init_one_off_subscriber();
+            let pool = get_connection_pool(&get_database_configuration()?);
+            return run_admin_command(command, &pool).await;
+        }
+        Command::Migrate => {
+            init_one_off_subscriber();
+            let pool = get_connection_pool(&get_database_configuration()?);
+            return run_migrations(&pool).await;
+        }
+        Command::CheckConfig => {
+            init_one_off_subscriber();
+            get_configuration()?;
+            println!("The configuration is valid");
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // get configuration for the application, an invalid one is reported with all its problems
+    let configuration = get_configuration()?;
+
     // tracing/telemetry setup
-    let subscriber = get_subscriber("newsletter".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
+    init_subscriber(get_subscriber(
+        "newsletter".into(),
+        "info".into(),
+        std::io::stdout,
+        otlp_tracer(&configuration.telemetry)?,
+    ));
+
+    // cancelled on SIGTERM/SIGINT or as soon as one of the tasks exits
+    let shutdown = CancellationToken::new();
+    let mut tasks = Tasks::default();
+
+    match command {
+        Command::Admin { .. } | Command::Migrate | Command::CheckConfig => {
+            unreachable!("one-off commands have already returned")
+        }
+        Command::Serve => spawn_api(&mut tasks, &configuration, &shutdown).await?,
+        Command::Worker {
+            worker: Worker::Delivery,
+        } => {
+            spawn_metrics_server(&mut tasks, &configuration, &shutdown)?;
+            spawn_delivery_worker(&mut tasks, &configuration, &shutdown);
+        }
+        Command::Worker {
+            worker: Worker::Cleanup,
+        } => {
+            spawn_metrics_server(&mut tasks, &configuration, &shutdown)?;
+            spawn_idempotency_worker(&mut tasks, &configuration, &shutdown);
+            spawn_session_cleaner(&mut tasks, &configuration, &shutdown);
+        }
+        Command::All => {
+            spawn_api(&mut tasks, &configuration, &shutdown).await?;
+            spawn_delivery_worker(&mut tasks, &configuration, &shutdown);
+            spawn_idempotency_worker(&mut tasks, &configuration, &shutdown);
+            spawn_session_cleaner(&mut tasks, &configuration, &shutdown);
+        }
+    }
+
+    let outcome = tasks
+        .run_until_shutdown(shutdown, configuration.application.shutdown_timeout())
+        .await;
+    tokio::task::spawn_blocking(flush_traces).await?;
+    outcome
+}
 
-    // get configuration for the application
-    let configuration = get_configuration().expect("Failed to read configuration.");
+fn init_one_off_subscriber() {
+    init_subscriber(get_subscriber(
+        "newsletter".into(),
+        "warn".into(),
+        std::io::stderr,
+        None,
+    ));
+}
 
+async fn spawn_api(
+    tasks: &mut Tasks,
+    configuration: &Settings,
+    shutdown: &CancellationToken,
+) -> anyhow::Result<()> {
     // build the application and run the server
     let application = Application::build(configuration.clone()).await?;
-    let application_task = tokio::spawn(application.run_until_stopped());
-
-    // worker task
-    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
-    let idempotency_cleaner_task =
-        tokio::spawn(run_idempotency_worker_until_stopped(configuration));
-
-    // All selected futures are polled on same task, concurrency not parallel.
-    // Both run on the same thread, if one branch blocks the thread, all other expressions will be unable to continue
-    // If want parallelism, run on separate threads
-    tokio::select! {
-        o = application_task => report_exit("API", o),
-        o = worker_task => report_exit("Background worker", o),
-        o = idempotency_cleaner_task => report_exit("Idempotency worker", o),
-    };
+    let shutdown = shutdown.clone();
+    tasks.spawn("API", async move {
+        application
+            .run_until_shutdown(shutdown)
+            .await
+            .map_err(anyhow::Error::from)
+    });
+    Ok(())
+}
 
+// Workers running without the API expose their metrics on the metrics port, when there is one
+fn spawn_metrics_server(
+    tasks: &mut Tasks,
+    configuration: &Settings,
+    shutdown: &CancellationToken,
+) -> anyhow::Result<()> {
+    let Some(port) = configuration.application.metrics_port else {
+        return Ok(());
+    };
+    let listener = TcpListener::bind(format!("{}:{}", configuration.application.host, port))?;
+    let server = run_metrics_server(
+        listener,
+        get_connection_pool(&configuration.database),
+        "metrics_server",
+    )?;
+    let server_handle = server.handle();
+    let shutdown = shutdown.clone();
+    tasks.spawn("Metrics server", async move {
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            server_handle.stop(true).await;
+        });
+        server.await.map_err(anyhow::Error::from)
+    });
     Ok(())
 }
 
-fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+fn spawn_delivery_worker(
+    tasks: &mut Tasks,
+    configuration: &Settings,
+    shutdown: &CancellationToken,
+) {
+    tasks.spawn(
+        "Background worker",
+        run_worker_until_stopped(configuration.clone(), shutdown.clone()),
+    );
+}
+
+fn spawn_idempotency_worker(
+    tasks: &mut Tasks,
+    configuration: &Settings,
+    shutdown: &CancellationToken,
+) {
+    tasks.spawn(
+        "Idempotency worker",
+        run_idempotency_worker_until_stopped(configuration.clone(), shutdown.clone()),
+    );
+}
+
+// Nothing to clean up when Redis holds the sessions. Not spawned rather than exiting right away,
+// since any task exiting stops the process
+fn spawn_session_cleaner(
+    tasks: &mut Tasks,
+    configuration: &Settings,
+    shutdown: &CancellationToken,
+) {
+    if configuration.session_store.backend == SessionStoreBackend::Redis {
+        return;
+    }
+    tasks.spawn(
+        "Session cleaner",
+        run_session_cleaner_until_stopped(configuration.clone(), shutdown.clone()),
+    );
+}
+
+// Long-running tasks of this process, tracked by name to report how each of them exited
+#[derive(Default)]
+struct Tasks {
+    set: JoinSet<anyhow::Result<()>>,
+    names: HashMap<Id, &'static str>,
+}
+
+impl Tasks {
+    fn spawn<F>(&mut self, name: &'static str, task: F)
+    where
+        F: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let handle = self.set.spawn(task);
+        self.names.insert(handle.id(), name);
+    }
+
+    async fn run_until_shutdown(
+        mut self,
+        shutdown: CancellationToken,
+        shutdown_timeout: Duration,
+    ) -> anyhow::Result<()> {
+        // All tasks run on the multi-threaded runtime, so they make progress in parallel.
+        // We wait for either a shutdown signal or for any of them to exit, then stop all of them
+        tokio::select! {
+            _ = shutdown_signal() => {
+                tracing::info!("Shutting down gracefully, waiting up to {:?}", shutdown_timeout)
+            }
+            Some(outcome) = self.set.join_next_with_id() => report_exit(&self.names, outcome),
+        };
+        shutdown.cancel();
+
+        let drain = async {
+            while let Some(outcome) = self.set.join_next_with_id().await {
+                report_exit(&self.names, outcome);
+            }
+        };
+        if tokio::time::timeout(shutdown_timeout, drain).await.is_err() {
+            self.set.abort_all();
+            anyhow::bail!(
+                "Tasks did not stop within {:?}, they have been aborted",
+                shutdown_timeout
+            );
+        }
+
+        tracing::info!("Shutdown completed");
+        Ok(())
+    }
+}
+
+fn report_exit(
+    task_names: &HashMap<Id, &str>,
+    outcome: Result<(Id, Result<(), impl Debug + Display>), JoinError>,
+) {
+    let (task_name, outcome) = match outcome {
+        Ok((id, o)) => (task_names[&id], Ok(o)),
+        Err(e) => (task_names[&e.id()], Err(e)),
+    };
+
     match outcome {
         Ok(Ok(())) => {
             tracing::info!("{} has exited", task_name)
@@ -0,0 +1,91 @@
+use std::{sync::OnceLock, time::Instant};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+};
+use actix_web_lab::middleware::Next;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use sqlx::PgPool;
+
+// Latencies range from a few milliseconds for most pages to seconds for the email provider
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+// Deliveries wait in the queue behind the other subscribers of the issue
+const DELIVERY_LATENCY_BUCKETS: &[f64] = &[
+    1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0, 10800.0, 43200.0, 86400.0,
+];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+// The recorder behind the `metrics` facade is global to the process: it's installed by the first
+// server exposing `/metrics`, the API and the workers of a process then report to it
+pub fn prometheus_handle() -> PrometheusHandle {
+    HANDLE
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Full("issue_delivery_latency_seconds".into()),
+                    DELIVERY_LATENCY_BUCKETS,
+                )
+                .and_then(|builder| {
+                    builder
+                        .set_buckets_for_metric(Matcher::Suffix("_seconds".into()), LATENCY_BUCKETS)
+                })
+                .expect("Failed to configure the histogram buckets")
+                .install_recorder()
+                .expect("Failed to install the Prometheus recorder")
+        })
+        .clone()
+}
+
+// Counts requests and measures their latency by route pattern, so that ids in paths don't create
+// a time series per resource
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let response = next.call(req).await;
+    let status = match &response {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", status.as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels)
+        .record(start.elapsed().as_secs_f64());
+    response
+}
+
+// Gauges are sampled when `/metrics` is scraped
+pub async fn record_queue_depth(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let depth = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(pool)
+        .await?;
+    metrics::gauge!("issue_delivery_queue_depth").set(depth as f64);
+    Ok(())
+}
+
+// Label of the pool of the server exposing `/metrics`, whose stats are sampled when scraped
+pub struct ScrapedPool(pub &'static str);
+
+// Each server and worker has its own pool, told apart by the `pool` label
+pub fn record_pool_stats(name: &'static str, pool: &PgPool) {
+    let idle = pool.num_idle() as f64;
+    let size = pool.size() as f64;
+    metrics::gauge!("db_pool_connections", "pool" => name, "state" => "idle").set(idle);
+    metrics::gauge!("db_pool_connections", "pool" => name, "state" => "in_use").set(size - idle);
+    metrics::gauge!("db_pool_max_connections", "pool" => name)
+        .set(pool.options().get_max_connections() as f64);
+}
@@ -0,0 +1,39 @@
+use actix_web::{HttpResponse, get, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{self, ApiToken, UserId},
+    templates::{AdminNav, render},
+    utils::e500,
+};
+
+#[derive(Template)]
+#[template(path = "admin/api_tokens.html")]
+struct ApiTokensTemplate {
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+    tokens: Vec<ApiToken>,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 200, description = "The API tokens of the user"))
+)]
+#[get("/api-tokens")]
+pub async fn list_api_tokens(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let tokens = authentication::list_api_tokens(**user_id, &pool)
+        .await
+        .map_err(e500)?;
+    render(&ApiTokensTemplate {
+        nav,
+        flash_messages,
+        tokens,
+    })
+}
@@ -0,0 +1,111 @@
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{self, UserId},
+    startup::HmacSecret,
+    templates::{AdminNav, render},
+    utils::{client_ip, e500, see_other},
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = CreateApiTokenForm)]
+pub struct CreateFormData {
+    name: String,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = RevokeApiTokenForm)]
+pub struct RevokeFormData {
+    token_id: Uuid,
+}
+
+#[derive(Template)]
+#[template(path = "admin/api_token_created.html")]
+struct ApiTokenCreatedTemplate {
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+    name: String,
+    token: String,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 200, description = "The new token, shown once"))
+)]
+#[tracing::instrument(
+    name = "Create an API token",
+    skip(form, request, pool, hmac_secret, nav, flash_messages),
+    fields(user_id=%*user_id)
+)]
+#[post("/api-tokens")]
+pub async fn create_api_token(
+    form: web::Form<CreateFormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let name = form.0.name.trim().to_string();
+    if name.is_empty() {
+        FlashMessage::error("The name of the token cannot be empty.").send();
+        return Ok(see_other("/admin/api-tokens"));
+    }
+
+    let (token_id, token) =
+        authentication::create_api_token(*user_id, &name, &hmac_secret.0, &pool)
+            .await
+            .map_err(e500)?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::ApiTokenCreated)
+        .target(token_id)
+        .ip_address(client_ip(&request))
+        .details(&name)
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
+
+    // the token can't be retrieved later on, only its signature is stored
+    render(&ApiTokenCreatedTemplate {
+        nav,
+        flash_messages,
+        name,
+        token: token.expose_secret().to_owned(),
+    })
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 303, description = "Redirects to the API tokens with a flash message"))
+)]
+#[tracing::instrument(name = "Revoke an API token", skip(form, request, pool), fields(user_id=%*user_id))]
+#[post("/api-tokens/revoke")]
+pub async fn revoke_api_token(
+    form: web::Form<RevokeFormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    // users can only revoke their own tokens
+    if authentication::revoke_api_token(*user_id, form.token_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        AuditEvent::new(Actor::User(*user_id), AuditAction::ApiTokenRevoked)
+            .target(form.token_id)
+            .ip_address(client_ip(&request))
+            .record(pool.get_ref())
+            .await
+            .map_err(e500)?;
+        FlashMessage::info("The API token has been revoked.").send();
+    }
+    Ok(see_other("/admin/api-tokens"))
+}
@@ -0,0 +1,40 @@
+use actix_web::{HttpResponse, get, http::header::ContentDisposition, web};
+use sqlx::PgPool;
+
+use super::QueryParams;
+use crate::{
+    audit::{AuditLogFilter, search_audit_log},
+    utils::{e400, e500},
+};
+
+// the whole export is built in memory
+const MAX_EXPORTED_ENTRIES: i64 = 10_000;
+
+// Same filters as the audit log page, up to MAX_EXPORTED_ENTRIES entries
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses(
+        (status = 200, description = "All the matching entries, as a JSON attachment, requires an owner"),
+        (status = 400, description = "Invalid filters, or too many matching entries"),
+    )
+)]
+#[get("/export")]
+pub async fn export_audit_log(
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = AuditLogFilter::try_from(&query.0).map_err(e400)?;
+    // one more to know whether some would be left out
+    let entries = search_audit_log(&filter, Some(MAX_EXPORTED_ENTRIES + 1), &pool)
+        .await
+        .map_err(e500)?;
+    if entries.len() as i64 > MAX_EXPORTED_ENTRIES {
+        return Err(e400(format!(
+            "More than {} entries match, narrow down the filters.",
+            MAX_EXPORTED_ENTRIES
+        )));
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition::attachment("audit-log.json"))
+        .json(entries))
+}
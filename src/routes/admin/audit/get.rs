@@ -0,0 +1,65 @@
+use actix_web::{HttpResponse, get, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+use super::QueryParams;
+use crate::{
+    audit::{AuditAction, AuditLogEntry, AuditLogFilter, search_audit_log},
+    templates::{AdminNav, render},
+    utils::{e400, e500},
+};
+
+// the export has a much higher limit
+const PAGE_SIZE: i64 = 200;
+
+#[derive(Template)]
+#[template(path = "admin/audit_log.html")]
+struct AuditLogTemplate {
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+    actions: [AuditAction; AuditAction::ALL.len()],
+    // to fill the filter form again
+    params: QueryParams,
+    export_query: String,
+    page_size: i64,
+    entries: Vec<AuditLogEntry>,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses(
+        (status = 200, description = "The latest entries of the audit log, requires an owner"),
+        (status = 400, description = "Invalid filters"),
+    )
+)]
+#[get("")]
+pub async fn audit_log(
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let params = query.0;
+    let filter = AuditLogFilter::try_from(&params).map_err(e400)?;
+    let entries = search_audit_log(&filter, Some(PAGE_SIZE), &pool)
+        .await
+        .map_err(e500)?;
+    let export_query = format!(
+        "action={}&actor={}&since={}&until={}",
+        urlencoding::Encoded::new(&params.action),
+        urlencoding::Encoded::new(&params.actor),
+        urlencoding::Encoded::new(&params.since),
+        urlencoding::Encoded::new(&params.until),
+    );
+
+    render(&AuditLogTemplate {
+        nav,
+        flash_messages,
+        actions: AuditAction::ALL,
+        params,
+        export_query,
+        page_size: PAGE_SIZE,
+        entries,
+    })
+}
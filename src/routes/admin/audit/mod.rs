@@ -0,0 +1,79 @@
+pub(crate) mod export;
+pub(crate) mod get;
+
+pub use export::export_audit_log;
+pub use get::audit_log;
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+
+use crate::audit::{AuditAction, AuditLogFilter};
+
+// Empty form fields are submitted too, they mean "no filter"
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+pub struct QueryParams {
+    #[serde(default)]
+    action: String,
+    #[serde(default)]
+    actor: String,
+    // YYYY-MM-DD, both days are included
+    #[serde(default)]
+    since: String,
+    #[serde(default)]
+    until: String,
+}
+
+impl TryFrom<&QueryParams> for AuditLogFilter {
+    type Error = String;
+
+    fn try_from(params: &QueryParams) -> Result<Self, Self::Error> {
+        let action = non_empty(&params.action)
+            .map(|action| AuditAction::try_from(action.to_string()).map(|a| a.as_str().to_string()))
+            .transpose()?;
+        let since = non_empty(&params.since).map(parse_date).transpose()?;
+        // the day after, as the last day is included
+        let until = non_empty(&params.until)
+            .map(|s| {
+                parse_date(s)?
+                    .checked_add_days(Days::new(1))
+                    .ok_or_else(|| format!("{} is not a valid date.", s))
+            })
+            .transpose()?;
+        Ok(AuditLogFilter {
+            action,
+            actor: non_empty(&params.actor).map(str::to_string),
+            since: since.map(start_of_day),
+            until: until.map(start_of_day),
+        })
+    }
+}
+
+fn non_empty(s: &str) -> Option<&str> {
+    let s = s.trim();
+    (!s.is_empty()).then_some(s)
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| format!("{} is not a valid date.", s))
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueryParams, parse_date};
+    use crate::audit::AuditLogFilter;
+
+    #[test]
+    fn the_last_representable_day_is_rejected_as_an_upper_bound() {
+        assert!(parse_date("+262142-12-31").is_ok());
+        let params = QueryParams {
+            action: String::new(),
+            actor: String::new(),
+            since: "+262142-12-31".into(),
+            until: "+262142-12-31".into(),
+        };
+        assert!(AuditLogFilter::try_from(&params).is_err());
+    }
+}
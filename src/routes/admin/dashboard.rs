@@ -1,43 +1,56 @@
-use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web::{HttpResponse, get, web};
+use actix_web_flash_messages::IncomingFlashMessages;
 use anyhow::Context;
+use askama::Template;
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::{authentication::UserId, utils::e500};
+use crate::{
+    authentication::UserId,
+    templates::{AdminNav, render},
+    utils::e500,
+};
 
+struct SubscriberCounts {
+    confirmed: i64,
+    pending_confirmation: i64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardTemplate {
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+    username: String,
+    confirmed: i64,
+    pending_confirmation: i64,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 200, description = "The dashboard"))
+)]
+#[get("/dashboard")]
 pub async fn admin_dashboard(
     user_id: web::ReqData<UserId>,
     pool: web::Data<PgPool>,
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let SubscriberCounts {
+        confirmed,
+        pending_confirmation,
+    } = get_subscriber_counts(&pool).await.map_err(e500)?;
 
-    let response = HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
- <html lang="en">
- <head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Admin dashboard</title>
- </head>
- <body>
-    <p>Welcome {username}!</p>
-    <p>Available actions:</p>
-    <ol>
-            <li><a href="/admin/password">Change password</a></li>
-            <li>
-                <form name="logoutForm" action="/admin/logout" method="post">
-                    <input type="submit" value="Logout">
-                </form>
-            </li>
-            <li><a href="/admin/newsletters">Send a newsletter issue</a></li>
-    </ol>
- </body>
- </html>"#
-        ));
-
-    Ok(response)
+    render(&DashboardTemplate {
+        nav,
+        flash_messages,
+        username,
+        confirmed,
+        pending_confirmation,
+    })
 }
 
 #[tracing::instrument(name = "Get username", skip(pool))]
@@ -56,3 +69,21 @@ pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow
 
     Ok(row.username)
 }
+
+#[tracing::instrument(name = "Get subscriber counts", skip(pool))]
+async fn get_subscriber_counts(pool: &PgPool) -> Result<SubscriberCounts, anyhow::Error> {
+    let counts = sqlx::query_as!(
+        SubscriberCounts,
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE status = 'confirmed') as "confirmed!",
+            COUNT(*) FILTER (WHERE status = 'pending_confirmation') as "pending_confirmation!"
+        FROM subscriptions
+        "#,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to count subscribers.")?;
+
+    Ok(counts)
+}
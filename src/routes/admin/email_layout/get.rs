@@ -0,0 +1,36 @@
+use actix_web::{HttpResponse, get, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::{
+    email_layout::{EmailLayout, get_email_layout},
+    templates::{AdminNav, render},
+    utils::e500,
+};
+
+#[derive(Template)]
+#[template(path = "admin/email_layout.html")]
+struct EmailLayoutTemplate {
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+    layout: EmailLayout,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 200, description = "The form to edit the email layout, requires an owner"))
+)]
+#[get("")]
+pub async fn email_layout_form(
+    pool: web::Data<PgPool>,
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let layout = get_email_layout(&pool).await.map_err(e500)?;
+    render(&EmailLayoutTemplate {
+        nav,
+        flash_messages,
+        layout,
+    })
+}
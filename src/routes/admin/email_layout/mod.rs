@@ -0,0 +1,7 @@
+pub(crate) mod get;
+pub(crate) mod post;
+pub(crate) mod preview;
+
+pub use get::email_layout_form;
+pub use post::update_email_layout;
+pub use preview::preview_email_layout;
@@ -0,0 +1,70 @@
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use super::preview::{PREVIEW_HTML_CONTENT, PREVIEW_TEXT_CONTENT};
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::UserId,
+    email_layout::{self, EmailLayout},
+    utils::{client_ip, e500, see_other},
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = EmailLayoutForm)]
+pub struct FormData {
+    header: String,
+    footer: String,
+    styles: String,
+    postal_address: String,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 303, description = "Redirects to the form with a flash message, requires an owner"))
+)]
+#[tracing::instrument(name = "Update the email layout", skip(form, request, pool), fields(user_id=%*user_id))]
+#[post("")]
+pub async fn update_email_layout(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let FormData {
+        header,
+        footer,
+        styles,
+        postal_address,
+    } = form.0;
+    let layout = EmailLayout {
+        header,
+        footer,
+        styles,
+        postal_address: postal_address.trim().to_string(),
+    };
+
+    // a layout that can't be rendered would block the delivery of every email
+    if let Err(e) = layout.render(PREVIEW_HTML_CONTENT, PREVIEW_TEXT_CONTENT, Some("#")) {
+        tracing::warn!(error.cause_chain = ?e, "Rejected an email layout that can't be rendered");
+        FlashMessage::error(format!(
+            "The email layout can't be rendered: {}",
+            e.root_cause()
+        ))
+        .send();
+        return Ok(see_other("/admin/email-layout"));
+    }
+
+    email_layout::update_email_layout(&layout, pool.get_ref())
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::SettingsUpdated)
+        .ip_address(client_ip(&request))
+        .details("email_layout")
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("The email layout has been saved.").send();
+    Ok(see_other("/admin/email-layout"))
+}
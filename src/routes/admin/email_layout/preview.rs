@@ -0,0 +1,27 @@
+use actix_web::{HttpResponse, get, http::header::ContentType, web};
+use sqlx::PgPool;
+
+use crate::{email_layout::get_email_layout, utils::e500};
+
+pub const PREVIEW_HTML_CONTENT: &str = "<h2>Issue title</h2>\
+    <p>This is how the issues and the other emails look like once wrapped in the layout.</p>";
+pub const PREVIEW_TEXT_CONTENT: &str =
+    "This is how the issues and the other emails look like once wrapped in the layout.";
+
+// The email as subscribers receive it, with its styles inlined
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 200, description = "An email wrapped in the layout, with its styles inlined, requires an owner"))
+)]
+#[get("/preview")]
+pub async fn preview_email_layout(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let layout = get_email_layout(&pool).await.map_err(e500)?;
+    let email = layout
+        .render(PREVIEW_HTML_CONTENT, PREVIEW_TEXT_CONTENT, Some("#"))
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(email.html))
+}
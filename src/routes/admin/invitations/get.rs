@@ -0,0 +1,27 @@
+use actix_web::{HttpResponse, get};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+use crate::templates::{AdminNav, render};
+
+#[derive(Template)]
+#[template(path = "admin/invite_user.html")]
+struct InviteUserTemplate {
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 200, description = "The form to invite a user, requires an owner"))
+)]
+#[get("")]
+pub async fn invite_user_form(
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&InviteUserTemplate {
+        nav,
+        flash_messages,
+    })
+}
@@ -0,0 +1,5 @@
+pub(crate) mod get;
+pub(crate) mod post;
+
+pub use get::invite_user_form;
+pub use post::invite_user;
@@ -0,0 +1,171 @@
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{Role, UserId, generate_token, sign_token},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    email_layout::{EmailLayout, get_email_layout},
+    startup::{ApplicationBaseUrl, HmacSecret},
+    utils::{client_ip, e500, see_other},
+};
+
+// invitees have a week to accept their invitation
+const INVITATION_TTL: chrono::TimeDelta = chrono::TimeDelta::days(7);
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = InviteUserForm)]
+pub struct FormData {
+    email: String,
+    role: String,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 303, description = "Redirects to the form with a flash message, requires an owner"))
+)]
+#[tracing::instrument(
+    name = "Invite a new user",
+    skip(form, request, pool, email_client, base_url, hmac_secret),
+    fields(user_id=%*user_id, invitee_email=%form.email)
+)]
+#[post("")]
+pub async fn invite_user(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let FormData { email, role } = form.0;
+
+    let email = match SubscriberEmail::parse(email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/invitations"));
+        }
+    };
+    let role = match Role::try_from(role) {
+        Ok(role) => role,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/invitations"));
+        }
+    };
+
+    let token = generate_token();
+    let token_signature = sign_token(token.expose_secret(), &hmac_secret.0);
+    store_invitation(&pool, &email, role, &token_signature, *user_id)
+        .await
+        .context("Failed to store the invitation")
+        .map_err(e500)?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::UserInvited)
+        .target(&email)
+        .ip_address(client_ip(&request))
+        .details(format!("as {}", role))
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
+
+    let layout = get_email_layout(&pool).await.map_err(e500)?;
+    send_invitation_email(
+        &email_client,
+        &layout,
+        &email,
+        role,
+        &base_url.0,
+        token.expose_secret(),
+    )
+    .await
+    .context("Failed to send an invitation email")
+    .map_err(e500)?;
+
+    FlashMessage::info(format!("An invitation has been sent to {}.", email)).send();
+    Ok(see_other("/admin/invitations"))
+}
+
+#[tracing::instrument(skip_all)]
+async fn store_invitation(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+    role: Role,
+    token_signature: &str,
+    invited_by: Uuid,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO invitations (
+            invitation_id,
+            email,
+            role,
+            token_signature,
+            invited_by,
+            created_at,
+            expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        email.as_ref(),
+        role.as_str(),
+        token_signature,
+        invited_by,
+        now,
+        now + INVITATION_TTL,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Send an invitation email", skip_all)]
+async fn send_invitation_email(
+    email_client: &EmailClient,
+    layout: &EmailLayout,
+    email: &SubscriberEmail,
+    role: Role,
+    base_url: &str,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let invitation_link = format!("{}/invitations/accept?token={}", base_url, token);
+
+    let plain_body = format!(
+        "You have been invited to administer our newsletter as {}.\n\
+        Visit {} to choose your username and password. The invitation expires in {} days.",
+        role,
+        invitation_link,
+        INVITATION_TTL.num_days()
+    );
+
+    let html_body = format!(
+        "You have been invited to administer our newsletter as {}.<br />\
+        Click <a href=\"{}\">here</a> to choose your username and password. \
+        The invitation expires in {} days.",
+        role,
+        invitation_link,
+        INVITATION_TTL.num_days()
+    );
+
+    let rendered = layout.render(&html_body, &plain_body, None)?;
+    email_client
+        .send_email(
+            email,
+            "You have been invited to our newsletter",
+            &rendered.html,
+            &rendered.text,
+        )
+        .await?;
+    Ok(())
+}
@@ -1,9 +1,37 @@
-use actix_web::HttpResponse;
+use actix_web::{HttpRequest, HttpResponse, post, web};
 use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
 
-use crate::{session_state::TypedSession, utils::see_other};
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{UserId, revoke_session},
+    session_state::TypedSession,
+    utils::{client_ip, e500, see_other},
+};
 
-pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 303, description = "Redirects to the login form"))
+)]
+#[post("/logout")]
+pub async fn log_out(
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    // so that the session is not listed as active anymore
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        revoke_session(*user_id, session_id, &pool)
+            .await
+            .map_err(e500)?;
+    }
+    AuditEvent::new(Actor::User(*user_id), AuditAction::Logout)
+        .ip_address(client_ip(&request))
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
     session.log_out();
     FlashMessage::info("You have successfully logged out.").send();
     Ok(see_other("/login"))
@@ -1,9 +1,23 @@
-mod dashboard;
-mod logout;
-mod newsletters;
-mod password;
+pub(crate) mod api_tokens;
+pub(crate) mod audit;
+pub(crate) mod dashboard;
+pub(crate) mod email_layout;
+pub(crate) mod invitations;
+pub(crate) mod logout;
+pub(crate) mod newsletters;
+pub(crate) mod password;
+pub(crate) mod sessions;
+pub(crate) mod settings;
+pub(crate) mod two_factor;
 
-pub use dashboard::admin_dashboard;
+pub use api_tokens::*;
+pub use audit::*;
+pub use dashboard::{admin_dashboard, get_username};
+pub use email_layout::*;
+pub use invitations::*;
 pub use logout::log_out;
 pub use newsletters::*;
 pub use password::*;
+pub use sessions::*;
+pub use settings::*;
+pub use two_factor::*;
@@ -1,30 +1,49 @@
-use actix_web::{HttpResponse, web};
+use actix_web::{HttpRequest, HttpResponse, post, web};
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
     authentication::UserId,
-    idempotency::{IdempotencyKey, NextAction, save_response, try_processing},
-    utils::{e400, e500, see_other},
+    idempotency::{
+        IdempotencyKey, IdempotencyScope, IdempotentEndpoint, NextAction, RequestFingerprint,
+        save_response, try_processing,
+    },
+    telemetry::current_traceparent,
+    utils::{client_ip, e400, e422, e500, see_other},
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = PublishNewsletterForm)]
 pub struct FormData {
     title: String,
     text_content: String,
     html_content: String,
-    idempotency_key: String,
+    // can be sent in the `Idempotency-Key` header instead
+    #[serde(default)]
+    idempotency_key: Option<String>,
 }
 
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    params(("Idempotency-Key" = Option<String>, Header, description = "Alternative to the idempotency_key field")),
+    responses(
+        (status = 303, description = "Redirects to the form with a flash message, requires an editor"),
+        (status = 400, description = "Missing or invalid idempotency key"),
+        (status = 422, description = "The idempotency key has already been used for another issue"),
+    )
+)]
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(form, pool),
+    skip(form, request, pool),
     fields(user_id=%*user_id)
 )]
+#[post("")]
 pub async fn publish_newsletters(
     form: web::Form<FormData>,
+    request: HttpRequest,
     user_id: web::ReqData<UserId>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
@@ -35,18 +54,40 @@ pub async fn publish_newsletters(
         html_content,
         idempotency_key,
     } = form.0;
+    // wherever the key comes from, retries are the same request
+    let fingerprint =
+        RequestFingerprint::new(&request, &(&title, &text_content, &html_content)).map_err(e500)?;
 
-    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
-    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
-        .await
-        .map_err(e500)?
-    {
-        NextAction::StartProcessing(t) => t,
-        NextAction::ReturnSavedResponse(saved_response) => {
-            success_message().send();
-            return Ok(saved_response);
+    let header_key = IdempotencyKey::from_header(&request).map_err(e400)?;
+    let idempotency_key = match (header_key, idempotency_key) {
+        (Some(key), None) => key,
+        (None, Some(key)) => key.try_into().map_err(e400)?,
+        (Some(key), Some(field)) if key.as_ref() == field => key,
+        (Some(_), Some(_)) => {
+            return Err(e400(
+                "The idempotency keys of the header and of the form differ.",
+            ));
         }
+        (None, None) => return Err(e400("The idempotency key is missing.")),
     };
+    let endpoint = IdempotentEndpoint::PublishNewsletter;
+    let scope = IdempotencyScope::User(*user_id);
+    let mut transaction =
+        match try_processing(&pool, endpoint, &idempotency_key, &fingerprint, &scope)
+            .await
+            .map_err(e500)?
+        {
+            NextAction::StartProcessing(t) => t,
+            NextAction::ReturnSavedResponse(saved_response) => {
+                success_message().send();
+                return Ok(saved_response);
+            }
+            NextAction::RejectReusedKey => {
+                return Err(e422(
+                    "The idempotency key has already been used to publish another issue.",
+                ));
+            }
+        };
 
     let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
         .await
@@ -57,9 +98,16 @@ pub async fn publish_newsletters(
         .await
         .context("Failed to enque delivery tasks")
         .map_err(e500)?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::NewsletterPublished)
+        .target(issue_id)
+        .ip_address(client_ip(&request))
+        .details(&title)
+        .record(&mut *transaction)
+        .await
+        .map_err(e500)?;
 
     let response = see_other("/admin/newsletters");
-    let response = save_response(transaction, &idempotency_key, *user_id, response)
+    let response = save_response(transaction, endpoint, &idempotency_key, &scope, response)
         .await
         .map_err(e500)?;
     success_message().send();
@@ -96,8 +144,9 @@ async fn insert_newsletter_issue(
     Ok(newsletter_issue_id)
 }
 
+// One task per confirmed subscriber, picked up by the delivery workers
 #[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+pub async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
 ) -> Result<(), sqlx::Error> {
@@ -105,13 +154,15 @@ async fn enqueue_delivery_tasks(
         r#"
         INSERT INTO issue_delivery_queue (
             newsletter_issue_id,
-            subscriber_email
+            subscriber_email,
+            traceparent
         )
-        SELECT $1, email
+        SELECT $1, email, $2
         FROM subscriptions
         WHERE status = 'confirmed'
         "#,
         newsletter_issue_id,
+        current_traceparent(),
     )
     .execute(&mut **transaction)
     .await?;
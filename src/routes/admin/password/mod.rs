@@ -1,5 +1,5 @@
-mod get;
+pub(crate) mod get;
 pub use get::change_password_form;
 
-mod post;
+pub(crate) mod post;
 pub use post::change_password;
@@ -1,42 +1,47 @@
-use actix_web::{HttpResponse, web};
+use actix_web::{HttpRequest, HttpResponse, post, web};
 use actix_web_flash_messages::FlashMessage;
-use secrecy::{ExposeSecret, Secret};
+use secrecy::Secret;
 use sqlx::PgPool;
 
 use crate::{
-    authentication::{AuthError, Credentials, UserId, validate_credentials},
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{
+        AuthError, Credentials, UserId, check_new_password, revoke_sessions, validate_credentials,
+    },
     routes::admin::dashboard::get_username,
-    utils::{e500, see_other},
+    session_state::TypedSession,
+    utils::{client_ip, e500, see_other},
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = ChangePasswordForm)]
 pub struct FormData {
+    #[schema(value_type = String, format = Password)]
     current_password: Secret<String>,
+    #[schema(value_type = String, format = Password)]
     new_password: Secret<String>,
+    #[schema(value_type = String, format = Password)]
     new_password_check: Secret<String>,
 }
 
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 303, description = "Redirects to the form with a flash message"))
+)]
+#[post("/password")]
 pub async fn change_password(
     form: web::Form<FormData>,
+    request: HttpRequest,
     user_id: web::ReqData<UserId>,
     pool: web::Data<PgPool>,
+    session: TypedSession,
 ) -> Result<HttpResponse, actix_web::Error> {
     // user should be authenticated, in redis, we must have a user_id for token
     let user_id = user_id.into_inner();
 
     // new password and confirm new passwords should match
-    let new_password = form.new_password.expose_secret();
-    let new_password_check = form.new_password_check.expose_secret();
-    if new_password != new_password_check {
-        FlashMessage::error(
-            "You entered two different new passwords - the field values must match.",
-        )
-        .send();
-        return Ok(see_other("/admin/password"));
-    }
-
-    if new_password.len() <= 12 {
-        FlashMessage::error("New password should be greater than 12 characters.").send();
+    if let Err(e) = check_new_password(&form.new_password, &form.new_password_check) {
+        FlashMessage::error(e).send();
         return Ok(see_other("/admin/password"));
     }
 
@@ -56,9 +61,21 @@ pub async fn change_password(
         };
     }
 
-    crate::authentication::change_password(*user_id, form.0.new_password, &pool)
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    crate::authentication::change_password(*user_id, form.0.new_password, &mut *transaction)
+        .await
+        .map_err(e500)?;
+    // whoever knew the previous password must not stay logged in elsewhere
+    let session_id = session.get_session_id().map_err(e500)?;
+    revoke_sessions(*user_id, session_id, &mut *transaction)
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::PasswordChanged)
+        .ip_address(client_ip(&request))
+        .record(&mut *transaction)
         .await
         .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
     FlashMessage::error("Your password has been changed.").send();
     Ok(see_other("/admin/password"))
 }
@@ -0,0 +1,51 @@
+use actix_web::{HttpResponse, get, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{UserId, UserSession, list_active_sessions},
+    session_state::TypedSession,
+    templates::{AdminNav, render},
+    utils::e500,
+};
+
+#[derive(Template)]
+#[template(path = "admin/sessions.html")]
+struct SessionsTemplate {
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+    // along with whether it is the session of this request, which can't be revoked from here
+    sessions: Vec<(UserSession, bool)>,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 200, description = "The active sessions of the user"))
+)]
+#[get("/sessions")]
+pub async fn list_sessions(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let sessions = list_active_sessions(*user_id, &pool)
+        .await
+        .map_err(e500)?
+        .into_iter()
+        .map(|s| {
+            let is_current = Some(s.session_id) == current_session_id;
+            (s, is_current)
+        })
+        .collect();
+
+    render(&SessionsTemplate {
+        nav,
+        flash_messages,
+        sessions,
+    })
+}
@@ -0,0 +1,5 @@
+pub(crate) mod get;
+pub(crate) mod post;
+
+pub use get::list_sessions;
+pub use post::{revoke_other_sessions, revoke_session};
@@ -0,0 +1,71 @@
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{self, UserId, revoke_sessions},
+    session_state::TypedSession,
+    utils::{client_ip, e500, see_other},
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = RevokeSessionForm)]
+pub struct FormData {
+    session_id: Uuid,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 303, description = "Redirects to the sessions with a flash message"))
+)]
+#[tracing::instrument(name = "Revoke a session", skip(form, request, pool), fields(user_id=%*user_id))]
+#[post("/sessions/revoke")]
+pub async fn revoke_session(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    // users can only revoke their own sessions
+    authentication::revoke_session(*user_id, form.session_id, &pool)
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::SessionRevoked)
+        .target(form.session_id)
+        .ip_address(client_ip(&request))
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("The session has been revoked.").send();
+    Ok(see_other("/admin/sessions"))
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 303, description = "Redirects to the sessions with a flash message"))
+)]
+#[tracing::instrument(name = "Revoke other sessions", skip(request, pool, session), fields(user_id=%*user_id))]
+#[post("/sessions/revoke-others")]
+pub async fn revoke_other_sessions(
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let session_id = session.get_session_id().map_err(e500)?;
+    revoke_sessions(*user_id, session_id, pool.get_ref())
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::SessionRevoked)
+        .target("all other sessions")
+        .ip_address(client_ip(&request))
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("All your other sessions have been revoked.").send();
+    Ok(see_other("/admin/sessions"))
+}
@@ -0,0 +1,36 @@
+use actix_web::{HttpResponse, get, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::is_two_factor_required,
+    templates::{AdminNav, render},
+    utils::e500,
+};
+
+#[derive(Template)]
+#[template(path = "admin/settings.html")]
+struct SettingsTemplate {
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+    two_factor_required: bool,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 200, description = "The settings, requires an owner"))
+)]
+#[get("")]
+pub async fn settings_form(
+    pool: web::Data<PgPool>,
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let two_factor_required = is_two_factor_required(&pool).await.map_err(e500)?;
+    render(&SettingsTemplate {
+        nav,
+        flash_messages,
+        two_factor_required,
+    })
+}
@@ -0,0 +1,5 @@
+pub(crate) mod get;
+pub(crate) mod post;
+
+pub use get::settings_form;
+pub use post::update_settings;
@@ -0,0 +1,43 @@
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{UserId, set_two_factor_required},
+    utils::{client_ip, e500, see_other},
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = SettingsForm)]
+pub struct FormData {
+    // unchecked checkboxes are not submitted
+    two_factor_required: Option<String>,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 303, description = "Redirects to the settings with a flash message, requires an owner"))
+)]
+#[tracing::instrument(name = "Update settings", skip(form, request, pool), fields(user_id=%*user_id))]
+#[post("")]
+pub async fn update_settings(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let two_factor_required = form.two_factor_required.is_some();
+    set_two_factor_required(two_factor_required, &pool)
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::SettingsUpdated)
+        .ip_address(client_ip(&request))
+        .details(format!("two_factor_required={}", two_factor_required))
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("The settings have been saved.").send();
+    Ok(see_other("/admin/settings"))
+}
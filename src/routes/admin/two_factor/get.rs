@@ -0,0 +1,76 @@
+use actix_web::{HttpResponse, get, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{
+        UserId, generate_totp_secret, get_totp_secret, is_two_factor_required, totp_qr_code_svg,
+        totp_uri,
+    },
+    routes::admin::dashboard::get_username,
+    session_state::TypedSession,
+    templates::{AdminNav, render},
+    utils::e500,
+};
+
+// Shown until the user has enrolled
+struct Enrollment {
+    uri: String,
+    // SVG markup generated from the URI
+    qr_code: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/two_factor.html")]
+struct TwoFactorTemplate {
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+    required: bool,
+    enrollment: Option<Enrollment>,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 200, description = "The two-factor enrollment form"))
+)]
+#[get("/two-factor")]
+pub async fn two_factor_form(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let required = is_two_factor_required(&pool).await.map_err(e500)?;
+    let enrolled = get_totp_secret(*user_id, &pool)
+        .await
+        .map_err(e500)?
+        .is_some();
+
+    let enrollment = if enrolled {
+        None
+    } else {
+        // the secret is only stored once the user proved their app generates valid codes
+        let secret = match session.get_pending_totp_secret().map_err(e500)? {
+            Some(secret) => secret,
+            None => {
+                let secret = generate_totp_secret();
+                session.insert_pending_totp_secret(&secret).map_err(e500)?;
+                secret
+            }
+        };
+        let username = get_username(*user_id, &pool).await.map_err(e500)?;
+        let uri = totp_uri(&secret, &username).map_err(e500)?;
+        let qr_code = totp_qr_code_svg(&uri).map_err(e500)?;
+        Some(Enrollment { uri, qr_code })
+    };
+
+    render(&TwoFactorTemplate {
+        nav,
+        flash_messages,
+        required,
+        enrollment,
+    })
+}
@@ -0,0 +1,5 @@
+pub(crate) mod get;
+pub(crate) mod post;
+
+pub use get::two_factor_form;
+pub use post::{disable_two_factor, enable_two_factor};
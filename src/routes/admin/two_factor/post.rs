@@ -0,0 +1,149 @@
+use actix_web::{FromRequest, HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{
+        self, UserId, get_totp_secret, is_two_factor_required, totp_code_step, use_recovery_code,
+        verify_totp_code,
+    },
+    session_state::TypedSession,
+    startup::HmacSecret,
+    templates::{AdminNav, render},
+    utils::{client_ip, e500, see_other},
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = TwoFactorForm)]
+pub struct FormData {
+    code: String,
+    // to replace the secret of a user who is already enrolled: a code of the current secret,
+    // or a recovery code
+    #[serde(default)]
+    current_code: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/recovery_codes.html")]
+struct RecoveryCodesTemplate {
+    nav: AdminNav,
+    flash_messages: IncomingFlashMessages,
+    recovery_codes: Vec<String>,
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses(
+        (status = 200, description = "The recovery codes, shown once"),
+        (status = 303, description = "Redirects to the form when the code is invalid"),
+    )
+)]
+#[tracing::instrument(
+    name = "Enroll in two-factor authentication",
+    skip(form, request, pool, session, hmac_secret, nav),
+    fields(user_id=%*user_id)
+)]
+#[post("/two-factor")]
+pub async fn enable_two_factor(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    hmac_secret: web::Data<HmacSecret>,
+    nav: AdminNav,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let Some(secret) = session.get_pending_totp_secret().map_err(e500)? else {
+        return Ok(see_other("/admin/two-factor"));
+    };
+
+    let Some(code_step) = totp_code_step(&secret, &form.code).map_err(e500)? else {
+        FlashMessage::error("Invalid authentication code.").send();
+        return Ok(see_other("/admin/two-factor"));
+    };
+
+    // checked last, as a code is used up once verified
+    if let Some(current_secret) = get_totp_secret(*user_id, &pool).await.map_err(e500)? {
+        let verified = verify_totp_code(*user_id, &current_secret, &form.current_code, &pool)
+            .await
+            .map_err(e500)?
+            || use_recovery_code(*user_id, &form.current_code, &hmac_secret.0, &pool)
+                .await
+                .map_err(e500)?;
+        if !verified {
+            FlashMessage::error(
+                "Two-factor authentication is already enabled, \
+                enter a code of your current authenticator app or a recovery code to replace it.",
+            )
+            .send();
+            return Ok(see_other("/admin/two-factor"));
+        }
+    }
+
+    let recovery_codes =
+        authentication::enable_two_factor(*user_id, &secret, code_step, &hmac_secret.0, &pool)
+            .await
+            .map_err(e500)?;
+    session.remove_pending_totp_secret();
+    AuditEvent::new(Actor::User(*user_id), AuditAction::TwoFactorEnabled)
+        .ip_address(client_ip(&request))
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
+
+    // only signatures of the recovery codes are stored: this is the only time they are shown
+    let flash_messages = IncomingFlashMessages::extract(&request).await?;
+    render(&RecoveryCodesTemplate {
+        nav,
+        flash_messages,
+        recovery_codes,
+    })
+}
+
+#[utoipa::path(
+    tag = "admin", security(("session" = [])),
+    responses((status = 303, description = "Redirects to the form with a flash message"))
+)]
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip(form, request, pool),
+    fields(user_id=%*user_id)
+)]
+#[post("/two-factor/disable")]
+pub async fn disable_two_factor(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    if is_two_factor_required(&pool).await.map_err(e500)? {
+        FlashMessage::error("Two-factor authentication is required for all users.").send();
+        return Ok(see_other("/admin/two-factor"));
+    }
+    let Some(secret) = get_totp_secret(*user_id, &pool).await.map_err(e500)? else {
+        return Ok(see_other("/admin/two-factor"));
+    };
+    if !verify_totp_code(*user_id, &secret, &form.code, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("Invalid authentication code.").send();
+        return Ok(see_other("/admin/two-factor"));
+    }
+
+    authentication::disable_two_factor(*user_id, &pool)
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::TwoFactorDisabled)
+        .ip_address(client_ip(&request))
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/two-factor"))
+}
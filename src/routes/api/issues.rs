@@ -0,0 +1,287 @@
+use actix_web::{HttpRequest, HttpResponse, get, http::header::LOCATION, post, web};
+use actix_web_lab::middleware::from_fn;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::{
+    ApiError, ErrorBody, IdempotentEndpoint, Processing, finish_processing, start_processing,
+};
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{UserId, reject_viewers},
+    routes::enqueue_delivery_tasks,
+    utils::client_ip,
+};
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueStatus {
+    Draft,
+    // published, some subscribers haven't been sent the issue yet
+    Delivering,
+    Delivered,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Issue {
+    pub issue_id: Uuid,
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub status: IssueStatus,
+    pub published_at: Option<DateTime<Utc>>,
+    pub pending_deliveries: i64,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+pub struct NewIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+// Issues are created as drafts, subscribers receive them once they are published
+#[utoipa::path(
+    tag = "api", security(("api_token" = [])),
+    params(("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the response of the first request")),
+    responses(
+        (status = 201, description = "The draft, its URL is in the Location header", body = Issue),
+        (status = 400, description = "Invalid issue", body = ErrorBody),
+        (status = 422, description = "The idempotency key has already been used for another request", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Create a newsletter issue",
+    skip(body, request, pool),
+    fields(user_id=%*user_id)
+)]
+#[post("/issues", wrap = "from_fn(reject_viewers)")]
+pub async fn create_issue(
+    body: web::Json<NewIssue>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
+    let body = body.into_inner();
+    if body.title.trim().is_empty() {
+        return Err(ApiError::ValidationError(
+            "The title of an issue cannot be empty.".into(),
+        ));
+    }
+
+    let (mut transaction, idempotency_key) = match start_processing(
+        &request,
+        IdempotentEndpoint::CreateIssue,
+        &body,
+        *user_id,
+        &pool,
+    )
+    .await?
+    {
+        Processing::Start(transaction, idempotency_key) => (transaction, idempotency_key),
+        Processing::Replay(saved_response) => return Ok(saved_response),
+    };
+    let NewIssue {
+        title,
+        text_content,
+        html_content,
+    } = body;
+
+    let issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, NULL)
+        "#,
+        issue_id,
+        title,
+        text_content,
+        html_content
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store newsletter issue details")?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::NewsletterCreated)
+        .target(issue_id)
+        .ip_address(client_ip(&request))
+        .details(&title)
+        .record(&mut *transaction)
+        .await?;
+
+    let issue = fetch_issue(issue_id, &mut *transaction)
+        .await?
+        .context("The issue we just created is missing")?;
+    let response = HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/v1/issues/{}", issue_id)))
+        .json(issue);
+    finish_processing(
+        transaction,
+        IdempotentEndpoint::CreateIssue,
+        idempotency_key,
+        *user_id,
+        response,
+    )
+    .await
+}
+
+#[utoipa::path(
+    tag = "api", security(("api_token" = [])),
+    responses(
+        (status = 200, description = "The issue", body = Issue),
+        (status = 404, description = "Unknown issue", body = ErrorBody),
+    )
+)]
+#[get("/issues/{issue_id}")]
+pub async fn get_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let issue = fetch_issue(*issue_id, pool.get_ref())
+        .await?
+        .ok_or_else(issue_not_found)?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+#[utoipa::path(
+    tag = "api", security(("api_token" = [])),
+    params(("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the response of the first request")),
+    responses(
+        (status = 202, description = "The issue is being delivered to the confirmed subscribers", body = Issue),
+        (status = 404, description = "Unknown issue", body = ErrorBody),
+        (status = 409, description = "The issue has already been published", body = ErrorBody),
+        (status = 422, description = "The idempotency key has already been used for another request", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(request, pool),
+    fields(user_id=%*user_id)
+)]
+#[post("/issues/{issue_id}/publish", wrap = "from_fn(reject_viewers)")]
+pub async fn publish_issue(
+    issue_id: web::Path<Uuid>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
+    let issue_id = issue_id.into_inner();
+    let (mut transaction, idempotency_key) = match start_processing(
+        &request,
+        IdempotentEndpoint::PublishIssue,
+        &(),
+        *user_id,
+        &pool,
+    )
+    .await?
+    {
+        Processing::Start(transaction, idempotency_key) => (transaction, idempotency_key),
+        Processing::Replay(saved_response) => return Ok(saved_response),
+    };
+
+    // the row is locked until the transaction ends: an issue can't be published twice
+    let published = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET published_at = now()
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        RETURNING title
+        "#,
+        issue_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to publish a newsletter issue")?;
+    let title = match published {
+        Some(r) => r.title,
+        None => {
+            return match fetch_issue(issue_id, &mut *transaction).await? {
+                Some(_) => Err(ApiError::Conflict(
+                    "The issue has already been published.".into(),
+                )),
+                None => Err(issue_not_found()),
+            };
+        }
+    };
+
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::NewsletterPublished)
+        .target(issue_id)
+        .ip_address(client_ip(&request))
+        .details(&title)
+        .record(&mut *transaction)
+        .await?;
+
+    let issue = fetch_issue(issue_id, &mut *transaction)
+        .await?
+        .context("The issue we just published is missing")?;
+    let response = HttpResponse::Accepted().json(issue);
+    finish_processing(
+        transaction,
+        IdempotentEndpoint::PublishIssue,
+        idempotency_key,
+        *user_id,
+        response,
+    )
+    .await
+}
+
+fn issue_not_found() -> ApiError {
+    ApiError::NotFound("There is no issue with this id.".into())
+}
+
+#[tracing::instrument(skip(executor))]
+async fn fetch_issue(
+    issue_id: Uuid,
+    executor: impl PgExecutor<'_>,
+) -> Result<Option<Issue>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            published_at,
+            (
+                SELECT COUNT(*)
+                FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            ) as "pending_deliveries!"
+        FROM newsletter_issues i
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to perform a query to retrieve a newsletter issue.")?;
+
+    Ok(row.map(|r| {
+        let status = match (r.published_at, r.pending_deliveries) {
+            (None, _) => IssueStatus::Draft,
+            (Some(_), 0) => IssueStatus::Delivered,
+            (Some(_), _) => IssueStatus::Delivering,
+        };
+        Issue {
+            issue_id: r.newsletter_issue_id,
+            title: r.title,
+            text_content: r.text_content,
+            html_content: r.html_content,
+            status,
+            published_at: r.published_at,
+            pending_deliveries: r.pending_deliveries,
+        }
+    }))
+}
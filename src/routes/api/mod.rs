@@ -0,0 +1,139 @@
+pub(crate) mod issues;
+pub(crate) mod stats;
+pub(crate) mod subscribers;
+
+pub use issues::{create_issue, get_issue, publish_issue};
+pub use stats::get_stats;
+pub use subscribers::{
+    create_subscriber, delete_subscriber, find_subscriber, get_subscriber, update_subscriber,
+};
+
+use actix_web::{HttpRequest, HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    idempotency::{
+        IdempotencyKey, IdempotencyScope, IdempotentEndpoint, NextAction, RequestFingerprint,
+        save_response, try_processing,
+    },
+    routes::error_chain_fmt,
+};
+
+// Errors of the JSON API are reported as `{"error": "<message>"}`
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ErrorBody {
+    error: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    UnprocessableEntity(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::UnprocessableEntity(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        // the causes of unexpected errors are logged, not leaked to the client
+        let error = match self {
+            ApiError::UnexpectedError(_) => "An unexpected error occurred.".to_string(),
+            e => e.to_string(),
+        };
+        HttpResponse::build(self.status_code()).json(ErrorBody { error })
+    }
+}
+
+// Malformed bodies are reported like the other errors of the API
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|e, _| ApiError::ValidationError(e.to_string()).into())
+}
+
+enum Processing {
+    Start(Transaction<'static, Postgres>, Option<IdempotencyKey>),
+    Replay(HttpResponse),
+}
+
+// Requests with an `Idempotency-Key` header are processed once per key and user,
+// retries get the response saved for the first request and other requests reusing the key a 422
+async fn start_processing(
+    request: &HttpRequest,
+    endpoint: IdempotentEndpoint,
+    payload: &impl serde::Serialize,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Processing, ApiError> {
+    let idempotency_key = IdempotencyKey::from_header(request)
+        .map_err(|e| ApiError::ValidationError(e.to_string()))?;
+
+    match idempotency_key {
+        Some(idempotency_key) => {
+            let fingerprint = RequestFingerprint::new(request, payload)?;
+            let scope = IdempotencyScope::User(user_id);
+            match try_processing(pool, endpoint, &idempotency_key, &fingerprint, &scope).await? {
+                NextAction::StartProcessing(transaction) => {
+                    Ok(Processing::Start(transaction, Some(idempotency_key)))
+                }
+                NextAction::ReturnSavedResponse(saved_response) => {
+                    Ok(Processing::Replay(saved_response))
+                }
+                NextAction::RejectReusedKey => Err(ApiError::UnprocessableEntity(
+                    "The idempotency key has already been used for another request.".into(),
+                )),
+            }
+        }
+        None => {
+            let transaction = pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")?;
+            Ok(Processing::Start(transaction, None))
+        }
+    }
+}
+
+async fn finish_processing(
+    transaction: Transaction<'static, Postgres>,
+    endpoint: IdempotentEndpoint,
+    idempotency_key: Option<IdempotencyKey>,
+    user_id: Uuid,
+    response: HttpResponse,
+) -> Result<HttpResponse, ApiError> {
+    match idempotency_key {
+        Some(idempotency_key) => {
+            let scope = IdempotencyScope::User(user_id);
+            Ok(save_response(transaction, endpoint, &idempotency_key, &scope, response).await?)
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction.")?;
+            Ok(response)
+        }
+    }
+}
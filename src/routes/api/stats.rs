@@ -0,0 +1,64 @@
+use actix_web::{HttpResponse, get, web};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::ApiError;
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Stats {
+    subscribers: SubscriberStats,
+    issues: IssueStats,
+    pending_deliveries: i64,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct SubscriberStats {
+    confirmed: i64,
+    pending_confirmation: i64,
+    unsubscribed: i64,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct IssueStats {
+    drafts: i64,
+    published: i64,
+}
+
+#[utoipa::path(
+    tag = "api", security(("api_token" = [])),
+    responses(
+        (status = 200, description = "Counts of subscribers, issues and pending deliveries", body = Stats),
+    )
+)]
+#[get("/stats")]
+pub async fn get_stats(pool: web::Data<PgPool>) -> Result<HttpResponse, ApiError> {
+    let r = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions WHERE status = 'confirmed') as "confirmed!",
+            (SELECT COUNT(*) FROM subscriptions WHERE status = 'pending_confirmation')
+                as "pending_confirmation!",
+            (SELECT COUNT(*) FROM subscriptions WHERE status = 'unsubscribed') as "unsubscribed!",
+            (SELECT COUNT(*) FROM newsletter_issues WHERE published_at IS NULL) as "drafts!",
+            (SELECT COUNT(*) FROM newsletter_issues WHERE published_at IS NOT NULL)
+                as "published!",
+            (SELECT COUNT(*) FROM issue_delivery_queue) as "pending_deliveries!"
+        "#
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to perform a query to compute the stats.")?;
+
+    Ok(HttpResponse::Ok().json(Stats {
+        subscribers: SubscriberStats {
+            confirmed: r.confirmed,
+            pending_confirmation: r.pending_confirmation,
+            unsubscribed: r.unsubscribed,
+        },
+        issues: IssueStats {
+            drafts: r.drafts,
+            published: r.published,
+        },
+        pending_deliveries: r.pending_deliveries,
+    }))
+}
@@ -0,0 +1,374 @@
+use actix_web::{HttpRequest, HttpResponse, delete, get, http::header::LOCATION, patch, post, web};
+use actix_web_lab::middleware::from_fn;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::{
+    ApiError, ErrorBody, IdempotentEndpoint, Processing, finish_processing, start_processing,
+};
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{UserId, reject_viewers},
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    email_client::EmailClient,
+    email_layout::get_email_layout,
+    routes::{
+        generate_subscription_token, insert_subscriber, send_confirmation_email, store_token,
+    },
+    startup::ApplicationBaseUrl,
+    utils::client_ip,
+};
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Subscriber {
+    pub subscriber_id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+#[schema(as = NewSubscriber)]
+pub struct NewSubscriberBody {
+    email: String,
+    name: String,
+    // subscribers who already opted in elsewhere don't get a confirmation email
+    #[serde(default)]
+    confirmed: bool,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct SubscriberUpdate {
+    name: Option<String>,
+    status: Option<String>,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+pub struct QueryParams {
+    email: String,
+}
+
+#[utoipa::path(
+    tag = "api", security(("api_token" = [])),
+    params(("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the response of the first request")),
+    responses(
+        (status = 201, description = "The subscriber, its URL is in the Location header", body = Subscriber),
+        (status = 400, description = "Invalid name or email address", body = ErrorBody),
+        (status = 409, description = "The email address is already subscribed", body = ErrorBody),
+        (status = 422, description = "The idempotency key has already been used for another request", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Create a subscriber",
+    skip(body, request, pool, email_client, base_url),
+    fields(user_id=%*user_id, subscriber_email=%body.email)
+)]
+#[post("/subscribers", wrap = "from_fn(reject_viewers)")]
+pub async fn create_subscriber(
+    body: web::Json<NewSubscriberBody>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
+    let body = body.into_inner();
+    let new_subscriber = NewSubscriber {
+        email: SubscriberEmail::parse(body.email.clone()).map_err(ApiError::ValidationError)?,
+        name: SubscriberName::parse(body.name.clone()).map_err(ApiError::ValidationError)?,
+    };
+
+    // read before the transaction begins, a request only holds one connection at a time
+    let layout = get_email_layout(&pool).await?;
+
+    let (mut transaction, idempotency_key) = match start_processing(
+        &request,
+        IdempotentEndpoint::CreateSubscriber,
+        &body,
+        *user_id,
+        &pool,
+    )
+    .await?
+    {
+        Processing::Start(transaction, idempotency_key) => (transaction, idempotency_key),
+        Processing::Replay(saved_response) => return Ok(saved_response),
+    };
+
+    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber).await {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e)
+            if e.as_database_error()
+                .is_some_and(|e| e.is_unique_violation()) =>
+        {
+            return Err(ApiError::Conflict(
+                "There is already a subscriber with this email address.".into(),
+            ));
+        }
+        Err(e) => {
+            return Err(anyhow::Error::new(e)
+                .context("Failed to insert new subscriber in the database.")
+                .into());
+        }
+    };
+    let subscription_token = if body.confirmed {
+        sqlx::query!(
+            "UPDATE subscriptions SET status = 'confirmed' WHERE id = $1",
+            subscriber_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to confirm a new subscriber.")?;
+        None
+    } else {
+        let subscription_token = generate_subscription_token();
+        store_token(&mut transaction, subscriber_id, &subscription_token)
+            .await
+            .context("Failed to store the confirmation token for a new subcriber.")?;
+        Some(subscription_token)
+    };
+    AuditEvent::new(Actor::User(*user_id), AuditAction::SubscriberCreated)
+        .target(subscriber_id)
+        .ip_address(client_ip(&request))
+        .record(&mut *transaction)
+        .await?;
+
+    let subscriber = fetch_subscriber(subscriber_id, &mut *transaction)
+        .await?
+        .context("The subscriber we just created is missing")?;
+
+    // sent before the response is saved: if it fails, nothing is committed and a retry with the
+    // same key creates the subscriber again
+    if let Some(subscription_token) = subscription_token {
+        send_confirmation_email(
+            &email_client,
+            &layout,
+            new_subscriber,
+            &base_url.0,
+            &subscription_token,
+        )
+        .await
+        .context("Failed to send a confirmation email.")?;
+    }
+
+    let response = HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/v1/subscribers/{}", subscriber_id)))
+        .json(subscriber);
+    let response = finish_processing(
+        transaction,
+        IdempotentEndpoint::CreateSubscriber,
+        idempotency_key,
+        *user_id,
+        response,
+    )
+    .await?;
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "api", security(("api_token" = [])),
+    responses(
+        (status = 200, description = "The subscriber", body = Subscriber),
+        (status = 404, description = "Unknown subscriber", body = ErrorBody),
+    )
+)]
+#[get("/subscribers/{subscriber_id}")]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber = fetch_subscriber(*subscriber_id, pool.get_ref())
+        .await?
+        .ok_or_else(subscriber_not_found)?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+// Looks a subscriber up by email address
+#[utoipa::path(
+    tag = "api", security(("api_token" = [])),
+    responses(
+        (status = 200, description = "The subscriber with this email address", body = Subscriber),
+        (status = 404, description = "Unknown subscriber", body = ErrorBody),
+    )
+)]
+#[get("/subscribers")]
+pub async fn find_subscriber(
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id as subscriber_id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE email = $1
+        "#,
+        query.email,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to perform a query to look a subscriber up.")?
+    .ok_or_else(subscriber_not_found)?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[utoipa::path(
+    tag = "api", security(("api_token" = [])),
+    responses(
+        (status = 200, description = "The updated subscriber", body = Subscriber),
+        (status = 400, description = "Invalid name or status", body = ErrorBody),
+        (status = 404, description = "Unknown subscriber", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Update a subscriber",
+    skip(body, request, pool),
+    fields(user_id=%*user_id)
+)]
+#[patch("/subscribers/{subscriber_id}", wrap = "from_fn(reject_viewers)")]
+pub async fn update_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<SubscriberUpdate>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
+    let SubscriberUpdate { name, status } = body.0;
+    let name = name
+        .map(SubscriberName::parse)
+        .transpose()
+        .map_err(ApiError::ValidationError)?;
+    // subscribers go back to pending confirmation by subscribing again, not through the API
+    if let Some(status) = &status
+        && !["confirmed", "unsubscribed"].contains(&status.as_str())
+    {
+        return Err(ApiError::ValidationError(format!(
+            "{} is not a valid status, use confirmed or unsubscribed.",
+            status
+        )));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        UPDATE subscriptions
+        SET name = COALESCE($2, name), status = COALESCE($3, status)
+        WHERE id = $1
+        RETURNING id as subscriber_id, email, name, status, subscribed_at
+        "#,
+        *subscriber_id,
+        name.as_ref().map(|n| n.as_ref()),
+        status,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to update a subscriber.")?
+    .ok_or_else(subscriber_not_found)?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::SubscriberUpdated)
+        .target(subscriber.subscriber_id)
+        .ip_address(client_ip(&request))
+        .details(format!("status={}", subscriber.status))
+        .record(&mut *transaction)
+        .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a subscriber.")?;
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[utoipa::path(
+    tag = "api", security(("api_token" = [])),
+    responses(
+        (status = 204, description = "The subscriber and its pending deliveries are deleted"),
+        (status = 404, description = "Unknown subscriber", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Delete a subscriber",
+    skip(request, pool),
+    fields(user_id=%*user_id)
+)]
+#[delete("/subscribers/{subscriber_id}", wrap = "from_fn(reject_viewers)")]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the tokens of a subscriber.")?;
+    let email = sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = $1 RETURNING email",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to delete a subscriber.")?
+    .ok_or_else(subscriber_not_found)?
+    .email;
+    // issues still waiting to be delivered to them
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE subscriber_email = $1",
+        email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the pending deliveries of a subscriber.")?;
+    AuditEvent::new(Actor::User(*user_id), AuditAction::SubscriberDeleted)
+        .target(subscriber_id)
+        .ip_address(client_ip(&request))
+        .record(&mut *transaction)
+        .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a subscriber.")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn subscriber_not_found() -> ApiError {
+    ApiError::NotFound("There is no such subscriber.".into())
+}
+
+#[tracing::instrument(skip(executor))]
+async fn fetch_subscriber(
+    subscriber_id: Uuid,
+    executor: impl PgExecutor<'_>,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id as subscriber_id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to perform a query to retrieve a subscriber.")?;
+    Ok(subscriber)
+}
@@ -0,0 +1,22 @@
+use actix_web::{HttpResponse, get};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+use crate::templates::render;
+
+#[derive(Template)]
+#[template(path = "forgot_password.html")]
+struct ForgotPasswordTemplate {
+    flash_messages: IncomingFlashMessages,
+}
+
+#[utoipa::path(
+    tag = "authentication",
+    responses((status = 200, description = "The form asking for a username"))
+)]
+#[get("/forgot-password")]
+pub async fn forgot_password_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&ForgotPasswordTemplate { flash_messages })
+}
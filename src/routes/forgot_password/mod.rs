@@ -0,0 +1,5 @@
+pub(crate) mod get;
+pub(crate) mod post;
+
+pub use get::forgot_password_form;
+pub use post::forgot_password;
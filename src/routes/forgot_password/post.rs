@@ -0,0 +1,216 @@
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{LoginThrottle, ThrottleDecision, generate_token, sign_token},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    email_layout::{EmailLayout, get_email_layout},
+    startup::{ApplicationBaseUrl, HmacSecret},
+    utils::{client_ip, see_other},
+};
+
+// reset links are only valid for a short while, in case the mailbox is compromised later on
+const PASSWORD_RESET_TTL: chrono::TimeDelta = chrono::TimeDelta::minutes(30);
+// no other link is sent while the last one is this recent and unused, so that requests can't
+// flood the mailbox of a user
+const PASSWORD_RESET_COOLDOWN: chrono::TimeDelta = chrono::TimeDelta::minutes(5);
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = ForgotPasswordForm)]
+pub struct FormData {
+    username: String,
+}
+
+#[utoipa::path(
+    tag = "authentication",
+    responses((status = 303, description = "Redirects to the login form, whether the username exists or not"))
+)]
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, request, throttle, pool, email_client, base_url, hmac_secret),
+    fields(username=%form.username)
+)]
+#[post("/forgot-password")]
+pub async fn forgot_password(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    throttle: web::Data<LoginThrottle>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    // The response must not reveal whether the username exists: it doesn't wait for the lookup,
+    // nor for the email, and their failures are only logged
+    let username = form.into_inner().username;
+    // counted like a failed login, per username and per IP address, whether the username exists
+    // or not: past the limits, nothing is looked up nor sent
+    match throttle
+        .reserve_attempt(&username, &client_ip(&request))
+        .await
+    {
+        Ok(ThrottleDecision::Allowed { .. }) => {
+            tokio::spawn(
+                async move {
+                    let _ = send_password_reset_link(
+                        &username,
+                        &pool,
+                        &email_client,
+                        &base_url.0,
+                        &hmac_secret,
+                    )
+                    .await;
+                }
+                .in_current_span(),
+            );
+        }
+        Ok(ThrottleDecision::LockedOut { .. }) => {
+            tracing::warn!("Too many password reset requests, no link is sent")
+        }
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to throttle a password reset request")
+        }
+    }
+
+    FlashMessage::info(
+        "If this account has an email address, a link to reset its password has been sent to it.",
+    )
+    .send();
+    see_other("/login")
+}
+
+#[tracing::instrument(skip_all, err)]
+async fn send_password_reset_link(
+    username: &str,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+) -> Result<(), anyhow::Error> {
+    let Some((user_id, email)) = get_user_email(username, pool).await? else {
+        return Ok(());
+    };
+    let token = generate_token();
+    let token_signature = sign_token(token.expose_secret(), &hmac_secret.0);
+    if !store_password_reset_token(pool, user_id, &token_signature)
+        .await
+        .context("Failed to store the password reset token")?
+    {
+        tracing::info!("A recent password reset link is still unused, no other one is sent");
+        return Ok(());
+    }
+
+    let layout = get_email_layout(pool).await?;
+    send_password_reset_email(
+        email_client,
+        &layout,
+        &email,
+        base_url,
+        token.expose_secret(),
+    )
+    .await
+    .context("Failed to send a password reset email")
+}
+
+// Users without an email address (e.g. created from the CLI) can't reset their password
+#[tracing::instrument(skip(pool))]
+async fn get_user_email(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, SubscriberEmail)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, email as "email!"
+        FROM users
+        WHERE username = $1 AND email IS NOT NULL
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve the email of a user.")?;
+
+    row.map(|r| {
+        let email = SubscriberEmail::parse(r.email).map_err(anyhow::Error::msg)?;
+        Ok((r.user_id, email))
+    })
+    .transpose()
+}
+
+// false, and nothing is stored, when the user got a link within the cooldown which is still unused
+#[tracing::instrument(skip_all)]
+async fn store_password_reset_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_signature: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    // concurrent requests for the same user wait for each other, so that only one link is sent
+    sqlx::query!(
+        "SELECT user_id FROM users WHERE user_id = $1 FOR UPDATE",
+        user_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let now = Utc::now();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_signature, user_id, created_at, expires_at)
+        SELECT $1, $2, $3, $4
+        WHERE NOT EXISTS (
+            SELECT 1 FROM password_reset_tokens
+            WHERE user_id = $2 AND used_at IS NULL AND expires_at > $3 AND created_at > $5
+        )
+        "#,
+        token_signature,
+        user_id,
+        now,
+        now + PASSWORD_RESET_TTL,
+        now - PASSWORD_RESET_COOLDOWN,
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    transaction.commit().await?;
+
+    Ok(inserted > 0)
+}
+
+#[tracing::instrument(name = "Send a password reset email", skip_all)]
+async fn send_password_reset_email(
+    email_client: &EmailClient,
+    layout: &EmailLayout,
+    email: &SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let reset_link = format!("{}/reset-password?token={}", base_url, token);
+
+    let plain_body = format!(
+        "Visit {} to choose a new password. The link expires in {} minutes.\n\
+        If you didn't ask to reset your password, you can ignore this email.",
+        reset_link,
+        PASSWORD_RESET_TTL.num_minutes()
+    );
+
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to choose a new password. \
+        The link expires in {} minutes.<br />\
+        If you didn't ask to reset your password, you can ignore this email.",
+        reset_link,
+        PASSWORD_RESET_TTL.num_minutes()
+    );
+
+    let rendered = layout.render(&html_body, &plain_body, None)?;
+    email_client
+        .send_email(email, "Reset your password", &rendered.html, &rendered.text)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,212 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{HttpResponse, get, web};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    email_client::EmailClient,
+    health::{ReadinessProbe, WorkerKind, last_heartbeats},
+};
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyStatus {
+    Up,
+    Down,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct DependencyCheck {
+    status: DependencyStatus,
+    latency_ms: f64,
+    // the cause is logged rather than shown to anyone who can reach the probe
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Dependencies {
+    database: DependencyCheck,
+    // only when it stores the sessions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redis: Option<DependencyCheck>,
+    // only when `health.check_email_provider` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_provider: Option<DependencyCheck>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerStatus {
+    Alive,
+    Stale,
+    // never reported, or the heartbeats couldn't be read
+    Unknown,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct WorkerHeartbeat {
+    status: WorkerStatus,
+    last_heartbeat: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Workers {
+    delivery_worker: WorkerHeartbeat,
+    idempotency_cleaner: WorkerHeartbeat,
+    // only runs when the sessions are stored in Postgres
+    session_cleaner: WorkerHeartbeat,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessStatus {
+    Ready,
+    NotReady,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Readiness {
+    status: ReadinessStatus,
+    dependencies: Dependencies,
+    // workers may run elsewhere: they are reported, but don't make the API unready
+    workers: Workers,
+}
+
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, description = "The process is up, restart it otherwise"))
+)]
+#[get("/health/live")]
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "alive" }))
+}
+
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "The dependencies are up, the API can take traffic", body = Readiness),
+        (status = 503, description = "A dependency is down", body = Readiness),
+    )
+)]
+#[tracing::instrument(name = "Check readiness", skip_all)]
+#[get("/health/ready")]
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    probe: web::Data<ReadinessProbe>,
+) -> HttpResponse {
+    let timeout = probe.settings.timeout();
+    let database = check("database", timeout, async {
+        sqlx::query!("SELECT 1 as one")
+            .fetch_one(pool.get_ref())
+            .await
+    });
+    let redis = async {
+        match &probe.redis {
+            Some(redis) => Some(check("redis", timeout, ReadinessProbe::ping_redis(redis)).await),
+            None => None,
+        }
+    };
+    let email_provider = async {
+        match probe.settings.check_email_provider {
+            true => Some(
+                check(
+                    "email_provider",
+                    timeout,
+                    email_client.check_reachable(timeout),
+                )
+                .await,
+            ),
+            false => None,
+        }
+    };
+    let (database, redis, email_provider, workers) = tokio::join!(
+        database,
+        redis,
+        email_provider,
+        workers(&pool, timeout, probe.settings.heartbeat_max_age_seconds)
+    );
+
+    let dependencies = Dependencies {
+        database,
+        redis,
+        email_provider,
+    };
+    let ready = [
+        Some(&dependencies.database),
+        dependencies.redis.as_ref(),
+        dependencies.email_provider.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .all(|check| matches!(check.status, DependencyStatus::Up));
+    let readiness = Readiness {
+        status: match ready {
+            true => ReadinessStatus::Ready,
+            false => ReadinessStatus::NotReady,
+        },
+        dependencies,
+        workers,
+    };
+    match ready {
+        true => HttpResponse::Ok().json(readiness),
+        false => HttpResponse::ServiceUnavailable().json(readiness),
+    }
+}
+
+async fn check<T, E: std::fmt::Display>(
+    dependency: &str,
+    timeout: Duration,
+    probe: impl Future<Output = Result<T, E>>,
+) -> DependencyCheck {
+    let start = Instant::now();
+    let outcome = tokio::time::timeout(timeout, probe).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.;
+    let error = match outcome {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => {
+            tracing::warn!(error.message = %e, dependency, "A dependency is down");
+            Some("unavailable".to_string())
+        }
+        Err(_) => {
+            tracing::warn!(dependency, "A dependency check timed out");
+            Some(format!("timed out after {}ms", timeout.as_millis()))
+        }
+    };
+    DependencyCheck {
+        status: match error {
+            None => DependencyStatus::Up,
+            Some(_) => DependencyStatus::Down,
+        },
+        latency_ms,
+        error,
+    }
+}
+
+async fn workers(pool: &PgPool, timeout: Duration, max_age_seconds: u64) -> Workers {
+    let heartbeats = match tokio::time::timeout(timeout, last_heartbeats(pool)).await {
+        Ok(Ok(heartbeats)) => heartbeats,
+        _ => Default::default(),
+    };
+    let heartbeat = |worker: WorkerKind| {
+        let last_heartbeat = heartbeats.get(worker.as_str()).copied();
+        let status = match last_heartbeat {
+            None => WorkerStatus::Unknown,
+            Some(at) if (Utc::now() - at).num_seconds() > max_age_seconds as i64 => {
+                WorkerStatus::Stale
+            }
+            Some(_) => WorkerStatus::Alive,
+        };
+        WorkerHeartbeat {
+            status,
+            last_heartbeat,
+        }
+    };
+    Workers {
+        delivery_worker: heartbeat(WorkerKind::DeliveryWorker),
+        idempotency_cleaner: heartbeat(WorkerKind::IdempotencyCleaner),
+        session_cleaner: heartbeat(WorkerKind::SessionCleaner),
+    }
+}
@@ -1,5 +1,10 @@
-use actix_web::HttpResponse;
+use actix_web::{HttpResponse, get};
 
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, description = "The application is up"))
+)]
+#[get("/health_check")]
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
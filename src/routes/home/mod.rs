@@ -1,5 +1,20 @@
-use actix_web::HttpResponse;
+use actix_web::{HttpResponse, get};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
 
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok().body(include_str!("home.html"))
+use crate::templates::render;
+
+#[derive(Template)]
+#[template(path = "home.html")]
+struct HomeTemplate {
+    flash_messages: IncomingFlashMessages,
+}
+
+#[utoipa::path(
+    tag = "subscriptions",
+    responses((status = 200, description = "The home page, with a subscription form"))
+)]
+#[get("/")]
+pub async fn home(flash_messages: IncomingFlashMessages) -> Result<HttpResponse, actix_web::Error> {
+    render(&HomeTemplate { flash_messages })
 }
@@ -0,0 +1,5 @@
+pub(crate) mod get;
+pub(crate) mod post;
+
+pub use get::accept_invitation_form;
+pub use post::accept_invitation;
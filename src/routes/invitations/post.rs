@@ -0,0 +1,170 @@
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{CreateUserError, Role, check_new_password, insert_user, sign_token},
+    domain::SubscriberEmail,
+    startup::HmacSecret,
+    utils::{client_ip, e500, see_other},
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = AcceptInvitationForm)]
+pub struct FormData {
+    token: String,
+    username: String,
+    #[schema(value_type = String, format = Password)]
+    new_password: Secret<String>,
+    #[schema(value_type = String, format = Password)]
+    new_password_check: Secret<String>,
+}
+
+struct Invitation {
+    invitation_id: Uuid,
+    email: SubscriberEmail,
+    role: Role,
+}
+
+#[utoipa::path(
+    tag = "authentication",
+    responses((status = 303, description = "Redirects to the login form, or back to the form with an error"))
+)]
+#[tracing::instrument(
+    name = "Accept an invitation",
+    skip(form, request, pool, hmac_secret),
+    fields(username=%form.username)
+)]
+#[post("/invitations/accept")]
+pub async fn accept_invitation(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData {
+        token,
+        username,
+        new_password,
+        new_password_check,
+    } = form.0;
+    let form_location = format!(
+        "/invitations/accept?token={}",
+        urlencoding::Encoded::new(&token)
+    );
+
+    // same rules as when changing a password
+    if let Err(e) = check_new_password(&new_password, &new_password_check) {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&form_location));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let token_signature = sign_token(&token, &hmac_secret.0);
+    let invitation = match get_pending_invitation(&mut transaction, &token_signature)
+        .await
+        .map_err(e500)?
+    {
+        Some(invitation) => invitation,
+        None => {
+            FlashMessage::error("This invitation is invalid or has expired.").send();
+            return Ok(see_other("/login"));
+        }
+    };
+
+    let user_id = match insert_user(
+        &username,
+        new_password,
+        invitation.role,
+        Some(&invitation.email),
+        &mut transaction,
+    )
+    .await
+    {
+        Ok(user_id) => user_id,
+        Err(CreateUserError::ValidationError(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&form_location));
+        }
+        Err(e @ CreateUserError::UnexpectedError(_)) => return Err(e500(e)),
+    };
+
+    mark_invitation_as_accepted(&mut transaction, invitation.invitation_id)
+        .await
+        .context("Failed to mark the invitation as accepted")
+        .map_err(e500)?;
+    AuditEvent::new(Actor::User(user_id), AuditAction::UserCreated)
+        .target(&username)
+        .ip_address(client_ip(&request))
+        .details(format!("invited as {}", invitation.role))
+        .record(&mut *transaction)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to accept an invitation.")
+        .map_err(e500)?;
+
+    FlashMessage::info("Your account has been created, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+// Locks the invitation, so that it can't be accepted twice concurrently
+#[tracing::instrument(skip_all)]
+async fn get_pending_invitation(
+    transaction: &mut Transaction<'_, Postgres>,
+    token_signature: &str,
+) -> Result<Option<Invitation>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT invitation_id, email, role
+        FROM invitations
+        WHERE
+            token_signature = $1 AND
+            accepted_at IS NULL AND
+            expires_at > now()
+        FOR UPDATE
+        "#,
+        token_signature,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to perform a query to retrieve an invitation.")?;
+
+    row.map(|r| {
+        Ok(Invitation {
+            invitation_id: r.invitation_id,
+            email: SubscriberEmail::parse(r.email).map_err(anyhow::Error::msg)?,
+            role: Role::try_from(r.role).map_err(anyhow::Error::msg)?,
+        })
+    })
+    .transpose()
+}
+
+#[tracing::instrument(skip_all)]
+async fn mark_invitation_as_accepted(
+    transaction: &mut Transaction<'_, Postgres>,
+    invitation_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE invitations
+        SET accepted_at = now()
+        WHERE invitation_id = $1
+        "#,
+        invitation_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
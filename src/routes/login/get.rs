@@ -1,34 +1,22 @@
-use actix_web::{HttpResponse, http::header::ContentType};
+use actix_web::{HttpResponse, get};
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use askama::Template;
 
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut error_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
+use crate::templates::render;
 
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-<html lang="en">
-    <head>
-        <meta http-equiv="content-type" content="text/html; charset=utf-8">
-        <title>Login</title>
-    </head>
-    <body>
-        {error_html}
-        <form action="/login" method="post">
-            <label>Username
-                <input type="text" placeholder="Enter Username" name="username">
-            </label>
-            <label>Password
-                <input type="password" placeholder="Enter Password" name="password">
-            </label>
-            <button type="submit">Login</button>
-        </form>
-    </body>
-</html>"#
-        ))
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    flash_messages: IncomingFlashMessages,
+}
+
+#[utoipa::path(
+    tag = "authentication",
+    responses((status = 200, description = "The login form"))
+)]
+#[get("/login")]
+pub async fn login_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&LoginTemplate { flash_messages })
 }
@@ -1,4 +1,6 @@
-mod get;
-mod post;
+pub(crate) mod get;
+pub(crate) mod post;
+pub(crate) mod two_factor;
 pub use get::login_form;
 pub use post::login;
+pub use two_factor::*;
@@ -1,27 +1,43 @@
-use actix_web::{HttpResponse, error::InternalError, http::header::LOCATION, web};
+use actix_web::{
+    HttpRequest, HttpResponse, error::InternalError, http::header::LOCATION, post, web,
+};
 use actix_web_flash_messages::FlashMessage;
 use secrecy::Secret;
 use sqlx::PgPool;
 
 use crate::{
-    authentication::{AuthError, Credentials, validate_credentials},
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{
+        AuthError, Credentials, FailedLoginReason, LoginThrottle, ThrottleDecision,
+        get_totp_secret, record_failed_login, register_session, validate_credentials,
+    },
     routes::error_chain_fmt,
     session_state::TypedSession,
+    utils::{client_ip, user_agent},
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = LoginForm)]
 pub struct FormData {
     username: String,
+    #[schema(value_type = String, format = Password)]
     password: Secret<String>,
 }
 
+#[utoipa::path(
+    tag = "authentication",
+    responses((status = 303, description = "Redirects to the dashboard, to the second factor form or back to the login form with an error"))
+)]
 #[tracing::instrument(
-    skip(form, pool, session),
+    skip(form, request, pool, throttle, session),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
+#[post("/login")]
 pub async fn login(
     form: web::Form<FormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottle>,
     /*
         SessionMiddleware does all heavy lifting of checking for a session cookie in incoming requests,
         if it finds one, it loads the corresponding session state from the chosen storage backend eg: Redis
@@ -34,13 +50,62 @@ pub async fn login(
         password: form.0.password,
     };
     tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let username = credentials.username.clone();
+    let ip = client_ip(&request);
+
+    // reserved before the password is checked, so that locked out attempts don't cost an argon2
+    // verification
+    match throttle
+        .reserve_attempt(&username, &ip)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+    {
+        ThrottleDecision::LockedOut { retry_after } => {
+            record_failed_login(&username, &ip, FailedLoginReason::LockedOut, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            return Err(login_redirect(LoginError::TooManyAttempts(retry_after)));
+        }
+        ThrottleDecision::Allowed { delay } => tokio::time::sleep(delay).await,
+    }
 
     match validate_credentials(credentials, &pool).await {
         Ok(user_id) => {
             tracing::Span::current().record("user_id", tracing::field::display(&user_id));
-            session.renew(); // creates a new session key and map existing state to it
+            let totp_secret = get_totp_secret(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            // users who enrolled in two-factor authentication are only logged in
+            // once they entered a code from their authenticator app
+            if totp_secret.is_some() {
+                throttle
+                    .refund_attempt(&username, &ip)
+                    .await
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                session.renew();
+                session
+                    .insert_pending_two_factor_user_id(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(HttpResponse::SeeOther()
+                    .insert_header((LOCATION, "/login/two-factor"))
+                    .finish());
+            }
+
+            // failures are only forgiven once all the authentication steps succeeded
+            throttle
+                .reset(&username, &ip)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            let session_id = register_session(user_id, &ip, &user_agent(&request), &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            AuditEvent::new(Actor::User(user_id), AuditAction::Login)
+                .ip_address(&ip)
+                .record(pool.get_ref())
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             session
-                .insert_user_id(user_id)
+                .log_in(user_id, session_id)
                 .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
             Ok(HttpResponse::SeeOther()
                 .insert_header((LOCATION, "/admin/dashboard"))
@@ -48,7 +113,18 @@ pub async fn login(
         }
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                // the reserved attempt is kept as a failure
+                AuthError::InvalidCredentials(_) => {
+                    record_failed_login(
+                        &username,
+                        &ip,
+                        FailedLoginReason::InvalidCredentials,
+                        &pool,
+                    )
+                    .await
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                    LoginError::AuthError(e.into())
+                }
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
 
@@ -74,6 +150,11 @@ fn login_redirect(e: LoginError) -> InternalError<LoginError> {
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error(
+        "Too many failed login attempts, try again in {} minutes.",
+        .0.as_secs().div_ceil(60)
+    )]
+    TooManyAttempts(std::time::Duration),
     #[error("Something went wrong")]
     UnexpectedError(#[from] anyhow::Error),
 }
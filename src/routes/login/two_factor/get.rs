@@ -0,0 +1,39 @@
+use actix_web::{HttpResponse, get};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+use crate::{
+    session_state::TypedSession,
+    templates::render,
+    utils::{e500, see_other},
+};
+
+#[derive(Template)]
+#[template(path = "two_factor_login.html")]
+struct TwoFactorLoginTemplate {
+    flash_messages: IncomingFlashMessages,
+}
+
+#[utoipa::path(
+    tag = "authentication",
+    responses(
+        (status = 200, description = "The form asking for a two-factor code"),
+        (status = 303, description = "Redirects to the login form when no password has been checked yet"),
+    )
+)]
+#[get("/login/two-factor")]
+pub async fn two_factor_login_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    // only reachable after entering a valid username and password
+    if session
+        .get_pending_two_factor_user_id()
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(see_other("/login"));
+    }
+
+    render(&TwoFactorLoginTemplate { flash_messages })
+}
@@ -0,0 +1,105 @@
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{
+        FailedLoginReason, LoginThrottle, ThrottleDecision, get_totp_secret, record_failed_login,
+        register_session, use_recovery_code, verify_totp_code,
+    },
+    routes::get_username,
+    session_state::TypedSession,
+    startup::HmacSecret,
+    utils::{client_ip, e500, see_other, user_agent},
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = TwoFactorLoginForm)]
+pub struct FormData {
+    code: String,
+}
+
+#[utoipa::path(
+    tag = "authentication",
+    responses((status = 303, description = "Redirects to the dashboard, or back to a form with an error"))
+)]
+#[tracing::instrument(
+    skip(form, request, pool, throttle, session, hmac_secret),
+    fields(user_id=tracing::field::Empty)
+)]
+#[post("/login/two-factor")]
+pub async fn two_factor_login(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottle>,
+    session: TypedSession,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(user_id) = session.get_pending_two_factor_user_id().map_err(e500)? else {
+        return Ok(see_other("/login"));
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    // codes are only 6 digits: failures count against the same limits as wrong passwords
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    let ip = client_ip(&request);
+    match throttle
+        .reserve_attempt(&username, &ip)
+        .await
+        .map_err(e500)?
+    {
+        ThrottleDecision::LockedOut { retry_after } => {
+            record_failed_login(&username, &ip, FailedLoginReason::LockedOut, &pool)
+                .await
+                .map_err(e500)?;
+            session.remove_pending_two_factor_user_id();
+            FlashMessage::error(format!(
+                "Too many failed login attempts, try again in {} minutes.",
+                retry_after.as_secs().div_ceil(60)
+            ))
+            .send();
+            return Ok(see_other("/login"));
+        }
+        ThrottleDecision::Allowed { delay } => tokio::time::sleep(delay).await,
+    }
+
+    // two-factor authentication may have been disabled since the password was checked
+    let verified = match get_totp_secret(user_id, &pool).await.map_err(e500)? {
+        Some(secret) => {
+            verify_totp_code(user_id, &secret, &form.code, &pool)
+                .await
+                .map_err(e500)?
+                || use_recovery_code(user_id, &form.code, &hmac_secret.0, &pool)
+                    .await
+                    .map_err(e500)?
+        }
+        None => true,
+    };
+    // the reserved attempt is kept as a failure
+    if !verified {
+        record_failed_login(
+            &username,
+            &ip,
+            FailedLoginReason::InvalidTwoFactorCode,
+            &pool,
+        )
+        .await
+        .map_err(e500)?;
+        FlashMessage::error("Invalid authentication code.").send();
+        return Ok(see_other("/login/two-factor"));
+    }
+
+    throttle.reset(&username, &ip).await.map_err(e500)?;
+    let session_id = register_session(user_id, &ip, &user_agent(&request), &pool)
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(Actor::User(user_id), AuditAction::Login)
+        .ip_address(&ip)
+        .details("two-factor")
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
+    session.log_in(user_id, session_id).map_err(e500)?;
+    Ok(see_other("/admin/dashboard"))
+}
@@ -0,0 +1,26 @@
+use actix_web::{HttpResponse, get, web};
+use metrics_exporter_prometheus::PrometheusHandle;
+use sqlx::PgPool;
+
+use crate::prometheus::{ScrapedPool, record_pool_stats, record_queue_depth};
+
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain"))
+)]
+#[get("/metrics")]
+pub async fn metrics(
+    handle: web::Data<PrometheusHandle>,
+    pool: web::Data<PgPool>,
+    pool_name: web::Data<ScrapedPool>,
+) -> HttpResponse {
+    // a scrape still succeeds while the database is down, with the last known queue depth
+    if let Err(e) = record_queue_depth(&pool).await {
+        tracing::warn!(error.cause_chain = ?e, "Failed to measure the depth of the delivery queue");
+    }
+    record_pool_stats(pool_name.0, &pool);
+    handle.run_upkeep();
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(handle.render())
+}
@@ -1,15 +1,30 @@
 // declare submodules
 mod admin;
-mod health_check;
-mod home;
-mod login;
+mod api;
+pub(crate) mod forgot_password;
+mod health;
+pub(crate) mod health_check;
+pub(crate) mod home;
+mod invitations;
+pub(crate) mod login;
+pub(crate) mod metrics;
+mod openapi;
+pub(crate) mod reset_password;
 mod subscriptions;
 mod subscriptions_confirm;
+pub(crate) mod unsubscribe;
 
-// re-export public items from submodules to make them accessible from outside
+// re-export public items from submodules to make them accessible from outside.
+// The route macros turn the handlers into types: the ones named after their module are reached
+// through it
 pub use admin::*;
-pub use health_check::*;
-pub use home::*;
-pub use login::*;
+pub use api::*;
+pub use forgot_password::forgot_password_form;
+pub use health::*;
+pub use invitations::*;
+pub use login::{login_form, two_factor_login, two_factor_login_form};
+pub use openapi::*;
+pub use reset_password::reset_password_form;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use unsubscribe::unsubscribe_form;
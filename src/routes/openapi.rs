@@ -0,0 +1,48 @@
+use actix_web::{HttpResponse, get, web};
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme},
+};
+
+// Common parts of the description: the paths are collected from the handlers as `startup::run`
+// registers them, so only the routes that are served get documented
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "newsletter"),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "subscriptions", description = "Subscribing to the newsletter"),
+        (name = "authentication", description = "Logging in, passwords and invitations"),
+        (name = "admin", description = "Back office, HTML forms protected by a CSRF token"),
+        (name = "api", description = "JSON API for the tools of the editors"),
+        (name = "health", description = "Monitoring"),
+    )
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        // actix-session's default cookie name
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("id"))),
+        );
+        // tokens are created from /admin/api-tokens
+        components.add_security_scheme(
+            "api_token",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+#[utoipa::path(
+    tag = "api",
+    responses((status = 200, description = "This OpenAPI description"))
+)]
+#[get("/api/openapi.json")]
+pub async fn openapi_json(openapi: web::Data<utoipa::openapi::OpenApi>) -> HttpResponse {
+    HttpResponse::Ok().json(openapi.get_ref())
+}
@@ -0,0 +1,33 @@
+use actix_web::{HttpResponse, get, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+use crate::templates::render;
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+pub struct QueryParams {
+    token: String,
+}
+
+#[derive(Template)]
+#[template(path = "reset_password.html")]
+struct ResetPasswordTemplate {
+    flash_messages: IncomingFlashMessages,
+    // the token is validated on submission, it is only carried along by the form here
+    token: String,
+}
+
+#[utoipa::path(
+    tag = "authentication",
+    responses((status = 200, description = "The form asking for a new password"))
+)]
+#[get("/reset-password")]
+pub async fn reset_password_form(
+    query: web::Query<QueryParams>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&ResetPasswordTemplate {
+        flash_messages,
+        token: query.0.token,
+    })
+}
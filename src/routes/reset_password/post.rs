@@ -0,0 +1,144 @@
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    authentication::{change_password, check_new_password, revoke_sessions, sign_token},
+    startup::HmacSecret,
+    utils::{client_ip, e500, see_other},
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = ResetPasswordForm)]
+pub struct FormData {
+    token: String,
+    #[schema(value_type = String, format = Password)]
+    new_password: Secret<String>,
+    #[schema(value_type = String, format = Password)]
+    new_password_check: Secret<String>,
+}
+
+#[utoipa::path(
+    tag = "authentication",
+    responses((status = 303, description = "Redirects to the login form, or back to the form with an error"))
+)]
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(form, request, pool, hmac_secret),
+    fields(user_id=tracing::field::Empty)
+)]
+#[post("/reset-password")]
+pub async fn reset_password(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData {
+        token,
+        new_password,
+        new_password_check,
+    } = form.0;
+    let form_location = format!(
+        "/reset-password?token={}",
+        urlencoding::Encoded::new(&token)
+    );
+
+    // same rules as when changing a password
+    if let Err(e) = check_new_password(&new_password, &new_password_check) {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&form_location));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let token_signature = sign_token(&token, &hmac_secret.0);
+    let user_id = match get_pending_reset(&mut transaction, &token_signature)
+        .await
+        .map_err(e500)?
+    {
+        Some(user_id) => user_id,
+        None => {
+            FlashMessage::error("This password reset link is invalid or has expired.").send();
+            return Ok(see_other("/login"));
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    change_password(user_id, new_password, &mut *transaction)
+        .await
+        .map_err(e500)?;
+    // whoever knew the previous password must not stay logged in
+    revoke_sessions(user_id, None, &mut *transaction)
+        .await
+        .map_err(e500)?;
+    use_reset_tokens(&mut transaction, user_id)
+        .await
+        .context("Failed to mark the password reset tokens as used")
+        .map_err(e500)?;
+    AuditEvent::new(Actor::User(user_id), AuditAction::PasswordReset)
+        .ip_address(client_ip(&request))
+        .record(&mut *transaction)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")
+        .map_err(e500)?;
+
+    FlashMessage::info("Your password has been reset, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+// Locks the token, so that it can't be used twice concurrently
+#[tracing::instrument(skip_all)]
+async fn get_pending_reset(
+    transaction: &mut Transaction<'_, Postgres>,
+    token_signature: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id
+        FROM password_reset_tokens
+        WHERE
+            token_signature = $1 AND
+            used_at IS NULL AND
+            expires_at > now()
+        FOR UPDATE
+        "#,
+        token_signature,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to perform a query to retrieve a password reset token.")?;
+
+    Ok(row.map(|r| r.user_id))
+}
+
+// Any other link sent to the user is invalidated along with the one that was used
+#[tracing::instrument(skip_all)]
+async fn use_reset_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = now()
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
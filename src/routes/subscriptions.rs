@@ -1,20 +1,37 @@
-use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use actix_web::{HttpRequest, HttpResponse, ResponseError, http::StatusCode, post, web};
 use anyhow::Context;
 use chrono::Utc;
 use rand::{Rng, distributions::Alphanumeric, thread_rng};
 use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::BTreeMap;
+use utoipa::{
+    IntoResponses,
+    openapi::{RefOr, response::Response},
+};
 use uuid::Uuid;
 
 use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    configuration::IdempotencySettings,
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
     email_client::EmailClient,
+    email_layout::{EmailLayout, get_email_layout},
+    idempotency::{
+        ClientFingerprint, IdempotencyKey, IdempotencyScope, IdempotentEndpoint, NextAction,
+        RequestFingerprint, save_response, try_processing_within_quota,
+    },
     startup::ApplicationBaseUrl,
+    utils::client_ip,
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = SubscribeForm)]
 pub struct FormData {
     email: String,
     name: String,
+    // forms may send the key in a hidden field, other clients in the `Idempotency-Key` header
+    #[serde(default)]
+    idempotency_key: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -27,26 +44,83 @@ impl TryFrom<FormData> for NewSubscriber {
     }
 }
 
+#[utoipa::path(
+    tag = "subscriptions",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the first response")),
+    responses((status = 200, description = "A confirmation email has been sent"), SubscribeError)
+)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, request, pool, email_client, base_url, idempotency_settings),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
     )
 )]
+#[post("/subscriptions")]
 pub async fn subscribe(
     form: web::Form<FormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    idempotency_settings: web::Data<IdempotencySettings>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let mut form = form.into_inner();
+    let idempotency_key = match (
+        IdempotencyKey::from_header(&request)
+            .map_err(|e| SubscribeError::ValidationError(e.to_string()))?,
+        form.idempotency_key.take(),
+    ) {
+        (Some(key), None) => Some(key),
+        (None, Some(field)) => Some(
+            field
+                .try_into()
+                .map_err(|e: anyhow::Error| SubscribeError::ValidationError(e.to_string()))?,
+        ),
+        (Some(key), Some(field)) if key.as_ref() == field => Some(key),
+        (Some(_), Some(_)) => {
+            return Err(SubscribeError::ValidationError(
+                "The idempotency keys of the header and of the form differ.".into(),
+            ));
+        }
+        (None, None) => None,
+    };
+    let fingerprint = RequestFingerprint::new(&request, &(&form.email, &form.name))?;
+    let new_subscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
 
-    let mut transaction = pool
-        .begin()
-        .await
-        .context("Failed to acquire a Postgres connection from the pool")?;
+    // read before the transaction begins, a request only holds one connection at a time
+    let layout = get_email_layout(&pool).await?;
+
+    let endpoint = IdempotentEndpoint::Subscribe;
+    let client = ClientFingerprint::new(&request);
+    let processing = match &idempotency_key {
+        Some(key) => {
+            try_processing_within_quota(
+                &pool,
+                endpoint,
+                key,
+                &fingerprint,
+                &client,
+                &idempotency_settings.anonymous_quota,
+            )
+            .await?
+        }
+        None => None,
+    };
+    let scope = IdempotencyScope::Anonymous(client);
+    let (mut transaction, idempotency_key) = match processing {
+        Some(NextAction::StartProcessing(transaction)) => (transaction, idempotency_key),
+        Some(NextAction::ReturnSavedResponse(saved_response)) => return Ok(saved_response),
+        Some(NextAction::RejectReusedKey) => return Err(SubscribeError::ReusedIdempotencyKey),
+        // without a key, or past its quota: processed as if it had not sent one
+        None => (
+            pool.begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")?,
+            None,
+        ),
+    };
 
     // Insert the subscriber details into database and get subscriber_id
     let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
@@ -61,15 +135,17 @@ pub async fn subscribe(
         .await
         .context("Failed to store the confirmation token for a new subcriber.")?;
 
-    // commit the transaction
-    transaction
-        .commit()
-        .await
-        .context("Failed to commit SQL transaction to store a new subscriber.")?;
+    AuditEvent::new(Actor::Anonymous, AuditAction::SubscriberCreated)
+        .target(subscriber_id)
+        .ip_address(client_ip(&request))
+        .record(&mut *transaction)
+        .await?;
 
-    // Send the subscriber confirmation mail with token
+    // sent before anything is committed: if it fails, a retry with the same key subscribes again
+    // rather than being told it succeeded
     send_confirmation_email(
         &email_client,
+        &layout,
         new_subscriber,
         &base_url.0,
         &subscription_token,
@@ -77,19 +153,40 @@ pub async fn subscribe(
     .await
     .context("Failed to send a confirmation email.")?;
 
-    Ok(HttpResponse::Ok().finish())
+    // commit the transaction, along with the response replayed to retries
+    let response = match &idempotency_key {
+        Some(key) => save_response(
+            transaction,
+            endpoint,
+            key,
+            &scope,
+            HttpResponse::Ok().finish(),
+        )
+        .await
+        .context("Failed to commit SQL transaction to store a new subscriber.")?,
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a new subscriber.")?;
+            HttpResponse::Ok().finish()
+        }
+    };
+
+    Ok(response)
 }
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    skip(email_client, layout, new_subscriber, base_url, subscription_token)
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
+    layout: &EmailLayout,
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token,
@@ -106,9 +203,11 @@ pub async fn send_confirmation_email(
         confirmation_link
     );
 
+    let email = layout.render(html_body, plain_body, None)?;
     email_client
-        .send_email(&new_subscriber.email, "Welcome!", html_body, plain_body)
-        .await
+        .send_email(&new_subscriber.email, "Welcome!", &email.html, &email.text)
+        .await?;
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -122,13 +221,14 @@ pub async fn insert_subscriber(
     let subscriber_id = Uuid::new_v4();
     sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+        VALUES ($1, $2, $3, $4, 'pending_confirmation', $5)
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now(),
+        generate_subscription_token(),
     )
     .execute(&mut **transaction) // reference to PgConnection or take ownership of transaction here and pass it back
     .await?;
@@ -161,7 +261,7 @@ pub async fn store_token(
     Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
 
     // generate 25 length token by random sampling of alphanumeric characters => 62^25 possible tokens
@@ -216,6 +316,8 @@ pub fn error_chain_fmt(
 pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
+    #[error("The idempotency key has already been used for another subscription.")]
+    ReusedIdempotencyKey,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -231,7 +333,25 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::ReusedIdempotencyKey => StatusCode::UNPROCESSABLE_ENTITY,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
+
+// Documents the responses of the variants in the OpenAPI description
+impl IntoResponses for SubscribeError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        BTreeMap::from([
+            (
+                "400".into(),
+                Response::new("Invalid name or email address, the reason is in the body").into(),
+            ),
+            (
+                "422".into(),
+                Response::new("The idempotency key was used for another subscription").into(),
+            ),
+            ("500".into(), Response::new("Unexpected error").into()),
+        ])
+    }
+}
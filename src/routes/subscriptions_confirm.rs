@@ -1,18 +1,33 @@
-use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use actix_web::{HttpRequest, HttpResponse, ResponseError, get, http::StatusCode, web};
 use anyhow::Context;
 use sqlx::PgPool;
+use std::collections::BTreeMap;
+use utoipa::{
+    IntoResponses,
+    openapi::{RefOr, response::Response},
+};
 use uuid::Uuid;
 
-use crate::routes::error_chain_fmt;
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    routes::error_chain_fmt,
+    utils::client_ip,
+};
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::IntoParams)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
+#[utoipa::path(
+    tag = "subscriptions",
+    responses((status = 200, description = "The subscription is confirmed"), SubscribeConfirmError)
+)]
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, request, pool))]
+#[get("/subscriptions/confirm")]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, SubscribeConfirmError> {
     // get subscriber_id using token from the database
@@ -25,6 +40,11 @@ pub async fn confirm(
     confirm_subscriber(&pool, subscriber_id)
         .await
         .context("Failed to update subscription status to succeeded.")?;
+    AuditEvent::new(Actor::Anonymous, AuditAction::SubscriberConfirmed)
+        .target(subscriber_id)
+        .ip_address(client_ip(&request))
+        .record(pool.get_ref())
+        .await?;
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -83,3 +103,15 @@ impl ResponseError for SubscribeConfirmError {
         }
     }
 }
+
+impl IntoResponses for SubscribeConfirmError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        BTreeMap::from([
+            (
+                "401".into(),
+                Response::new("There is no subscriber associated with the token").into(),
+            ),
+            ("500".into(), Response::new("Unexpected error").into()),
+        ])
+    }
+}
@@ -0,0 +1,34 @@
+use actix_web::{HttpResponse, get, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+use crate::templates::render;
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+pub struct QueryParams {
+    token: String,
+}
+
+#[derive(Template)]
+#[template(path = "unsubscribe.html")]
+struct UnsubscribeTemplate {
+    flash_messages: IncomingFlashMessages,
+    token: String,
+}
+
+// Mail clients and link scanners follow links on their own: the subscriber has to confirm by
+// submitting the form, a GET request never unsubscribes
+#[utoipa::path(
+    tag = "subscriptions",
+    responses((status = 200, description = "The form asking the subscriber to confirm"))
+)]
+#[get("/subscriptions/unsubscribe")]
+pub async fn unsubscribe_form(
+    query: web::Query<QueryParams>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&UnsubscribeTemplate {
+        flash_messages,
+        token: query.0.token,
+    })
+}
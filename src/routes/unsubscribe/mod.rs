@@ -0,0 +1,5 @@
+pub(crate) mod get;
+pub(crate) mod post;
+
+pub use get::unsubscribe_form;
+pub use post::unsubscribe;
@@ -0,0 +1,69 @@
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{Actor, AuditAction, AuditEvent},
+    utils::{client_ip, e500, see_other},
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(as = UnsubscribeForm)]
+pub struct FormData {
+    token: String,
+}
+
+#[utoipa::path(
+    tag = "subscriptions",
+    responses((status = 303, description = "Redirects to the home page with a flash message"))
+)]
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(form, request, pool))]
+#[post("/subscriptions/unsubscribe")]
+pub async fn unsubscribe(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = match mark_subscriber_as_unsubscribed(&pool, &form.token)
+        .await
+        .context("Failed to update the subscription status to unsubscribed.")
+        .map_err(e500)?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            FlashMessage::error("This unsubscribe link is invalid.").send();
+            return Ok(see_other("/"));
+        }
+    };
+    AuditEvent::new(Actor::Anonymous, AuditAction::SubscriberUnsubscribed)
+        .target(subscriber_id)
+        .ip_address(client_ip(&request))
+        .record(pool.get_ref())
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("You have been unsubscribed, you won't receive our newsletter anymore.")
+        .send();
+    Ok(see_other("/"))
+}
+
+#[tracing::instrument(skip_all)]
+async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    unsubscribe_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE unsubscribe_token = $1
+        RETURNING id
+        "#,
+        unsubscribe_token,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| r.id))
+}
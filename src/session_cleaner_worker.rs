@@ -0,0 +1,60 @@
+use std::time::Instant;
+
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    authentication::delete_expired_login_failures,
+    configuration::{SessionStoreSettings, Settings},
+    health::{HEARTBEAT_INTERVAL, Heartbeat, WorkerKind},
+    prometheus::record_pool_stats,
+    session_store::delete_expired_sessions,
+    startup::get_connection_pool,
+};
+
+// Sessions and failed login counters are only kept in Postgres when Redis isn't used, which
+// expires its keys by itself
+pub async fn run_session_cleaner_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+
+    worker_loop(connection_pool, configuration.session_store, shutdown).await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    settings: SessionStoreSettings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    // the cleanup interval is usually longer than the heartbeat one, the loop wakes up for both
+    let mut heartbeat = Heartbeat::new(WorkerKind::SessionCleaner);
+    let mut next_cleanup = Instant::now();
+    while !shutdown.is_cancelled() {
+        heartbeat.beat(&pool).await;
+        if next_cleanup <= Instant::now() {
+            // failures are only logged, the next run will try again
+            let _ = delete_expired_state(&pool).await;
+            record_pool_stats("session_cleaner", &pool);
+            next_cleanup = Instant::now() + settings.cleanup_interval();
+        }
+        let wait = next_cleanup
+            .saturating_duration_since(Instant::now())
+            .min(HEARTBEAT_INTERVAL);
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+    tracing::info!("Session cleaner stopped");
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, err)]
+async fn delete_expired_state(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let sessions = delete_expired_sessions(pool).await?;
+    let login_failures = delete_expired_login_failures(pool).await?;
+    tracing::info!(sessions, login_failures, "Purged expired sessions");
+    Ok(())
+}
@@ -2,17 +2,44 @@ use std::future::{Ready, ready};
 
 use actix_session::{Session, SessionExt};
 use actix_web::FromRequest;
+use secrecy::{ExposeSecret, Secret};
 use uuid::Uuid;
 
+use crate::authentication::generate_token;
+
+// Session state expires this long after the last login, unless the user logs out before
+pub const SESSION_TTL: chrono::TimeDelta = chrono::TimeDelta::days(1);
+
 pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const PENDING_TWO_FACTOR_USER_ID_KEY: &'static str = "pending_two_factor_user_id";
+    const PENDING_TOTP_SECRET_KEY: &'static str = "pending_totp_secret";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
 
     pub fn renew(&self) {
         self.0.renew();
     }
 
+    // Once all the authentication steps succeeded and the session has been registered
+    pub fn log_in(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.renew(); // creates a new session key and map existing state to it
+        self.remove_pending_two_factor_user_id();
+        self.insert_user_id(user_id)?;
+        // a new token, since one set before logging in could have been planted by someone else
+        self.0.insert(
+            Self::CSRF_TOKEN_KEY,
+            generate_token().expose_secret().as_str(),
+        )?;
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
     pub fn log_out(self) {
         self.0.purge()
     }
@@ -24,6 +51,66 @@ impl TypedSession {
     pub fn get_user_id(&self) -> Result<Option<Uuid>, actix_session::SessionGetError> {
         self.0.get(Self::USER_ID_KEY)
     }
+
+    // identifies the session in the `user_sessions` table, where it can be revoked
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, actix_session::SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    // the password of this user has been checked, the second factor hasn't been yet
+    pub fn insert_pending_two_factor_user_id(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.0.insert(Self::PENDING_TWO_FACTOR_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_two_factor_user_id(
+        &self,
+    ) -> Result<Option<Uuid>, actix_session::SessionGetError> {
+        self.0.get(Self::PENDING_TWO_FACTOR_USER_ID_KEY)
+    }
+
+    pub fn remove_pending_two_factor_user_id(&self) {
+        self.0.remove(Self::PENDING_TWO_FACTOR_USER_ID_KEY);
+    }
+
+    // the TOTP secret being enrolled, until the user proves their authenticator app has it
+    pub fn insert_pending_totp_secret(
+        &self,
+        secret: &Secret<String>,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.0
+            .insert(Self::PENDING_TOTP_SECRET_KEY, secret.expose_secret())
+    }
+
+    pub fn get_pending_totp_secret(
+        &self,
+    ) -> Result<Option<Secret<String>>, actix_session::SessionGetError> {
+        Ok(self
+            .0
+            .get::<String>(Self::PENDING_TOTP_SECRET_KEY)?
+            .map(Secret::new))
+    }
+
+    pub fn remove_pending_totp_secret(&self) {
+        self.0.remove(Self::PENDING_TOTP_SECRET_KEY);
+    }
+
+    // Embedded in the admin forms, so that other sites can't submit them on behalf of the user.
+    // It is set when logging in, sessions which predate CSRF protection get one here.
+    pub fn csrf_token(&self) -> Result<String, anyhow::Error> {
+        if let Some(token) = self.get_csrf_token()? {
+            return Ok(token);
+        }
+        let token = generate_token().expose_secret().to_owned();
+        self.0.insert(Self::CSRF_TOKEN_KEY, &token)?;
+        Ok(token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, actix_session::SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
 }
 
 /*
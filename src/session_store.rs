@@ -0,0 +1,357 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use actix_session::storage::{
+    LoadError, RedisSessionStore, SaveError, SessionKey, SessionStore, UpdateError,
+    generate_session_key,
+};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::configuration::SessionStoreBackend;
+
+type SessionState = HashMap<String, String>;
+
+// The session middleware is generic over its store: the one picked in the settings is wrapped in
+// this enum, so that the app has the same type whatever the backend
+#[derive(Clone)]
+pub enum SessionStorage {
+    Redis(RedisSessionStore),
+    Postgres(PostgresSessionStore),
+    Memory(InMemorySessionStore),
+}
+
+impl SessionStorage {
+    pub async fn new(
+        backend: SessionStoreBackend,
+        redis_uri: &Secret<String>,
+        pool: &PgPool,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(match backend {
+            SessionStoreBackend::Redis => Self::Redis(
+                RedisSessionStore::new(redis_uri.expose_secret())
+                    .await
+                    .context("Failed to connect to Redis")?,
+            ),
+            SessionStoreBackend::Postgres => {
+                Self::Postgres(PostgresSessionStore::new(pool.clone()))
+            }
+            SessionStoreBackend::Memory => Self::Memory(InMemorySessionStore::default()),
+        })
+    }
+}
+
+impl SessionStore for SessionStorage {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            Self::Redis(store) => store.load(session_key).await,
+            Self::Postgres(store) => store.load(session_key).await,
+            Self::Memory(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::Redis(store) => store.save(session_state, ttl).await,
+            Self::Postgres(store) => store.save(session_state, ttl).await,
+            Self::Memory(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::Redis(store) => store.update(session_key, session_state, ttl).await,
+            Self::Postgres(store) => store.update(session_key, session_state, ttl).await,
+            Self::Memory(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        match self {
+            Self::Redis(store) => store.update_ttl(session_key, ttl).await,
+            Self::Postgres(store) => store.update_ttl(session_key, ttl).await,
+            Self::Memory(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        match self {
+            Self::Redis(store) => store.delete(session_key).await,
+            Self::Postgres(store) => store.delete(session_key).await,
+            Self::Memory(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+// Session state serialized as JSON in the `sessions` table, like the Redis store does.
+// Expired sessions are ignored when loaded, and deleted by the cleanup worker
+#[derive(Clone)]
+pub struct PostgresSessionStore {
+    pool: PgPool,
+}
+
+impl PostgresSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl SessionStore for PostgresSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let state = sqlx::query_scalar!(
+            "SELECT state FROM sessions WHERE session_key = $1 AND expires_at > now()",
+            session_key.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| LoadError::Other(e.into()))?;
+        state
+            .map(|state| serde_json::from_str(&state))
+            .transpose()
+            .map_err(|e| LoadError::Deserialization(e.into()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .map_err(|e| SaveError::Serialization(e.into()))?;
+        let session_key = generate_session_key();
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key, state, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| SaveError::Other(e.into()))?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .map_err(|e| UpdateError::Serialization(e.into()))?;
+        let updated = sqlx::query!(
+            r#"
+            UPDATE sessions SET state = $2, expires_at = $3
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UpdateError::Other(e.into()))?;
+        if updated.rows_affected() > 0 {
+            return Ok(session_key);
+        }
+        // the session expired since it was loaded: saved under a new key, as the Redis store does
+        self.save(session_state, ttl).await.map_err(|e| match e {
+            SaveError::Serialization(e) => UpdateError::Serialization(e),
+            SaveError::Other(e) => UpdateError::Other(e),
+        })
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE sessions SET expires_at = $2 WHERE session_key = $1 AND expires_at > now()",
+            session_key.as_ref(),
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        sqlx::query!(
+            "DELETE FROM sessions WHERE session_key = $1",
+            session_key.as_ref()
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_expired_sessions(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let deleted = sqlx::query!("DELETE FROM sessions WHERE expires_at <= now()")
+        .execute(pool)
+        .await?;
+    Ok(deleted.rows_affected())
+}
+
+// Sessions kept in the memory of the process, lost when it stops and not shared with other
+// instances: for tests and local development only
+#[derive(Clone, Default)]
+pub struct InMemorySessionStore {
+    sessions: Arc<Mutex<HashMap<String, InMemorySession>>>,
+}
+
+struct InMemorySession {
+    state: SessionState,
+    expires_at: DateTime<Utc>,
+}
+
+impl SessionStore for InMemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let sessions = self.sessions.lock().unwrap();
+        Ok(sessions
+            .get(session_key.as_ref())
+            .filter(|session| session.expires_at > Utc::now())
+            .map(|session| session.state.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        let mut sessions = self.sessions.lock().unwrap();
+        // no cleanup worker here: expired sessions are dropped as new ones come in
+        let now = Utc::now();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(
+            session_key.as_ref().to_owned(),
+            InMemorySession {
+                state: session_state,
+                expires_at: expires_at(ttl),
+            },
+        );
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let session_state = {
+            let mut sessions = self.sessions.lock().unwrap();
+            match sessions.get_mut(session_key.as_ref()) {
+                Some(session) if session.expires_at > Utc::now() => {
+                    session.state = session_state;
+                    session.expires_at = expires_at(ttl);
+                    return Ok(session_key);
+                }
+                _ => session_state,
+            }
+        };
+        self.save(session_state, ttl).await.map_err(|e| match e {
+            SaveError::Serialization(e) => UpdateError::Serialization(e),
+            SaveError::Other(e) => UpdateError::Other(e),
+        })
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        // an expired session isn't brought back
+        if let Some(session) = self
+            .sessions
+            .lock()
+            .unwrap()
+            .get_mut(session_key.as_ref())
+            .filter(|session| session.expires_at > Utc::now())
+        {
+            session.expires_at = expires_at(ttl);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        self.sessions.lock().unwrap().remove(session_key.as_ref());
+        Ok(())
+    }
+}
+
+fn expires_at(ttl: &Duration) -> DateTime<Utc> {
+    Utc::now() + chrono::TimeDelta::seconds(ttl.whole_seconds())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_session::storage::SessionStore;
+    use actix_web::cookie::time::Duration;
+
+    use super::InMemorySessionStore;
+
+    fn state() -> HashMap<String, String> {
+        HashMap::from([("user_id".to_string(), "\"42\"".to_string())])
+    }
+
+    #[tokio::test]
+    async fn saved_sessions_are_loaded_until_they_expire() {
+        let store = InMemorySessionStore::default();
+
+        let live = store.save(state(), &Duration::hours(1)).await.unwrap();
+        let expired = store.save(state(), &Duration::seconds(-1)).await.unwrap();
+
+        assert_eq!(store.load(&live).await.unwrap(), Some(state()));
+        assert_eq!(store.load(&expired).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_saved_under_a_new_key_when_updated() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::seconds(-1)).await.unwrap();
+        let expired_key = key.as_ref().to_owned();
+
+        let key = store
+            .update(key, state(), &Duration::hours(1))
+            .await
+            .unwrap();
+
+        assert_ne!(key.as_ref(), expired_key);
+        assert_eq!(store.load(&key).await.unwrap(), Some(state()));
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_not_extended() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::seconds(-1)).await.unwrap();
+
+        store.update_ttl(&key, &Duration::hours(1)).await.unwrap();
+
+        assert_eq!(store.load(&key).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn deleted_sessions_are_gone() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::hours(1)).await.unwrap();
+
+        store.delete(&key).await.unwrap();
+
+        assert_eq!(store.load(&key).await.unwrap(), None);
+    }
+}
@@ -1,12 +1,30 @@
-use crate::authentication::reject_anonymous_users;
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::authentication::{
+    LoginThrottle, reject_anonymous_users, reject_invalid_api_tokens, reject_invalid_csrf_tokens,
+    reject_non_owners, reject_viewers,
+};
+use crate::configuration::{DatabaseSettings, SessionStoreBackend, Settings};
 use crate::email_client::EmailClient;
+use crate::flash_messages::SameSiteCookieMessageStore;
+use crate::health::ReadinessProbe;
+use crate::prometheus::{ScrapedPool, prometheus_handle, record_http_metrics};
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, publish_newsletters, publish_newsletters_form, subscribe,
+    ApiDoc, accept_invitation, accept_invitation_form, admin_dashboard, audit_log, change_password,
+    change_password_form, confirm, create_api_token, create_issue, create_subscriber,
+    delete_subscriber, disable_two_factor, email_layout_form, enable_two_factor, export_audit_log,
+    find_subscriber, forgot_password::forgot_password, forgot_password_form, get_issue, get_stats,
+    get_subscriber, health_check::health_check, home::home, invite_user, invite_user_form,
+    json_config, list_api_tokens, list_sessions, liveness, log_out, login::login, login_form,
+    metrics::metrics, openapi_json, preview_email_layout, publish_issue, publish_newsletters,
+    publish_newsletters_form, readiness, reset_password::reset_password, reset_password_form,
+    revoke_api_token, revoke_other_sessions, revoke_session, settings_form, subscribe,
+    two_factor_form, two_factor_login, two_factor_login_form, unsubscribe::unsubscribe,
+    unsubscribe_form, update_email_layout, update_settings, update_subscriber,
 };
+use crate::session_state::SESSION_TTL;
+use crate::session_store::SessionStorage;
+use crate::utils::TrustedProxies;
 use actix_session::SessionMiddleware;
-use actix_session::storage::RedisSessionStore;
+use actix_session::config::BrowserSession;
 use actix_web::cookie::Key;
 use actix_web::dev::Server;
 use actix_web::web::Data;
@@ -18,11 +36,16 @@ use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 use sqlx::postgres::PgPoolOptions;
 use std::net::TcpListener;
+use tokio_util::sync::CancellationToken;
 use tracing_actix_web::TracingLogger;
+use utoipa::OpenApi;
+use utoipa_actix_web::{AppExt, scope};
 
 pub struct Application {
     port: u16,
     server: Server,
+    // serving `/metrics` when it's on a separate port
+    metrics: Option<(u16, Server)>,
 }
 
 #[derive(Clone)]
@@ -34,8 +57,7 @@ impl Application {
         let connection_pool = get_connection_pool(&configuration.database);
 
         // create an `EmailClient` from config
-        // Is it better to handle this error than panic?
-        let email_client = configuration.email_client.client();
+        let email_client = configuration.email_client.clone().client()?;
 
         // bind the address and return the server
         let address = format!(
@@ -44,25 +66,61 @@ impl Application {
         );
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
-        let server = run(
-            listener,
-            connection_pool,
-            email_client,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
-            configuration.redis_uri,
-        )
-        .await?;
-
-        Ok(Self { port, server })
+        let metrics_server = match configuration.application.metrics_port {
+            Some(metrics_port) => {
+                let listener = TcpListener::bind(format!(
+                    "{}:{}",
+                    configuration.application.host, metrics_port
+                ))?;
+                let metrics_port = listener.local_addr().unwrap().port();
+                Some((
+                    metrics_port,
+                    run_metrics_server(listener, connection_pool.clone(), "api")?,
+                ))
+            }
+            None => None,
+        };
+        let server = run(listener, connection_pool, email_client, configuration).await?;
+
+        Ok(Self {
+            port,
+            server,
+            metrics: metrics_server,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics.as_ref().map(|(port, _)| *port)
+    }
+
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        match self.metrics {
+            Some((_, metrics_server)) => tokio::try_join!(self.server, metrics_server).map(|_| ()),
+            None => self.server.await,
+        }
+    }
+
+    // Stop accepting new connections once `shutdown` is cancelled and
+    // drain in-flight requests (bounded by the configured shutdown timeout)
+    pub async fn run_until_shutdown(
+        self,
+        shutdown: CancellationToken,
+    ) -> Result<(), std::io::Error> {
+        let mut server_handles = vec![self.server.handle()];
+        if let Some((_, metrics_server)) = &self.metrics {
+            server_handles.push(metrics_server.handle());
+        }
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            for server_handle in server_handles {
+                server_handle.stop(true).await;
+            }
+        });
+        self.run_until_stopped().await
     }
 }
 
@@ -80,52 +138,187 @@ async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
-    redis_uri: Secret<String>,
+    configuration: Settings,
 ) -> Result<Server, anyhow::Error> {
+    let base_url = configuration.application.base_url;
+    let hmac_secret = configuration.application.hmac_secret;
+    let redis_uri = configuration.redis_uri;
+
     // wrap the connection in a smart pointer Arc<T>
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let trusted_proxies = web::Data::new(TrustedProxies(configuration.application.trusted_proxies));
+    let idempotency_settings = web::Data::new(configuration.idempotency);
+    let prometheus_handle = web::Data::new(prometheus_handle());
+    // on the public port unless it has its own
+    let public_metrics = configuration.application.metrics_port.is_none();
 
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
-    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let same_site = configuration.application.cookie_same_site.into();
+    let message_store = SameSiteCookieMessageStore::new(
+        CookieMessageStore::builder(secret_key.clone()).build(),
+        same_site,
+    );
     let message_framework = FlashMessagesFramework::builder(message_store).build();
-    let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let session_backend = configuration.session_store.backend;
+    let session_store = SessionStorage::new(session_backend, &redis_uri, &db_pool).await?;
+    let session_ttl = actix_web::cookie::time::Duration::seconds(SESSION_TTL.num_seconds());
+    // Redis is only connected to when the sessions are stored there
+    let uses_redis = session_backend == SessionStoreBackend::Redis;
+    let login_throttle = web::Data::new(match uses_redis {
+        true => LoginThrottle::redis(&redis_uri, configuration.login_throttling).await?,
+        false => LoginThrottle::postgres(db_pool.get_ref().clone(), configuration.login_throttling),
+    });
+    let readiness_probe = web::Data::new(ReadinessProbe::new(
+        uses_redis.then_some(&redis_uri),
+        configuration.health,
+    )?);
 
     // Capture 'connection' from the surrounding environment
     let server = HttpServer::new(move || {
-        App::new()
-            .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
-            .wrap(TracingLogger::default()) // generates a unique request id for each request
-            .route("/", web::get().to(home))
-            .route("/login", web::get().to(login_form))
-            .route("/login", web::post().to(login))
-            .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
-            .route("/subscriptions/confirm", web::get().to(confirm))
+        let app = App::new()
+            .into_utoipa_app()
+            .openapi(ApiDoc::openapi())
+            .service(home)
+            .service(login_form)
+            .service(login)
+            .service(two_factor_login_form)
+            .service(two_factor_login)
+            .service(health_check)
+            .service(liveness)
+            .service(readiness)
+            .service(openapi_json);
+        let app = match public_metrics {
+            true => app.service(metrics),
+            false => app,
+        };
+        let (app, openapi) = app
+            .service(subscribe)
+            .service(confirm)
+            .service(unsubscribe_form)
+            .service(unsubscribe)
+            .service(forgot_password_form)
+            .service(forgot_password)
+            .service(reset_password_form)
+            .service(reset_password)
+            .service(accept_invitation_form)
+            .service(accept_invitation)
             .service(
-                web::scope("/admin")
+                scope("/admin")
+                    // runs after the user has been authenticated
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
                     .wrap(from_fn(reject_anonymous_users))
-                    .route("/newsletters", web::post().to(publish_newsletters))
-                    .route("/newsletters", web::get().to(publish_newsletters_form))
-                    .route("/logout", web::post().to(log_out))
-                    .route("/password", web::get().to(change_password_form))
-                    .route("/password", web::post().to(change_password))
-                    .route("/dashboard", web::get().to(admin_dashboard)),
+                    .service(
+                        scope("/newsletters")
+                            .wrap(from_fn(reject_viewers))
+                            .service(publish_newsletters)
+                            .service(publish_newsletters_form),
+                    )
+                    .service(
+                        scope("/invitations")
+                            .wrap(from_fn(reject_non_owners))
+                            .service(invite_user)
+                            .service(invite_user_form),
+                    )
+                    .service(
+                        scope("/settings")
+                            .wrap(from_fn(reject_non_owners))
+                            .service(update_settings)
+                            .service(settings_form),
+                    )
+                    .service(
+                        scope("/email-layout")
+                            .wrap(from_fn(reject_non_owners))
+                            .service(email_layout_form)
+                            .service(update_email_layout)
+                            .service(preview_email_layout),
+                    )
+                    .service(
+                        scope("/audit")
+                            .wrap(from_fn(reject_non_owners))
+                            .service(audit_log)
+                            .service(export_audit_log),
+                    )
+                    .service(two_factor_form)
+                    .service(enable_two_factor)
+                    .service(disable_two_factor)
+                    .service(list_sessions)
+                    .service(revoke_session)
+                    .service(revoke_other_sessions)
+                    .service(list_api_tokens)
+                    .service(create_api_token)
+                    .service(revoke_api_token)
+                    .service(log_out)
+                    .service(change_password_form)
+                    .service(change_password)
+                    .service(admin_dashboard),
+            )
+            .service(
+                scope("/api/v1")
+                    .wrap(from_fn(reject_invalid_api_tokens))
+                    .app_data(json_config())
+                    // viewers can read, the handlers that make changes reject them
+                    .service(get_stats)
+                    .service(create_issue)
+                    .service(get_issue)
+                    .service(publish_issue)
+                    .service(find_subscriber)
+                    .service(create_subscriber)
+                    .service(get_subscriber)
+                    .service(update_subscriber)
+                    .service(delete_subscriber),
+            )
+            .split_for_parts();
+        app.wrap(message_framework.clone())
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+                    .session_lifecycle(BrowserSession::default().state_ttl(session_ttl))
+                    .cookie_same_site(same_site)
+                    .build(),
             )
+            .wrap(TracingLogger::default()) // generates a unique request id for each request
+            .wrap(from_fn(record_http_metrics))
+            .app_data(Data::new(openapi))
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(idempotency_settings.clone())
+            .app_data(prometheus_handle.clone())
+            .app_data(Data::new(ScrapedPool("api")))
+            .app_data(login_throttle.clone())
+            .app_data(readiness_probe.clone())
             .app_data(Data::new(HmacSecret(hmac_secret.clone())))
     })
     .listen(listener)?
+    // signals are handled in `main` so that the server and the background workers stop together
+    .disable_signals()
+    .shutdown_timeout(configuration.application.shutdown_timeout_seconds)
     .run();
 
     Ok(server)
 }
+
+// Only serves `/metrics`, for a port that isn't exposed publicly.
+// The pool is used to measure the delivery queue, its stats are reported under `pool_name`
+pub fn run_metrics_server(
+    listener: TcpListener,
+    db_pool: PgPool,
+    pool_name: &'static str,
+) -> Result<Server, std::io::Error> {
+    let db_pool = web::Data::new(db_pool);
+    let handle = web::Data::new(prometheus_handle());
+    let pool_name = web::Data::new(ScrapedPool(pool_name));
+    let server = HttpServer::new(move || {
+        App::new()
+            .service(metrics)
+            .app_data(db_pool.clone())
+            .app_data(handle.clone())
+            .app_data(pool_name.clone())
+    })
+    .listen(listener)?
+    .disable_signals()
+    .run();
+    Ok(server)
+}
@@ -1,14 +1,32 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use anyhow::Context;
+use opentelemetry::{propagation::TextMapPropagator, trace::TracerProvider};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    Resource,
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracer, SdkTracerProvider},
+};
 use tokio::task::JoinHandle;
 use tracing::{Subscriber, subscriber::set_global_default};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{EnvFilter, Registry, fmt::MakeWriter, layer::SubscriberExt};
 
+use crate::configuration::TelemetrySettings;
+
+// Kept to flush the spans still buffered when the process exits
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
 // Compose multiple layers into a `tracing` subscriber.
+// Spans are also exported to an OpenTelemetry collector when given a tracer, see `otlp_tracer`
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Option<SdkTracer>,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
@@ -18,6 +36,7 @@ where
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
     Registry::default()
         .with(env_filer)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
@@ -29,6 +48,62 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     set_global_default(subscriber).expect("Failed to set subscriber.");
 }
 
+// None unless an OTLP endpoint is configured. Spans are exported in batches, from a background thread
+pub fn otlp_tracer(settings: &TelemetrySettings) -> Result<Option<SdkTracer>, anyhow::Error> {
+    let Some(endpoint) = &settings.otlp_endpoint else {
+        return Ok(None);
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()
+        .context("Failed to build the OTLP span exporter")?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            settings.sampling_ratio,
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(settings.service_name.clone())
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer("newsletter");
+    // `TracingLogger` continues the traces of incoming requests with a `traceparent` header
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    TRACER_PROVIDER
+        .set(provider)
+        .map_err(|_| anyhow::anyhow!("The OTLP tracer has already been initialised"))?;
+    Ok(Some(tracer))
+}
+
+// Blocks until the buffered spans have been exported
+pub fn flush_traces() {
+    if let Some(provider) = TRACER_PROVIDER.get()
+        && let Err(e) = provider.force_flush()
+    {
+        tracing::warn!(error.message = %e, "Failed to export the buffered spans");
+    }
+}
+
+// W3C `traceparent` of the current span, None when spans are not exported.
+// Stored along with work done later, like the deliveries of an issue, to continue its trace
+pub fn current_traceparent() -> Option<String> {
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&tracing::Span::current().context(), &mut carrier);
+    carrier.remove("traceparent")
+}
+
+// Makes `span` part of the trace a `traceparent` was taken from
+pub fn continue_trace(span: &tracing::Span, traceparent: &str) {
+    let carrier = HashMap::from([("traceparent".to_string(), traceparent.to_string())]);
+    let context = TraceContextPropagator::new().extract(&carrier);
+    if let Err(e) = span.set_parent(context) {
+        tracing::warn!(error.message = %e, "Failed to continue a stored trace");
+    }
+}
+
 pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
 where
     F: FnOnce() -> R + Send + 'static,
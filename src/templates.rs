@@ -0,0 +1,49 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::{FromRequest, HttpMessage, HttpRequest, HttpResponse, http::header::ContentType};
+use askama::Template;
+
+use crate::{authentication::Role, session_state::TypedSession, utils::e500};
+
+// Pages are askama templates from the `templates` directory, checked at compile time.
+// Values are HTML-escaped unless a template explicitly marks them as `safe`.
+pub fn render(template: &impl Template) -> Result<HttpResponse, actix_web::Error> {
+    let body = template.render().map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+// Navigation of the admin pages, only showing what the user is allowed to do.
+// Must be extracted behind `reject_anonymous_users`, which stores the role of the user.
+pub struct AdminNav {
+    pub role: Role,
+    // for the logout form, and any other form of the page
+    pub csrf_token: String,
+}
+
+impl AdminNav {
+    pub fn can_publish(&self) -> bool {
+        self.role >= Role::Editor
+    }
+
+    pub fn can_manage_users(&self) -> bool {
+        self.role >= Role::Owner
+    }
+}
+
+impl FromRequest for AdminNav {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let role = req.extensions().get::<Role>().copied();
+        let session = TypedSession::from_request(req, payload);
+        Box::pin(async move {
+            let role = role.ok_or_else(|| e500("The role of the user is not known"))?;
+            let csrf_token = session.await?.csrf_token().map_err(e500)?;
+            Ok(AdminNav { role, csrf_token })
+        })
+    }
+}
@@ -1,4 +1,11 @@
-use actix_web::{HttpResponse, http::header::LOCATION};
+use std::net::IpAddr;
+
+use actix_web::{
+    HttpRequest, HttpResponse,
+    http::header::{LOCATION, USER_AGENT},
+    web,
+};
+use ipnet::IpNet;
 
 pub fn e400<T>(e: T) -> actix_web::Error
 where
@@ -7,6 +14,13 @@ where
     actix_web::error::ErrorBadRequest(e)
 }
 
+pub fn e422<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorUnprocessableEntity(e)
+}
+
 pub fn e500<T>(e: T) -> actix_web::Error
 where
     T: std::fmt::Debug + std::fmt::Display + 'static,
@@ -19,3 +33,88 @@ pub fn see_other(location: &str) -> HttpResponse {
         .insert_header((LOCATION, location))
         .finish()
 }
+
+// The reverse proxies whose X-Forwarded-For header is trusted, from the settings
+pub struct TrustedProxies(pub Vec<IpNet>);
+
+impl TrustedProxies {
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+}
+
+// The peer address, unless the peer is a trusted reverse proxy: the client is then the last
+// address added to X-Forwarded-For by a proxy, the ones before it could be made up by anyone
+pub fn client_ip(request: &HttpRequest) -> String {
+    let Some(peer) = request.peer_addr().map(|address| address.ip()) else {
+        return "unknown".to_string();
+    };
+    let Some(proxies) = request.app_data::<web::Data<TrustedProxies>>() else {
+        return peer.to_string();
+    };
+    if !proxies.contains(&peer) {
+        return peer.to_string();
+    }
+    let forwarded: Vec<&str> = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(','))
+        .collect();
+    for address in forwarded.into_iter().rev() {
+        match address.trim().parse::<IpAddr>() {
+            Ok(ip) if proxies.contains(&ip) => continue,
+            Ok(ip) => return ip.to_string(),
+            Err(_) => break,
+        }
+    }
+    peer.to_string()
+}
+
+pub fn user_agent(request: &HttpRequest) -> String {
+    request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test::TestRequest, web};
+
+    use super::{TrustedProxies, client_ip};
+
+    fn request(peer: &str, forwarded_for: &str) -> TestRequest {
+        TestRequest::default()
+            .peer_addr(format!("{}:4242", peer).parse().unwrap())
+            .insert_header(("X-Forwarded-For", forwarded_for))
+            .app_data(web::Data::new(TrustedProxies(vec![
+                "10.0.0.0/8".parse().unwrap(),
+            ])))
+    }
+
+    #[test]
+    fn the_forwarded_address_is_ignored_from_untrusted_peers() {
+        let request = request("203.0.113.7", "198.51.100.1").to_http_request();
+        assert_eq!(client_ip(&request), "203.0.113.7");
+    }
+
+    #[test]
+    fn the_last_address_forwarded_by_trusted_proxies_is_the_client() {
+        let request = request("10.0.0.1", "192.0.2.99, 198.51.100.1, 10.0.0.2").to_http_request();
+        assert_eq!(client_ip(&request), "198.51.100.1");
+    }
+
+    #[test]
+    fn the_peer_is_the_client_without_a_forwarded_address() {
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.1:4242".parse().unwrap())
+            .app_data(web::Data::new(TrustedProxies(vec![
+                "10.0.0.0/8".parse().unwrap(),
+            ])))
+            .to_http_request();
+        assert_eq!(client_ip(&request), "10.0.0.1");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{TestUser, assert_is_redirect_to, spawn_app};
 
 #[tokio::test]
 async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
@@ -35,3 +35,62 @@ async fn logout_clears_session_state() {
     let response = app.get_admin_dashboard().await;
     assert_is_redirect_to(&response, "/login");
 }
+
+#[tokio::test]
+async fn viewers_can_see_subscriber_counts_but_not_the_publish_action() {
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    let html_page = app.get_admin_dashboard_html().await;
+
+    assert!(html_page.contains("<li>Confirmed: 0</li>"));
+    assert!(html_page.contains("<li>Pending confirmation: 0</li>"));
+    assert!(!html_page.contains(r#"<a href="/admin/newsletters">"#));
+}
+
+#[tokio::test]
+async fn a_deleted_user_is_logged_out() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    sqlx::query!(
+        "DELETE FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn usernames_are_escaped() {
+    let app = spawn_app().await;
+    let mut user = TestUser::generate();
+    user.username = "<b>mallory</b>".into();
+    user.store(&app.db_pool).await;
+    user.login(&app).await;
+
+    let html_page = app.get_admin_dashboard_html().await;
+
+    assert!(html_page.contains("Welcome &#60;b&#62;mallory&#60;/b&#62;!"));
+}
+
+#[tokio::test]
+async fn the_navigation_only_shows_what_the_user_can_do() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    // every admin page shares the navigation
+    let html_page = app.get_change_password_html().await;
+
+    assert!(html_page.contains(r#"<a href="/admin/newsletters">"#));
+    assert!(!html_page.contains(r#"<a href="/admin/invitations">"#));
+    assert!(html_page.contains(r#"action="/admin/logout""#));
+}
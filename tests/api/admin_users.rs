@@ -0,0 +1,70 @@
+use newsletter::authentication::{Role, create_user, delete_user, get_user_id, list_users};
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn a_created_user_can_log_in() {
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    create_user(
+        &username,
+        Secret::new(password.clone()),
+        Role::Viewer,
+        None,
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &username,
+            "password": &password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn creating_a_user_with_an_existing_username_fails() {
+    let app = spawn_app().await;
+
+    let outcome = create_user(
+        &app.test_user.username,
+        Secret::new(Uuid::new_v4().to_string()),
+        Role::Editor,
+        None,
+        &app.db_pool,
+    )
+    .await;
+
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn a_deleted_user_cannot_log_in() {
+    let app = spawn_app().await;
+
+    delete_user(app.test_user.user_id, &app.db_pool)
+        .await
+        .unwrap();
+
+    assert!(
+        get_user_id(&app.test_user.username, &app.db_pool)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(list_users(&app.db_pool).await.unwrap().is_empty());
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,124 @@
+use reqwest::Method;
+
+use crate::helpers::{TestUser, assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn api_tokens_authenticate_requests_to_the_api() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let token = app.create_api_token("CMS").await;
+
+    let response = app
+        .api_request(Method::GET, "/stats", &token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/api-tokens", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("CMS"));
+    assert!(!html_page.contains(&token));
+}
+
+#[tokio::test]
+async fn tokens_are_not_stored_in_clear() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let token = app.create_api_token("CMS").await;
+
+    let signature = sqlx::query!("SELECT token_signature FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_signature;
+    assert_ne!(signature, token);
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_token_are_rejected() {
+    let app = spawn_app().await;
+
+    let without_token = reqwest::get(format!("{}/api/v1/stats", &app.address))
+        .await
+        .unwrap();
+    let with_invalid_token = app
+        .api_request(Method::GET, "/stats", "forged-token")
+        .send()
+        .await
+        .unwrap();
+
+    for response in [without_token, with_invalid_token] {
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["error"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("CMS").await;
+    let token_id = sqlx::query!("SELECT token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_id;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/api-tokens/revoke", &app.address))
+        .form(
+            &app.with_csrf_token(&serde_json::json!({ "token_id": token_id }))
+                .await,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+
+    let response = app
+        .api_request(Method::GET, "/stats", &token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn tokens_act_with_the_role_of_their_user() {
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+    let token = app.create_api_token("Dashboard").await;
+
+    let read = app
+        .api_request(Method::GET, "/stats", &token)
+        .send()
+        .await
+        .unwrap();
+    let write = app
+        .api_request(Method::POST, "/issues", &token)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(read.status().as_u16(), 200);
+    assert_eq!(write.status().as_u16(), 403);
+}
@@ -0,0 +1,192 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, TestUser, spawn_app, spawn_app_with};
+
+async fn export(app: &TestApp, query: &str) -> Vec<serde_json::Value> {
+    app.get_audit_log_export(query)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn logins_and_logouts_are_recorded() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+
+    let entries = export(&app, "actor=").await;
+
+    let actions: Vec<_> = entries
+        .iter()
+        .map(|e| e["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(actions, vec!["login", "logout", "login"]);
+    for entry in &entries {
+        assert_eq!(entry["actor"], app.test_user.username.as_str());
+        assert_eq!(entry["actor_id"], app.test_user.user_id.to_string());
+        assert!(entry["ip_address"].as_str().unwrap().starts_with("10."));
+    }
+}
+
+#[tokio::test]
+async fn forwarded_addresses_are_ignored_unless_the_peer_is_a_trusted_proxy() {
+    let app = spawn_app_with(|c| c.application.trusted_proxies = vec![]).await;
+    app.test_user.login(&app).await;
+
+    let entries = export(&app, "action=login").await;
+
+    assert_eq!(entries[0]["ip_address"], "127.0.0.1");
+}
+
+#[tokio::test]
+async fn publishing_a_newsletter_is_recorded() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    }))
+    .await;
+
+    let entries = export(&app, "action=newsletter_published").await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["details"], "Newsletter title");
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    assert_eq!(entries[0]["target"], issue_id.to_string());
+}
+
+#[tokio::test]
+async fn new_subscribers_are_recorded() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    app.test_user.login(&app).await;
+    let created = export(&app, "action=subscriber_created").await;
+    let confirmed = export(&app, "action=subscriber_confirmed").await;
+    assert_eq!(created.len(), 1);
+    assert_eq!(confirmed.len(), 1);
+    assert!(created[0]["actor"].is_null());
+    assert_eq!(created[0]["target"], confirmed[0]["target"]);
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_filtered() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+
+    let by_editor = export(&app, &format!("actor={}", editor.username)).await;
+    assert_eq!(by_editor.len(), 2);
+    let logins = export(&app, "action=login").await;
+    assert_eq!(logins.len(), 2);
+    let today = chrono::Utc::now().date_naive();
+    let in_range = export(&app, &format!("since={today}&until={today}")).await;
+    assert_eq!(in_range.len(), 3);
+    let before = export(&app, "until=2000-01-01").await;
+    assert!(before.is_empty());
+
+    let html_page = app
+        .get_audit_log(&format!("actor={}", editor.username))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("logout"));
+    assert!(!html_page.contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    for query in [
+        "action=drop_database",
+        "since=yesterday",
+        "until=+262142-12-31",
+    ] {
+        assert_eq!(app.get_audit_log(query).await.status().as_u16(), 400);
+        assert_eq!(app.get_audit_log_export(query).await.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn only_owners_can_see_the_audit_log() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    assert_eq!(app.get_audit_log("").await.status().as_u16(), 403);
+    assert_eq!(app.get_audit_log_export("").await.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn the_audit_log_is_append_only() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let update = sqlx::query!("UPDATE audit_log SET actor = 'someone else'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query!("DELETE FROM audit_log")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(update.is_err());
+    assert!(delete.is_err());
+}
+
+#[tokio::test]
+async fn exports_with_too_many_entries_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (occurred_at, actor, action)
+        SELECT now(), 'robot', 'login' FROM generate_series(1, 10000)
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_audit_log_export("actor=robot").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app.get_audit_log_export("actor=").await;
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,128 @@
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app};
+
+async fn post_publish_newsletter_with_token(
+    app: &TestApp,
+    token: Option<&str>,
+) -> reqwest::Response {
+    let mut body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    if let Some(token) = token {
+        body["csrf_token"] = token.into();
+    }
+    app.api_client
+        .post(format!("{}/admin/newsletters", &app.address))
+        .form(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn issue_count(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn admin_forms_embed_the_csrf_token_of_the_session() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.csrf_token().await;
+    assert_eq!(token.len(), 32);
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(&format!(r#"name="csrf_token" value="{token}""#)));
+}
+
+#[tokio::test]
+async fn a_form_without_csrf_token_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = post_publish_newsletter_with_token(&app, None).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(issue_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_form_with_an_invalid_csrf_token_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = post_publish_newsletter_with_token(&app, Some("forged-token")).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(issue_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_form_with_the_csrf_token_is_accepted() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.csrf_token().await;
+
+    let response = post_publish_newsletter_with_token(&app, Some(&token)).await;
+
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert_eq!(issue_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn logging_out_requires_the_csrf_token() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_csrf_token_changes_when_logging_in_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let first_token = app.csrf_token().await;
+    app.post_logout().await;
+
+    app.test_user.login(&app).await;
+
+    assert_ne!(app.csrf_token().await, first_token);
+}
+
+#[tokio::test]
+async fn cookies_are_same_site() {
+    let app = spawn_app().await;
+
+    // a failed login sets a flash message
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "random-username",
+            "password": "random-password",
+        }))
+        .await;
+    let flash_cookie = response.cookies().find(|c| c.name() == "_flash").unwrap();
+    assert!(flash_cookie.same_site_lax());
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    let session_cookie = response.cookies().find(|c| c.name() == "id").unwrap();
+    assert!(session_cookie.same_site_lax());
+}
@@ -0,0 +1,236 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, TestUser, assert_is_redirect_to, spawn_app};
+
+fn layout() -> serde_json::Value {
+    serde_json::json!({
+        "header": "<h1>The Weekly</h1>",
+        "footer": "<p class=\"footer\">Thanks for reading!</p>",
+        "styles": "h1 { color: #ff0000; }",
+        "postal_address": "1 Main Street, Springfield",
+    })
+}
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn publish_and_deliver_an_issue(app: &TestApp) {
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn last_email(app: &TestApp) -> serde_json::Value {
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+fn unsubscribe_token(email: &serde_json::Value) -> String {
+    let text = email["TextBody"].as_str().unwrap();
+    let link = text
+        .lines()
+        .find_map(|l| l.strip_prefix("Unsubscribe: "))
+        .unwrap();
+    let link = reqwest::Url::parse(link).unwrap();
+    assert_eq!(link.path(), "/subscriptions/unsubscribe");
+    link.query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[tokio::test]
+async fn issues_are_wrapped_in_the_layout_with_an_unsubscribe_link() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_email_layout(&layout()).await;
+    assert_is_redirect_to(&response, "/admin/email-layout");
+    publish_and_deliver_an_issue(&app).await;
+
+    let email = last_email(&app).await;
+    let html = email["HtmlBody"].as_str().unwrap();
+    assert!(html.contains(r#"<h1 style="color: #ff0000;">The Weekly</h1>"#));
+    assert!(html.contains("<p>Newsletter body as HTML</p>"));
+    assert!(html.contains("Thanks for reading!"));
+    assert!(html.contains("1 Main Street, Springfield"));
+    assert!(html.contains("/subscriptions/unsubscribe?token="));
+    let text = email["TextBody"].as_str().unwrap();
+    assert!(text.starts_with("Newsletter body as plain text"));
+    assert!(text.contains("1 Main Street, Springfield"));
+    unsubscribe_token(&email);
+}
+
+#[tokio::test]
+async fn transactional_emails_are_wrapped_in_the_layout() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(&app).await;
+    app.post_email_layout(&layout()).await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email = last_email(&app).await;
+    let html = email["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("The Weekly"));
+    assert!(html.contains("to confirm your subscription"));
+    assert!(!html.contains("Unsubscribe"));
+    assert!(!email["TextBody"].as_str().unwrap().contains("Unsubscribe"));
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_no_longer_receive_issues() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_and_deliver_an_issue(&app).await;
+    let token = unsubscribe_token(&last_email(&app).await);
+
+    // following the link only shows a form
+    let html_page = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        app.address, token
+    ))
+    .await
+    .unwrap()
+    .text()
+    .await
+    .unwrap();
+    assert!(html_page.contains(r#"action="/subscriptions/unsubscribe""#));
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "confirmed");
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions/unsubscribe", app.address))
+        .form(&serde_json::json!({ "token": token }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/");
+    let html_page = app
+        .api_client
+        .get(&app.address)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("You have been unsubscribed"));
+
+    let sent = app.email_server.received_requests().await.unwrap().len();
+    publish_and_deliver_an_issue(&app).await;
+    assert_eq!(
+        app.email_server.received_requests().await.unwrap().len(),
+        sent
+    );
+}
+
+#[tokio::test]
+async fn an_invalid_unsubscribe_token_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions/unsubscribe", app.address))
+        .form(&serde_json::json!({ "token": "forged-token" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/");
+    let html_page = app
+        .api_client
+        .get(&app.address)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("This unsubscribe link is invalid."));
+}
+
+#[tokio::test]
+async fn the_preview_shows_the_layout_with_inlined_styles() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_email_layout(&layout()).await;
+
+    let response = app.get_email_layout_preview().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<h1 style="color: #ff0000;">The Weekly</h1>"#));
+    assert!(html.contains("Unsubscribe"));
+}
+
+#[tokio::test]
+async fn only_owners_can_edit_the_email_layout() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    let response = app.post_email_layout(&layout()).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(app.get_email_layout_preview().await.status().as_u16(), 403);
+}
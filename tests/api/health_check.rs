@@ -1,4 +1,10 @@
-use crate::helpers::spawn_app;
+use newsletter::configuration::{SessionStoreBackend, Settings};
+use newsletter::health::{WorkerKind, record_heartbeat};
+use newsletter::session_cleaner_worker::run_session_cleaner_until_stopped;
+use sqlx::{Connection, Executor, PgConnection};
+use tokio_util::sync::CancellationToken;
+
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
 
 // `tokio::test`is the testing equivalent of `tokio::main`.
 // It also spares you from having to specify the `#[test]` attribute.
@@ -13,7 +19,7 @@ async fn health_check_works() {
 
     // Act
     let response = client
-        .get(&format!("{}/health_check", &app.address))
+        .get(format!("{}/health_check", &app.address))
         .send()
         .await
         .expect("Failed to execute request.");
@@ -22,3 +28,137 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn liveness_does_not_check_the_dependencies() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "alive");
+}
+
+#[tokio::test]
+async fn readiness_reports_each_dependency() {
+    let app = spawn_app().await;
+
+    let response = get_readiness(&app).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["dependencies"]["database"]["status"], "up");
+    assert!(body["dependencies"]["database"]["latency_ms"].is_number());
+    // the test app keeps its sessions in memory
+    assert!(body["dependencies"].get("redis").is_none());
+    // not checked unless enabled
+    assert!(body["dependencies"].get("email_provider").is_none());
+    // no worker runs alongside the test app
+    assert_eq!(body["workers"]["delivery_worker"]["status"], "unknown");
+    assert_eq!(body["workers"]["idempotency_cleaner"]["status"], "unknown");
+    assert_eq!(body["workers"]["session_cleaner"]["status"], "unknown");
+}
+
+#[tokio::test]
+async fn readiness_reports_the_last_heartbeat_of_the_workers() {
+    let app = spawn_app().await;
+    record_heartbeat(&app.db_pool, WorkerKind::DeliveryWorker)
+        .await
+        .unwrap();
+
+    let body: serde_json::Value = get_readiness(&app).await.json().await.unwrap();
+
+    assert_eq!(body["workers"]["delivery_worker"]["status"], "alive");
+    assert!(body["workers"]["delivery_worker"]["last_heartbeat"].is_string());
+    assert_eq!(body["workers"]["idempotency_cleaner"]["status"], "unknown");
+    assert_eq!(body["workers"]["session_cleaner"]["status"], "unknown");
+}
+
+#[tokio::test]
+async fn the_session_cleaner_reports_its_heartbeat() {
+    let mut settings: Option<Settings> = None;
+    let app = spawn_app_with(|c| settings = Some(c.clone())).await;
+    let shutdown = CancellationToken::new();
+    let cleaner = tokio::spawn(run_session_cleaner_until_stopped(
+        settings.unwrap(),
+        shutdown.clone(),
+    ));
+
+    let mut body = serde_json::Value::Null;
+    for _ in 0..50 {
+        body = get_readiness(&app).await.json().await.unwrap();
+        if body["workers"]["session_cleaner"]["status"] == "alive" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    shutdown.cancel();
+    cleaner.await.unwrap().unwrap();
+
+    assert_eq!(body["workers"]["session_cleaner"]["status"], "alive");
+    assert!(body["workers"]["session_cleaner"]["last_heartbeat"].is_string());
+}
+
+#[tokio::test]
+async fn readiness_fails_when_the_database_is_down() {
+    let app = spawn_app().await;
+    let options = app.db_pool.connect_options();
+    let database_name = options.get_database().unwrap().to_string();
+    let mut connection = PgConnection::connect_with(&options.as_ref().clone().database("postgres"))
+        .await
+        .unwrap();
+    connection
+        .execute(format!(r#"DROP DATABASE "{database_name}" WITH (FORCE);"#).as_str())
+        .await
+        .unwrap();
+
+    let response = get_readiness(&app).await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["dependencies"]["database"]["status"], "down");
+}
+
+#[tokio::test]
+async fn readiness_checks_redis_when_it_stores_the_sessions() {
+    let app = spawn_app_with(|c| c.session_store.backend = SessionStoreBackend::Redis).await;
+
+    let response = get_readiness(&app).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["dependencies"]["redis"]["status"], "up");
+    assert!(body["dependencies"]["redis"]["latency_ms"].is_number());
+}
+
+#[tokio::test]
+async fn readiness_fails_when_the_email_provider_is_unreachable() {
+    let app = spawn_app_with(|c| {
+        c.health.check_email_provider = true;
+        c.email_client.base_url = "http://127.0.0.1:1".into();
+    })
+    .await;
+
+    let response = get_readiness(&app).await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["dependencies"]["email_provider"]["status"], "down");
+    assert_eq!(body["dependencies"]["database"]["status"], "up");
+}
+
+async fn get_readiness(app: &TestApp) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
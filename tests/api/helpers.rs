@@ -4,28 +4,68 @@ use argon2::Params;
 use argon2::PasswordHasher;
 use argon2::Version;
 use argon2::password_hash::SaltString;
-use newsletter::configuration::{DatabaseSettings, get_configuration};
+use newsletter::configuration::{
+    AnonymousQuotaSettings, DatabaseSettings, IdempotencySettings, SessionStoreBackend, Settings,
+    get_configuration,
+};
 use newsletter::email_client::EmailClient;
 use newsletter::idempotency_cleaner_worker::IdempotentExecutionOutcome;
 use newsletter::idempotency_cleaner_worker::delete_expired_idempotent_entries;
 use newsletter::issue_delivery_worker::ExecutionOutcome;
 use newsletter::issue_delivery_worker::try_execute_task;
 use newsletter::startup::{Application, get_connection_pool};
-use newsletter::telemetry::{get_subscriber, init_subscriber};
+use newsletter::telemetry::{get_subscriber, init_subscriber, otlp_tracer};
 use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// Stands in for an OpenTelemetry collector, for the tests of trace propagation.
+// It's started outside of the runtime of the first test to need it, which stops with the test
+pub static COLLECTOR: Lazy<MockServer> = Lazy::new(|| {
+    std::thread::spawn(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let collector = MockServer::start().await;
+                Mock::given(method("POST"))
+                    .and(path("/v1/traces"))
+                    .respond_with(ResponseTemplate::new(200))
+                    .mount(&collector)
+                    .await;
+                collector
+            })
+    })
+    .join()
+    .unwrap()
+});
 
 // Ensure that the `tracing` stack is only initialized once using `once_cell`
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    let mut telemetry = get_configuration()
+        .expect("Failed to read configuration.")
+        .telemetry;
+    telemetry.otlp_endpoint = Some(COLLECTOR.uri());
+    // the test apps never start a trace: only the ones continued from a sampled `traceparent`, which
+    // the tests of trace propagation send, are exported
+    telemetry.sampling_ratio = 0.0;
+    let tracer = otlp_tracer(&telemetry).expect("Failed to build the OTLP tracer.");
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            tracer,
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber =
+            get_subscriber(subscriber_name, default_filter_level, std::io::sink, tracer);
         init_subscriber(subscriber);
     }
 });
@@ -33,6 +73,7 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 pub struct TestApp {
     pub address: String,
     pub port: u16,
+    pub metrics_port: Option<u16>,
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub test_user: TestUser,
@@ -44,6 +85,7 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: String,
 }
 
 pub struct ConfirmationLinks {
@@ -54,7 +96,7 @@ pub struct ConfirmationLinks {
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -78,8 +120,8 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 
@@ -88,7 +130,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
@@ -97,7 +139,7 @@ impl TestApp {
 
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -108,7 +150,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -118,9 +160,41 @@ impl TestApp {
         self.get_admin_dashboard().await.text().await.unwrap()
     }
 
+    // The token embedded in the admin forms of the current session, empty if there is none.
+    // Users who must set up two-factor authentication are sent to its page, which has a form too.
+    pub async fn csrf_token(&self) -> String {
+        for page in ["/admin/dashboard", "/admin/two-factor"] {
+            let html = self
+                .api_client
+                .get(format!("{}{}", &self.address, page))
+                .send()
+                .await
+                .expect("Failed to execute request.")
+                .text()
+                .await
+                .unwrap();
+            let prefix = r#"name="csrf_token" value=""#;
+            if let Some(start) = html.find(prefix).map(|i| i + prefix.len()) {
+                let end = start + html[start..].find('"').unwrap();
+                return html[start..end].to_string();
+            }
+        }
+        String::new()
+    }
+
+    // Submits the form like a browser would from an admin page
+    pub async fn with_csrf_token<Body>(&self, body: &Body) -> serde_json::Value
+    where
+        Body: serde::Serialize,
+    {
+        let mut body = serde_json::to_value(body).unwrap();
+        body["csrf_token"] = self.csrf_token().await.into();
+        body
+    }
+
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -131,8 +205,8 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
-            .form(body)
+            .post(format!("{}/admin/password", &self.address))
+            .form(&self.with_csrf_token(body).await)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -144,7 +218,8 @@ impl TestApp {
 
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -152,7 +227,7 @@ impl TestApp {
 
     pub async fn get_publish_newsletter(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/newsletters", &self.address))
+            .get(format!("{}/admin/newsletters", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -167,17 +242,246 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/newsletters", &self.address))
+            .post(format!("{}/admin/newsletters", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_invite_user(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/invitations", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_invite_user_html(&self) -> String {
+        self.get_invite_user().await.text().await.unwrap()
+    }
+
+    pub async fn post_invite_user<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/invitations", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_accept_invitation<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/invitations/accept", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_forgot_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/forgot-password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_reset_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/reset-password", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_two_factor_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/two-factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_enable_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/two-factor", &self.address))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "code": code }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_disable_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/two-factor/disable", &self.address))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "code": code }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_two_factor_login(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/two-factor", &self.address))
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_settings<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/settings", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_email_layout<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/email-layout", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_email_layout_preview(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/email-layout/preview", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Creates a token for the logged in user from the admin UI, which shows it once
+    pub async fn create_api_token(&self, name: &str) -> String {
+        let html_page = self
+            .api_client
+            .post(format!("{}/admin/api-tokens", &self.address))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "name": name }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap();
+        let start = html_page.find("<code>").expect("The token is not shown") + "<code>".len();
+        let end = start + html_page[start..].find("</code>").unwrap();
+        html_page[start..end].to_string()
+    }
+
+    // A request to the JSON API, authenticated with an API token rather than a session
+    pub fn api_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        token: &str,
+    ) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}/api/v1{}", &self.address, path))
+            .bearer_auth(token)
+    }
+
+    // A separate browser, with its own cookies
+    pub fn another_api_client(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap()
+    }
+
+    pub async fn get_sessions_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_revoke_session(&self, session_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/sessions/revoke", &self.address))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "session_id": session_id }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_revoke_other_sessions(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/sessions/revoke-others", &self.address))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_audit_log(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_audit_log_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit/export?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn dispatch_all_pending_emails(&self) {
         loop {
             if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
+                try_execute_task(&self.db_pool, &self.email_client, &self.address)
                     .await
                     .unwrap()
             {
@@ -186,10 +490,21 @@ impl TestApp {
         }
     }
 
+    // with a retention of one second for every endpoint
     pub async fn clean_all_expired_idempotent_entries(&self) {
+        let retention = IdempotencySettings {
+            retention_seconds: 1,
+            endpoint_retention_seconds: Default::default(),
+            anonymous_quota: AnonymousQuotaSettings {
+                per_client: 0,
+                total: 0,
+            },
+            cleanup_idle_interval_milliseconds: 10000,
+            cleanup_error_backoff_milliseconds: 1000,
+        };
         loop {
             if let IdempotentExecutionOutcome::EmptyTable =
-                delete_expired_idempotent_entries(&self.db_pool, 1.into())
+                delete_expired_idempotent_entries(&self.db_pool, &retention)
                     .await
                     .unwrap()
             {
@@ -201,10 +516,15 @@ impl TestApp {
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::generate_with_role("owner")
+    }
+
+    pub fn generate_with_role(role: &str) -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role: role.into(),
         }
     }
 
@@ -216,7 +536,7 @@ impl TestUser {
         .await;
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::new(
             Algorithm::Argon2id,
@@ -228,10 +548,11 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, role) VALUES ($1, $2, $3, $4)",
             self.user_id,
             self.username,
             password_hash,
+            self.role,
         )
         .execute(pool)
         .await
@@ -240,6 +561,11 @@ impl TestUser {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+// For the tests of settings which are not the ones of the test configuration
+pub async fn spawn_app_with(customize: impl FnOnce(&mut Settings)) -> TestApp {
     // setup tracing
     Lazy::force(&TRACING);
 
@@ -252,6 +578,17 @@ pub async fn spawn_app() -> TestApp {
         c.database.database_name = Uuid::new_v4().to_string(); // Use a different database for each test case
         c.application.port = 0; // Use a random OS port
         c.email_client.base_url = email_server.uri();
+        // keep failed login tests fast
+        c.login_throttling.base_delay_milliseconds = 1;
+        c.login_throttling.max_delay_milliseconds = 10;
+        c.login_throttling.max_attempts_per_ip = 20;
+        // the tests storing sessions in Redis share it, each app counts failed logins apart
+        c.login_throttling.key_prefix = format!("login_failures:{}", Uuid::new_v4());
+        // the client IPs below are forwarded by the test client
+        c.application.trusted_proxies = vec!["127.0.0.1/32".parse().unwrap()];
+        // no Redis needed, failed logins are then counted in the test database
+        c.session_store.backend = SessionStoreBackend::Memory;
+        customize(&mut c);
         c
     };
 
@@ -264,23 +601,35 @@ pub async fn spawn_app() -> TestApp {
         .await
         .expect("Failed to build the application.");
     let application_port = application.port();
+    let metrics_port = application.metrics_port();
     let address = format!("http://127.0.0.1:{}", application_port);
-    let _ = tokio::spawn(application.run_until_stopped());
-
+    tokio::spawn(application.run_until_stopped());
+
+    // a client IP, as forwarded by a reverse proxy, for the audit log and the per-IP limits
+    let client_ip = format!(
+        "10.{}.{}.{}",
+        rand::random::<u8>(),
+        rand::random::<u8>(),
+        rand::random::<u8>()
+    );
+    let mut default_headers = reqwest::header::HeaderMap::new();
+    default_headers.insert("X-Forwarded-For", client_ip.parse().unwrap());
     let client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
         .cookie_store(true)
+        .default_headers(default_headers)
         .build()
         .unwrap();
 
     let test_app = TestApp {
         address,
         port: application_port,
+        metrics_port,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
         test_user: TestUser::generate(),
         api_client: client,
-        email_client: configuration.email_client.client(),
+        email_client: configuration.email_client.client().unwrap(),
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
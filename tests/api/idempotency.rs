@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use newsletter::{
+    configuration::{AnonymousQuotaSettings, IdempotencySettings},
+    idempotency::IdempotentEndpoint,
+    idempotency_cleaner_worker::{IdempotentExecutionOutcome, delete_expired_idempotent_entries},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::helpers::{TestUser, spawn_app};
+
+async fn insert_entry(pool: &PgPool, user_id: Uuid, endpoint: &str, key: &str, age_seconds: f64) {
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, endpoint, idempotency_key, created_at)
+        VALUES ($1, $2, $3, now() - ($4 * INTERVAL '1 second'))
+        "#,
+        user_id,
+        endpoint,
+        key,
+        age_seconds,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+async fn remaining_entries(pool: &PgPool) -> Vec<(Option<Uuid>, String)> {
+    sqlx::query!("SELECT user_id, endpoint FROM idempotency ORDER BY created_at")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.user_id, r.endpoint))
+        .collect()
+}
+
+const QUOTA: AnonymousQuotaSettings = AnonymousQuotaSettings {
+    per_client: 20,
+    total: 100,
+};
+
+async fn clean(pool: &PgPool, settings: &IdempotencySettings) {
+    while let IdempotentExecutionOutcome::TaskCompleted =
+        delete_expired_idempotent_entries(pool, settings)
+            .await
+            .unwrap()
+    {}
+}
+
+#[tokio::test]
+async fn the_cleaner_only_deletes_the_expired_key_of_a_user() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    let settings = IdempotencySettings {
+        retention_seconds: 3600,
+        endpoint_retention_seconds: HashMap::new(),
+        anonymous_quota: QUOTA,
+        cleanup_idle_interval_milliseconds: 10000,
+        cleanup_error_backoff_milliseconds: 1000,
+    };
+    // the same key, expired for one user only
+    insert_entry(
+        &app.db_pool,
+        app.test_user.user_id,
+        "create_issue",
+        "key",
+        7200.,
+    )
+    .await;
+    insert_entry(&app.db_pool, other_user.user_id, "create_issue", "key", 0.).await;
+
+    // Act
+    clean(&app.db_pool, &settings).await;
+
+    // Assert
+    assert_eq!(
+        remaining_entries(&app.db_pool).await,
+        [(Some(other_user.user_id), "create_issue".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn each_endpoint_has_its_own_retention() {
+    // Arrange
+    let app = spawn_app().await;
+    let settings = IdempotencySettings {
+        retention_seconds: 3600,
+        endpoint_retention_seconds: HashMap::from([(IdempotentEndpoint::CreateIssue, 86400)]),
+        anonymous_quota: QUOTA,
+        cleanup_idle_interval_milliseconds: 10000,
+        cleanup_error_backoff_milliseconds: 1000,
+    };
+    let user_id = app.test_user.user_id;
+    insert_entry(&app.db_pool, user_id, "publish_newsletter", "key", 7200.).await;
+    insert_entry(&app.db_pool, user_id, "create_issue", "key", 7200.).await;
+    // saved before keys were scoped by endpoint
+    insert_entry(&app.db_pool, user_id, "", "key", 7200.).await;
+
+    // Act
+    clean(&app.db_pool, &settings).await;
+
+    // Assert
+    assert_eq!(
+        remaining_entries(&app.db_pool).await,
+        [(Some(user_id), "create_issue".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn the_cleaner_deletes_the_expired_keys_of_anonymous_clients() {
+    // Arrange
+    let app = spawn_app().await;
+    let settings = IdempotencySettings {
+        retention_seconds: 3600,
+        endpoint_retention_seconds: HashMap::new(),
+        anonymous_quota: QUOTA,
+        cleanup_idle_interval_milliseconds: 10000,
+        cleanup_error_backoff_milliseconds: 1000,
+    };
+    // the same key, expired for one client only
+    for (client, age_seconds) in [("client-a", 7200.), ("client-b", 0.)] {
+        sqlx::query!(
+            r#"
+            INSERT INTO idempotency (client_fingerprint, endpoint, idempotency_key, created_at)
+            VALUES ($1, 'subscribe', 'key', now() - ($2 * INTERVAL '1 second'))
+            "#,
+            client,
+            age_seconds,
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+
+    // Act
+    clean(&app.db_pool, &settings).await;
+
+    // Assert
+    let remaining = sqlx::query_scalar!("SELECT client_fingerprint FROM idempotency")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining, [Some("client-b".to_string())]);
+}
@@ -0,0 +1,229 @@
+use uuid::Uuid;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, TestUser, assert_is_redirect_to, spawn_app};
+
+// Invite a new editor as the (owner) test user and return the token sent by email
+async fn invite_editor(app: &TestApp) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Send invitation")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_invite_user(&serde_json::json!({
+            "email": "new-editor@example.com",
+            "role": "editor",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/invitations");
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let links = app.get_confirmation_links(email_request);
+    assert_eq!(links.html.path(), "/invitations/accept");
+    links
+        .html
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[tokio::test]
+async fn you_must_be_an_owner_to_see_the_invitation_form() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    let response = app.get_invite_user().await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn you_must_be_an_owner_to_invite_a_user() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    let response = app
+        .post_invite_user(&serde_json::json!({
+            "email": "new-editor@example.com",
+            "role": "owner",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn invalid_emails_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_invite_user(&serde_json::json!({
+            "email": "not-an-email",
+            "role": "viewer",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/invitations");
+
+    let html_page = app.get_invite_user_html().await;
+    assert!(html_page.contains("<p><i>not-an-email is not a valid subscriber email.</i></p>"));
+}
+
+#[tokio::test]
+async fn an_invited_user_can_choose_credentials_and_log_in() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = invite_editor(&app).await;
+    let html_page = app.get_invite_user_html().await;
+    assert!(
+        html_page.contains("<p><i>An invitation has been sent to new-editor@example.com.</i></p>")
+    );
+
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "token": &token,
+            "username": &username,
+            "new_password": &password,
+            "new_password_check": &password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &username,
+            "password": &password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let saved = sqlx::query!(
+        "SELECT role, email FROM users WHERE username = $1",
+        username
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.role, "editor");
+    assert_eq!(saved.email.as_deref(), Some("new-editor@example.com"));
+}
+
+#[tokio::test]
+async fn an_invitation_can_only_be_accepted_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = invite_editor(&app).await;
+
+    for expected_users in [2, 2] {
+        let password = Uuid::new_v4().to_string();
+        app.post_accept_invitation(&serde_json::json!({
+            "token": &token,
+            "username": Uuid::new_v4().to_string(),
+            "new_password": &password,
+            "new_password_check": &password,
+        }))
+        .await;
+
+        let n_users = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM users"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+        assert_eq!(n_users, expected_users);
+    }
+}
+
+#[tokio::test]
+async fn expired_invitations_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = invite_editor(&app).await;
+    sqlx::query!("UPDATE invitations SET expires_at = now() - INTERVAL '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let password = Uuid::new_v4().to_string();
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "token": &token,
+            "username": Uuid::new_v4().to_string(),
+            "new_password": &password,
+            "new_password_check": &password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>This invitation is invalid or has expired.</i></p>"));
+}
+
+#[tokio::test]
+async fn invitees_passwords_follow_the_change_password_rules() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = invite_editor(&app).await;
+
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "token": &token,
+            "username": Uuid::new_v4().to_string(),
+            "new_password": "too-short",
+            "new_password_check": "too-short",
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, &format!("/invitations/accept?token={}", token));
+    let html_page = app
+        .api_client
+        .get(format!(
+            "{}/invitations/accept?token={}",
+            app.address, token
+        ))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>New password should be greater than 12 characters.</i></p>"));
+}
+
+#[tokio::test]
+async fn flash_messages_are_escaped() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_invite_user(&serde_json::json!({
+        "email": "<script>alert(1)</script>",
+        "role": "viewer",
+    }))
+    .await;
+
+    let html_page = app.get_invite_user_html().await;
+    assert!(
+        html_page
+            .contains("&#60;script&#62;alert(1)&#60;/script&#62; is not a valid subscriber email.")
+    );
+    assert!(!html_page.contains("<script>"));
+}
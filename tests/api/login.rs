@@ -1,4 +1,6 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use newsletter::configuration::SessionStoreBackend;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -37,3 +39,146 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+#[tokio::test]
+async fn a_username_is_locked_out_after_too_many_failed_attempts() {
+    let app = spawn_app().await;
+    let wrong_credentials = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password",
+    });
+    for _ in 0..10 {
+        app.post_login(&wrong_credentials).await;
+    }
+
+    // even the right password is rejected during the lockout
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(
+        html_page
+            .contains("<p><i>Too many failed login attempts, try again in 15 minutes.</i></p>")
+    );
+}
+
+#[tokio::test]
+async fn an_ip_address_is_locked_out_after_too_many_failed_attempts() {
+    let app = spawn_app().await;
+    // the test configuration allows 20 failed attempts per IP address
+    for _ in 0..20 {
+        app.post_login(&serde_json::json!({
+            "username": uuid::Uuid::new_v4().to_string(),
+            "password": "wrong-password",
+        }))
+        .await;
+    }
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn concurrent_attempts_cannot_get_past_the_limit() {
+    let app = spawn_app().await;
+    let mut attempts = tokio::task::JoinSet::new();
+    for _ in 0..30 {
+        let client = app.api_client.clone();
+        let url = format!("{}/login", app.address);
+        let form = [
+            ("username", app.test_user.username.clone()),
+            ("password", "wrong-password".to_string()),
+        ];
+        attempts.spawn(async move { client.post(url).form(&form).send().await.unwrap() });
+    }
+    attempts.join_all().await;
+
+    // the test configuration allows 10 failed attempts per username
+    let failures = sqlx::query_scalar!(
+        "SELECT failures FROM login_failure_counters WHERE counter_key LIKE '%:username:%'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(failures, 10);
+}
+
+#[tokio::test]
+async fn failed_attempts_are_counted_in_redis_when_it_stores_the_sessions() {
+    let app = spawn_app_with(|c| c.session_store.backend = SessionStoreBackend::Redis).await;
+    for _ in 0..10 {
+        app.post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "wrong-password",
+        }))
+        .await;
+    }
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+    let n_counters =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM login_failure_counters"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_counters, 0);
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failed_attempts_of_the_username() {
+    let app = spawn_app().await;
+    let wrong_credentials = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password",
+    });
+    let right_credentials = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+
+    for _ in 0..2 {
+        for _ in 0..9 {
+            app.post_login(&wrong_credentials).await;
+        }
+        let response = app.post_login(&right_credentials).await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+}
+
+#[tokio::test]
+async fn failed_attempts_are_recorded() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": "unknown-user",
+        "password": "wrong-password",
+    }))
+    .await;
+
+    let attempt = sqlx::query!(
+        "SELECT target, ip_address, details FROM audit_log WHERE action = 'login_failed'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(attempt.target.as_deref(), Some("unknown-user"));
+    assert!(attempt.ip_address.unwrap().starts_with("10."));
+    assert_eq!(attempt.details.as_deref(), Some("invalid_credentials"));
+}
@@ -1,12 +1,26 @@
 mod health_check;
 mod helpers;
+mod invitations;
 mod subscriptions;
 mod subscriptions_confirm;
 
 mod admin_dashboard;
+mod admin_users;
+mod api_tokens;
+mod audit;
 mod change_password;
+mod csrf;
+mod email_layout;
+mod idempotency;
 mod login;
+mod metrics;
 mod newsletter;
+mod openapi;
+mod password_reset;
+mod rest_api;
+mod sessions;
+mod traces;
+mod two_factor;
 
 // structuring test as single test executable with scoped submodules for each test.
 // Each submodule can be broken down further when it grows like tests/api/subscriptions/*.rs
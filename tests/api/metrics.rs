@@ -0,0 +1,153 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app, spawn_app_with};
+
+async fn get_metrics(address: &str) -> reqwest::Response {
+    reqwest::get(format!("{}/metrics", address))
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn metrics_text(app: &TestApp) -> String {
+    let response = get_metrics(&app.address).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.text().await.unwrap()
+}
+
+// The recorder is shared by all the apps of the test process: counters can only be asserted on
+// by the presence of their series
+fn has_series(metrics: &str, name: &str, labels: &[&str]) -> bool {
+    metrics.lines().any(|line| {
+        line.starts_with(&format!("{name}{{")) && labels.iter().all(|label| line.contains(label))
+    })
+}
+
+#[tokio::test]
+async fn http_requests_are_measured_by_route_and_status() {
+    // Arrange
+    let app = spawn_app().await;
+    reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+    reqwest::get(format!("{}/does-not-exist", &app.address))
+        .await
+        .unwrap();
+
+    // Act
+    let response = get_metrics(&app.address).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(
+        response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain")
+    );
+    let metrics = response.text().await.unwrap();
+    let health_check = [
+        r#"method="GET""#,
+        r#"route="/health_check""#,
+        r#"status="200""#,
+    ];
+    assert!(has_series(&metrics, "http_requests_total", &health_check));
+    assert!(has_series(
+        &metrics,
+        "http_request_duration_seconds_bucket",
+        &health_check
+    ));
+    assert!(has_series(
+        &metrics,
+        "http_requests_total",
+        &[r#"route="unmatched""#, r#"status="404""#]
+    ));
+    assert!(metrics.contains("\nissue_delivery_queue_depth "));
+    assert!(has_series(
+        &metrics,
+        "db_pool_max_connections",
+        &[r#"pool="api""#]
+    ));
+}
+
+#[tokio::test]
+async fn deliveries_and_login_failures_are_measured() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.test_user.login(&app).await;
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+    app.post_logout().await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password",
+    }))
+    .await;
+
+    // Act
+    let metrics = metrics_text(&app).await;
+
+    // Assert
+    assert!(has_series(
+        &metrics,
+        "emails_sent_total",
+        &[r#"provider="127.0.0.1""#]
+    ));
+    assert!(has_series(
+        &metrics,
+        "issue_delivery_latency_seconds_bucket",
+        &[]
+    ));
+    assert!(has_series(
+        &metrics,
+        "login_failures_total",
+        &[r#"reason="invalid_credentials""#]
+    ));
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_a_separate_port() {
+    // Arrange
+    let app = spawn_app_with(|c| c.application.metrics_port = Some(0)).await;
+    let metrics_address = format!("http://127.0.0.1:{}", app.metrics_port.unwrap());
+
+    // Act
+    let public = get_metrics(&app.address).await;
+    let private = get_metrics(&metrics_address).await;
+
+    // Assert
+    assert_eq!(public.status().as_u16(), 404);
+    assert_eq!(private.status().as_u16(), 200);
+    assert!(
+        private
+            .text()
+            .await
+            .unwrap()
+            .contains("issue_delivery_queue_depth")
+    );
+}
@@ -2,19 +2,24 @@ use fake::{
     Fake,
     faker::{internet::en::SafeEmail, name::en::Name},
 };
+use newsletter::{configuration::Settings, issue_delivery_worker::run_worker_until_stopped};
+use std::collections::HashMap;
 use tokio::time::{Duration, sleep};
+use tokio_util::sync::CancellationToken;
 use wiremock::{
     Mock, ResponseTemplate,
     matchers::{any, method, path},
 };
 
-use crate::helpers::{ConfirmationLinks, TestApp, assert_is_redirect_to, spawn_app};
+use crate::helpers::{
+    ConfirmationLinks, TestApp, TestUser, assert_is_redirect_to, spawn_app, spawn_app_with,
+};
 
 // helper functions to drive application state for tests
 async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
     let name: String = Name().fake();
     let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(&serde_json::json!({
+    let body = serde_urlencoded::to_string(serde_json::json!({
         "name": name,
         "email": email
     }))
@@ -28,7 +33,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .mount_as_scoped(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into())
+    app.post_subscriptions(body)
         .await
         .error_for_status()
         .unwrap();
@@ -41,7 +46,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .pop()
         .unwrap();
 
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
 }
 
 async fn create_confirmed_susbcriber(app: &TestApp) {
@@ -113,6 +118,76 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     app.dispatch_all_pending_emails().await;
 }
 
+#[tokio::test]
+async fn a_pool_of_workers_delivers_each_email_exactly_once() {
+    let mut settings: Option<Settings> = None;
+    let app = spawn_app_with(|c| {
+        c.delivery_worker.pool_size = 4;
+        settings = Some(c.clone());
+    })
+    .await;
+    for _ in 0..10 {
+        create_confirmed_susbcriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+
+    // slow enough for the workers to deliver concurrently
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(100)))
+        .expect(10)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act
+    let shutdown = CancellationToken::new();
+    let workers = tokio::spawn(run_worker_until_stopped(
+        settings.unwrap(),
+        shutdown.clone(),
+    ));
+    for _ in 0..100 {
+        let pending = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+        if pending == 0 {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    shutdown.cancel();
+    workers.await.unwrap().unwrap();
+
+    // Assert
+    let mut deliveries = HashMap::new();
+    for request in app.email_server.received_requests().await.unwrap() {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        if body["Subject"] == "Newsletter title" {
+            *deliveries
+                .entry(body["To"].as_str().unwrap().to_string())
+                .or_insert(0) += 1;
+        }
+    }
+    let subscribers = sqlx::query!("SELECT email FROM subscriptions WHERE status = 'confirmed'")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(deliveries.len(), subscribers.len());
+    for subscriber in subscribers {
+        assert_eq!(deliveries.get(&subscriber.email), Some(&1));
+    }
+}
+
 #[tokio::test]
 async fn you_must_be_logged_in_to_see_the_newsletter_form() {
     let app = spawn_app().await;
@@ -138,6 +213,71 @@ async fn you_must_be_logged_in_to_publish_a_newsletter() {
     assert_is_redirect_to(&response, "/login");
 }
 
+#[tokio::test]
+async fn viewers_cannot_see_the_newsletter_form() {
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    let response = app.get_publish_newsletter().await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn viewers_cannot_publish_a_newsletter() {
+    let app = spawn_app().await;
+    create_confirmed_susbcriber(&app).await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn editors_can_publish_a_newsletter() {
+    let app = spawn_app().await;
+    create_confirmed_susbcriber(&app).await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+}
+
 #[tokio::test]
 async fn newsletter_creation_is_idempotent() {
     let app = spawn_app().await;
@@ -243,3 +383,89 @@ async fn newsletter_issues_resubmit_after_idempotent_time_interval() {
     ));
     app.dispatch_all_pending_emails().await;
 }
+
+#[tokio::test]
+async fn the_idempotency_key_can_be_sent_in_a_header() {
+    let app = spawn_app().await;
+    create_confirmed_susbcriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+    });
+    let idempotency_key = format!("\"{}\"", uuid::Uuid::new_v4());
+    for _ in 0..2 {
+        let response = app
+            .api_client
+            .post(format!("{}/admin/newsletters", &app.address))
+            .header("Idempotency-Key", &idempotency_key)
+            .form(&app.with_csrf_token(&newsletter_request_body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_is_redirect_to(&response, "/admin/newsletters");
+    }
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn an_idempotency_key_is_required() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_another_issue_is_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_susbcriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": idempotency_key,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Another newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": idempotency_key,
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 422);
+    app.dispatch_all_pending_emails().await;
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeSet;
+
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
+
+// (method, path) of every documented operation
+async fn documented_routes(app: &TestApp) -> BTreeSet<(String, String)> {
+    let response = reqwest::Client::new()
+        .get(format!("{}/api/openapi.json", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+    let description: serde_json::Value = response.json().await.unwrap();
+    assert!(description["openapi"].as_str().unwrap().starts_with("3."));
+    description["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .flat_map(|(path, operations)| {
+            operations
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(move |method| (method.to_uppercase(), path.clone()))
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn the_documented_routes_are_served() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Act
+    let documented = documented_routes(&app).await;
+
+    // Assert
+    for route in [
+        ("GET", "/"),
+        ("POST", "/subscriptions"),
+        ("GET", "/admin/newsletters"),
+        ("GET", "/admin/email-layout/preview"),
+        ("PATCH", "/api/v1/subscribers/{subscriber_id}"),
+        ("GET", "/metrics"),
+    ] {
+        assert!(
+            documented.contains(&(route.0.to_string(), route.1.to_string())),
+            "{route:?} is not documented"
+        );
+    }
+    for (method, path) in documented {
+        let url = format!("{}{}", &app.address, path)
+            .replace("{subscriber_id}", &Uuid::new_v4().to_string())
+            .replace("{issue_id}", &Uuid::new_v4().to_string());
+        let response = client
+            .request(method.parse().unwrap(), url)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert!(
+            ![404, 405].contains(&response.status().as_u16()),
+            "{method} {path} is documented but not served"
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_metrics_are_not_documented_when_they_have_their_own_port() {
+    // Arrange
+    let app = spawn_app_with(|c| c.application.metrics_port = Some(0)).await;
+
+    // Act
+    let documented = documented_routes(&app).await;
+
+    // Assert
+    assert!(!documented.iter().any(|(_, path)| path == "/metrics"));
+    assert!(documented.contains(&("GET".to_string(), "/health/ready".to_string())));
+}
+
+#[tokio::test]
+async fn the_forms_and_errors_are_described() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let description: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/api/openapi.json", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    let subscribe = &description["paths"]["/subscriptions"]["post"];
+    let body = &subscribe["requestBody"]["content"]["application/x-www-form-urlencoded"];
+    assert_eq!(body["schema"]["$ref"], "#/components/schemas/SubscribeForm");
+    for status in ["200", "400", "500"] {
+        assert!(subscribe["responses"][status].is_object());
+    }
+    let schemas = &description["components"]["schemas"];
+    assert!(schemas["SubscribeForm"]["properties"]["email"].is_object());
+    assert!(schemas["PublishNewsletterForm"]["properties"]["idempotency_key"].is_object());
+    assert_eq!(
+        schemas["LoginForm"]["properties"]["password"]["format"],
+        "password"
+    );
+    let get_issue = &description["paths"]["/api/v1/issues/{issue_id}"]["get"];
+    assert_eq!(get_issue["parameters"][0]["name"], "issue_id");
+    assert_eq!(
+        get_issue["responses"]["404"]["content"]["application/json"]["schema"]["$ref"],
+        "#/components/schemas/ErrorBody"
+    );
+    assert!(description["components"]["securitySchemes"]["api_token"].is_object());
+}
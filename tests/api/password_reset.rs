@@ -0,0 +1,257 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{any, method, path},
+};
+
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app};
+
+const RESET_LINK_SENT: &str = "<p><i>If this account has an email address, \
+    a link to reset its password has been sent to it.</i></p>";
+
+async fn set_test_user_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = 'admin@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+// Ask for a reset link for the test user and return the token sent by email
+async fn request_reset_token(app: &TestApp) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Send password reset link")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_forgot_password(&serde_json::json!({
+            "username": &app.test_user.username,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let email_request = &wait_for_emails(app, 1).await.pop().unwrap();
+    let links = app.get_confirmation_links(email_request);
+    assert_eq!(links.html.path(), "/reset-password");
+    links
+        .html
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+// The email is sent after the response
+async fn wait_for_emails(app: &TestApp, count: usize) -> Vec<wiremock::Request> {
+    for _ in 0..100 {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if requests.len() >= count {
+            return requests;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{} emails were expected", count);
+}
+
+#[tokio::test]
+async fn the_response_does_not_reveal_whether_a_username_exists() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    for username in [app.test_user.username.clone(), Uuid::new_v4().to_string()] {
+        let response = app
+            .post_forgot_password(&serde_json::json!({ "username": username }))
+            .await;
+        assert_is_redirect_to(&response, "/login");
+
+        let html_page = app.get_login_html().await;
+        assert!(html_page.contains(RESET_LINK_SENT));
+    }
+    wait_for_emails(&app, 1).await;
+}
+
+#[tokio::test]
+async fn the_response_does_not_depend_on_the_email_being_sent() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_forgot_password(&serde_json::json!({ "username": &app.test_user.username }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+    wait_for_emails(&app, 1).await;
+}
+
+#[tokio::test]
+async fn a_user_can_log_in_with_the_password_they_reset() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let token = request_reset_token(&app).await;
+
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": &token,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your password has been reset, you can now log in.</i></p>"));
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_reset_link_can_only_be_used_once() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let token = request_reset_token(&app).await;
+
+    let new_password = Uuid::new_v4().to_string();
+    let body = serde_json::json!({
+        "token": &token,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    });
+    app.post_reset_password(&body).await;
+    let response = app.post_reset_password(&body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(
+        html_page.contains("<p><i>This password reset link is invalid or has expired.</i></p>")
+    );
+}
+
+#[tokio::test]
+async fn a_second_request_within_the_cooldown_sends_no_other_link() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let token = request_reset_token(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_forgot_password(&serde_json::json!({
+            "username": &app.test_user.username,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // The email would be sent after the response
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let tokens = sqlx::query!("SELECT token_signature FROM password_reset_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.len(), 1);
+    // The first link still works
+    let new_password = Uuid::new_v4().to_string();
+    app.post_reset_password(&serde_json::json!({
+        "token": &token,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    }))
+    .await;
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("This password reset link is invalid or has expired."));
+}
+
+#[tokio::test]
+async fn expired_reset_links_are_rejected() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let token = request_reset_token(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - INTERVAL '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let new_password = Uuid::new_v4().to_string();
+    app.post_reset_password(&serde_json::json!({
+        "token": &token,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    }))
+    .await;
+
+    let html_page = app.get_login_html().await;
+    assert!(
+        html_page.contains("<p><i>This password reset link is invalid or has expired.</i></p>")
+    );
+}
+
+#[tokio::test]
+async fn new_passwords_must_match() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let token = request_reset_token(&app).await;
+
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": &token,
+            "new_password": Uuid::new_v4().to_string(),
+            "new_password_check": Uuid::new_v4().to_string(),
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, &format!("/reset-password?token={}", token));
+}
+
+#[tokio::test]
+async fn resetting_a_password_logs_out_existing_sessions() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    app.test_user.login(&app).await;
+    let token = request_reset_token(&app).await;
+
+    let new_password = Uuid::new_v4().to_string();
+    app.post_reset_password(&serde_json::json!({
+        "token": &token,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    }))
+    .await;
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,357 @@
+use reqwest::Method;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, spawn_app};
+
+async fn editor_token(app: &TestApp) -> String {
+    app.test_user.login(app).await;
+    app.create_api_token("CMS").await
+}
+
+async fn create_issue(app: &TestApp, token: &str) -> serde_json::Value {
+    let response = app
+        .api_request(Method::POST, "/issues", token)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    response.json().await.unwrap()
+}
+
+async fn create_subscriber(app: &TestApp, token: &str, confirmed: bool) -> reqwest::Response {
+    app.api_request(Method::POST, "/subscribers", token)
+        .json(&serde_json::json!({
+            "email": "ursula_le_guin@gmail.com",
+            "name": "le guin",
+            "confirmed": confirmed,
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn issues_are_drafts_until_they_are_published() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let token = editor_token(&app).await;
+    create_subscriber(&app, &token, true).await;
+
+    let issue = create_issue(&app, &token).await;
+    assert_eq!(issue["status"], "draft");
+    assert!(issue["published_at"].is_null());
+    let issue_path = format!("/issues/{}", issue["issue_id"].as_str().unwrap());
+    app.dispatch_all_pending_emails().await;
+
+    let response = app
+        .api_request(Method::POST, &format!("{issue_path}/publish"), &token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 202);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["status"], "delivering");
+    assert_eq!(issue["pending_deliveries"], 1);
+
+    app.dispatch_all_pending_emails().await;
+    let issue: serde_json::Value = app
+        .api_request(Method::GET, &issue_path, &token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["status"], "delivered");
+    assert!(issue["published_at"].is_string());
+}
+
+#[tokio::test]
+async fn an_issue_cannot_be_published_twice() {
+    let app = spawn_app().await;
+    let token = editor_token(&app).await;
+    let issue = create_issue(&app, &token).await;
+    let publish_path = format!("/issues/{}/publish", issue["issue_id"].as_str().unwrap());
+
+    let first = app
+        .api_request(Method::POST, &publish_path, &token)
+        .send()
+        .await
+        .unwrap();
+    let second = app
+        .api_request(Method::POST, &publish_path, &token)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(first.status().as_u16(), 202);
+    assert_eq!(second.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn retries_with_the_same_idempotency_key_get_the_same_response() {
+    let app = spawn_app().await;
+    let token = editor_token(&app).await;
+    let issue = create_issue(&app, &token).await;
+    let publish_path = format!("/issues/{}/publish", issue["issue_id"].as_str().unwrap());
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    let mut responses = Vec::new();
+    for _ in 0..2 {
+        let response = app
+            .api_request(Method::POST, &publish_path, &token)
+            .header("Idempotency-Key", &idempotency_key)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 202);
+        responses.push(response.text().await.unwrap());
+    }
+
+    assert_eq!(responses[0], responses[1]);
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_another_payload_is_rejected() {
+    let app = spawn_app().await;
+    let token = editor_token(&app).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    let mut statuses = Vec::new();
+    for title in ["Newsletter title", "Another newsletter title"] {
+        let response = app
+            .api_request(Method::POST, "/issues", &token)
+            .header("Idempotency-Key", &idempotency_key)
+            .json(&serde_json::json!({
+                "title": title,
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+            }))
+            .send()
+            .await
+            .unwrap();
+        statuses.push(response.status().as_u16());
+    }
+
+    assert_eq!(statuses, [201, 422]);
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_scoped_by_endpoint() {
+    let app = spawn_app().await;
+    let token = editor_token(&app).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    let response = app
+        .api_request(Method::POST, "/issues", &token)
+        .header("Idempotency-Key", &idempotency_key)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    let response = app
+        .api_request(
+            Method::POST,
+            &format!("/issues/{}/publish", issue["issue_id"].as_str().unwrap()),
+            &token,
+        )
+        .header("Idempotency-Key", &idempotency_key)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn unknown_issues_are_not_found() {
+    let app = spawn_app().await;
+    let token = editor_token(&app).await;
+
+    let response = app
+        .api_request(
+            Method::GET,
+            &format!("/issues/{}", uuid::Uuid::new_v4()),
+            &token,
+        )
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_managed_through_the_api() {
+    let app = spawn_app().await;
+    let token = editor_token(&app).await;
+
+    let response = create_subscriber(&app, &token, true).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["status"], "confirmed");
+    let subscriber_path = format!(
+        "/subscribers/{}",
+        subscriber["subscriber_id"].as_str().unwrap()
+    );
+
+    let found: serde_json::Value = app
+        .api_request(
+            Method::GET,
+            "/subscribers?email=ursula_le_guin%40gmail.com",
+            &token,
+        )
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(found, subscriber);
+
+    let updated: serde_json::Value = app
+        .api_request(Method::PATCH, &subscriber_path, &token)
+        .json(&serde_json::json!({ "name": "Ursula", "status": "unsubscribed" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(updated["name"], "Ursula");
+    assert_eq!(updated["status"], "unsubscribed");
+
+    let response = app
+        .api_request(Method::DELETE, &subscriber_path, &token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    let response = app
+        .api_request(Method::GET, &subscriber_path, &token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn new_subscribers_are_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let token = editor_token(&app).await;
+
+    let response = create_subscriber(&app, &token, false).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn a_retry_succeeds_once_the_confirmation_email_can_be_sent() {
+    let app = spawn_app().await;
+    let token = editor_token(&app).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let create = || {
+        app.api_request(Method::POST, "/subscribers", &token)
+            .header("Idempotency-Key", &idempotency_key)
+            .json(&serde_json::json!({
+                "email": "ursula_le_guin@gmail.com",
+                "name": "le guin",
+            }))
+            .send()
+    };
+
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        let response = create().await.unwrap();
+        assert_eq!(response.status().as_u16(), 500);
+    }
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = create().await.unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn invalid_requests_are_rejected_with_a_json_error() {
+    let app = spawn_app().await;
+    let token = editor_token(&app).await;
+    create_subscriber(&app, &token, true).await;
+
+    let duplicate = create_subscriber(&app, &token, true).await;
+    let invalid_email = app
+        .api_request(Method::POST, "/subscribers", &token)
+        .json(&serde_json::json!({ "email": "not-an-email", "name": "le guin" }))
+        .send()
+        .await
+        .unwrap();
+    let malformed = app
+        .api_request(Method::POST, "/issues", &token)
+        .header("Content-Type", "application/json")
+        .body("{\"title\":")
+        .send()
+        .await
+        .unwrap();
+
+    for (response, status) in [(duplicate, 409), (invalid_email, 400), (malformed, 400)] {
+        assert_eq!(response.status().as_u16(), status);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["error"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn stats_count_subscribers_and_issues() {
+    let app = spawn_app().await;
+    let token = editor_token(&app).await;
+    create_subscriber(&app, &token, true).await;
+    create_issue(&app, &token).await;
+
+    let stats: serde_json::Value = app
+        .api_request(Method::GET, "/stats", &token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(stats["subscribers"]["confirmed"], 1);
+    assert_eq!(stats["subscribers"]["pending_confirmation"], 0);
+    assert_eq!(stats["issues"]["drafts"], 1);
+    assert_eq!(stats["issues"]["published"], 0);
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use actix_session::storage::SessionStore;
+use actix_web::cookie::time::Duration;
+use newsletter::configuration::SessionStoreBackend;
+use newsletter::session_store::{PostgresSessionStore, delete_expired_sessions};
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, TestUser, assert_is_redirect_to, spawn_app, spawn_app_with};
+
+// Log the test user in from another browser, returning it along with the id of its session
+async fn log_in_elsewhere(app: &TestApp, user: &TestUser) -> (reqwest::Client, Uuid) {
+    let client = app.another_api_client();
+    let response = client
+        .post(format!("{}/login", app.address))
+        .header("User-Agent", "Stolen laptop")
+        .form(&serde_json::json!({
+            "username": &user.username,
+            "password": &user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let session_id =
+        sqlx::query!("SELECT session_id FROM user_sessions WHERE user_agent = 'Stolen laptop'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .session_id;
+    (client, session_id)
+}
+
+async fn dashboard_status(app: &TestApp, client: &reqwest::Client) -> u16 {
+    client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+#[tokio::test]
+async fn active_sessions_are_listed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    log_in_elsewhere(&app, &app.test_user).await;
+
+    let html_page = app.get_sessions_html().await;
+
+    assert!(html_page.contains("This session"));
+    assert!(html_page.contains("Stolen laptop"));
+}
+
+#[tokio::test]
+async fn a_revoked_session_is_logged_out() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (stolen_laptop, session_id) = log_in_elsewhere(&app, &app.test_user).await;
+    assert_eq!(dashboard_status(&app, &stolen_laptop).await, 200);
+
+    let response = app.post_revoke_session(session_id).await;
+    assert_is_redirect_to(&response, "/admin/sessions");
+
+    assert_eq!(dashboard_status(&app, &stolen_laptop).await, 303);
+    assert!(!app.get_sessions_html().await.contains("Stolen laptop"));
+    // the current session is untouched
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn an_expired_session_is_logged_out_and_no_longer_listed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (stolen_laptop, session_id) = log_in_elsewhere(&app, &app.test_user).await;
+
+    sqlx::query!(
+        "UPDATE user_sessions SET expires_at = now() - INTERVAL '1 second' WHERE session_id = $1",
+        session_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    assert_eq!(dashboard_status(&app, &stolen_laptop).await, 303);
+    assert!(!app.get_sessions_html().await.contains("Stolen laptop"));
+}
+
+#[tokio::test]
+async fn requests_mark_the_session_as_seen() {
+    let app = spawn_app().await;
+    let (stolen_laptop, session_id) = log_in_elsewhere(&app, &app.test_user).await;
+    sqlx::query!(
+        "UPDATE user_sessions SET last_seen_at = now() - INTERVAL '1 hour' WHERE session_id = $1",
+        session_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    dashboard_status(&app, &stolen_laptop).await;
+
+    let seen_recently = sqlx::query_scalar!(
+        r#"
+        SELECT last_seen_at > now() - INTERVAL '1 minute' as "seen_recently!"
+        FROM user_sessions WHERE session_id = $1
+        "#,
+        session_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(seen_recently);
+}
+
+#[tokio::test]
+async fn users_cannot_revoke_the_sessions_of_others() {
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    let (other_client, session_id) = log_in_elsewhere(&app, &other_user).await;
+    app.test_user.login(&app).await;
+
+    app.post_revoke_session(session_id).await;
+
+    assert_eq!(dashboard_status(&app, &other_client).await, 200);
+}
+
+#[tokio::test]
+async fn all_other_sessions_can_be_revoked_at_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (stolen_laptop, _) = log_in_elsewhere(&app, &app.test_user).await;
+
+    let response = app.post_revoke_other_sessions().await;
+    assert_is_redirect_to(&response, "/admin/sessions");
+
+    assert_eq!(dashboard_status(&app, &stolen_laptop).await, 303);
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn changing_password_revokes_the_other_sessions() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (stolen_laptop, _) = log_in_elsewhere(&app, &app.test_user).await;
+
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    assert_eq!(dashboard_status(&app, &stolen_laptop).await, 303);
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn logging_out_ends_the_session() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_logout().await;
+
+    let n_active =
+        sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM user_sessions WHERE revoked_at IS NULL"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(n_active, 0);
+}
+
+#[tokio::test]
+async fn sessions_can_be_stored_in_postgres() {
+    let app = spawn_app_with(|c| c.session_store.backend = SessionStoreBackend::Postgres).await;
+    app.test_user.login(&app).await;
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+    assert!(count_stored_sessions(&app).await > 0);
+
+    app.post_logout().await;
+
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn expired_sessions_stored_in_postgres_are_ignored_then_deleted() {
+    let app = spawn_app_with(|c| c.session_store.backend = SessionStoreBackend::Postgres).await;
+    app.test_user.login(&app).await;
+
+    sqlx::query!("UPDATE sessions SET expires_at = now() - INTERVAL '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+    delete_expired_sessions(&app.db_pool).await.unwrap();
+    assert_eq!(count_stored_sessions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn expired_sessions_stored_in_postgres_are_not_extended() {
+    let app = spawn_app().await;
+    let store = PostgresSessionStore::new(app.db_pool.clone());
+    let state = HashMap::from([("user_id".to_string(), "\"42\"".to_string())]);
+    let key = store.save(state, &Duration::seconds(-1)).await.unwrap();
+
+    store.update_ttl(&key, &Duration::hours(1)).await.unwrap();
+
+    assert_eq!(store.load(&key).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn sessions_can_be_stored_in_redis() {
+    let app = spawn_app_with(|c| c.session_store.backend = SessionStoreBackend::Redis).await;
+    app.test_user.login(&app).await;
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+
+    app.post_logout().await;
+
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+    // nothing is stored in Postgres
+    assert_eq!(count_stored_sessions(&app).await, 0);
+}
+
+async fn count_stored_sessions(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM sessions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
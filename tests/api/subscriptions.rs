@@ -1,7 +1,24 @@
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
+
+async fn post_subscriptions_with_key(
+    app: &TestApp,
+    body: &str,
+    key: &str,
+    user_agent: &str,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Idempotency-Key", key)
+        .header("User-Agent", user_agent)
+        .body(body.to_string())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -133,7 +150,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     // Assert
     // Get the 1st intercepted request
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
@@ -153,3 +170,213 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn retried_subscriptions_are_processed_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - a double submit, the key may also be sent in a hidden field
+    let response = post_subscriptions_with_key(&app, body, "key", "firefox").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = post_subscriptions_with_key(
+        &app,
+        &format!("{body}&idempotency_key=key"),
+        "key",
+        "firefox",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscribers = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers, 1);
+}
+
+#[tokio::test]
+async fn subscribing_someone_else_with_a_used_key_is_rejected_with_a_422() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = post_subscriptions_with_key(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "key",
+        "firefox",
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let response = post_subscriptions_with_key(
+        &app,
+        "name=tolkien&email=tolkien%40gmail.com",
+        "key",
+        "firefox",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 422);
+}
+
+#[tokio::test]
+async fn keys_of_other_clients_are_not_replayed() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let response = post_subscriptions_with_key(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "key",
+        "firefox",
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - the same key, from another browser
+    let response = post_subscriptions_with_key(
+        &app,
+        "name=tolkien&email=tolkien%40gmail.com",
+        "key",
+        "curl",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn keys_past_the_anonymous_quota_are_not_stored() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    // other clients have used up the storage of anonymous keys
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency (client_fingerprint, endpoint, idempotency_key, created_at)
+        SELECT 'client-' || n, 'subscribe', 'key', now()
+        FROM generate_series(1, 10000) AS n
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = post_subscriptions_with_key(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "key",
+        "firefox",
+    )
+    .await;
+
+    // Assert - the subscription is processed without a key
+    assert_eq!(response.status().as_u16(), 200);
+    let stored = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM idempotency WHERE client_fingerprint NOT LIKE 'client-%'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(stored, 0);
+}
+
+#[tokio::test]
+async fn concurrent_keys_of_a_client_cannot_get_past_its_quota() {
+    // Arrange
+    let app = spawn_app_with(|c| c.idempotency.anonymous_quota.per_client = 3).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut requests = tokio::task::JoinSet::new();
+    for i in 0..20 {
+        let client = reqwest::Client::new();
+        let url = format!("{}/subscriptions", &app.address);
+        requests.spawn(async move {
+            client
+                .post(url)
+                .header("Idempotency-Key", format!("key-{i}"))
+                .header("User-Agent", "firefox")
+                .form(&[
+                    ("name", "le guin"),
+                    ("email", &format!("ursula{i}@gmail.com")),
+                ])
+                .send()
+                .await
+                .unwrap()
+        });
+    }
+    let responses = requests.join_all().await;
+
+    // Assert - all of them are processed, only 3 of them with their key
+    assert!(responses.iter().all(|r| r.status().as_u16() == 200));
+    let stored = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM idempotency WHERE client_fingerprint IS NOT NULL"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(stored, 3);
+}
+
+#[tokio::test]
+async fn a_retry_subscribes_once_the_confirmation_email_can_be_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        let response = post_subscriptions_with_key(&app, body, "key", "firefox").await;
+        assert_eq!(response.status().as_u16(), 500);
+    }
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_subscriptions_with_key(&app, body, "key", "firefox").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscribers = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers, 1);
+}
@@ -34,7 +34,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
 
     // intercept the request on email server to extract the GET url and token
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // Act, call the GET API
     let response = reqwest::get(confirmation_links.html).await.unwrap();
@@ -56,7 +56,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 
     app.post_subscriptions(body.into()).await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     reqwest::get(confirmation_links.html)
         .await
@@ -100,7 +100,7 @@ async fn clicking_on_the_confirmation_link_fails_if_database_error() {
 
     app.post_subscriptions(body.into()).await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // sabotage the db
     sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",)
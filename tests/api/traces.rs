@@ -0,0 +1,99 @@
+use newsletter::telemetry::flush_traces;
+use opentelemetry_proto::tonic::{
+    collector::trace::v1::ExportTraceServiceRequest, trace::v1::Span,
+};
+use prost::Message;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{COLLECTOR, TestApp, assert_is_redirect_to, spawn_app};
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+// Spans of a trace received by the collector so far
+async fn exported_spans(trace_id: &[u8]) -> Vec<Span> {
+    tokio::task::spawn_blocking(flush_traces).await.unwrap();
+    COLLECTOR
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .flat_map(|request| {
+            ExportTraceServiceRequest::decode(request.body.as_slice())
+                .unwrap()
+                .resource_spans
+        })
+        .flat_map(|resource_spans| resource_spans.scope_spans)
+        .flat_map(|scope_spans| scope_spans.spans)
+        .filter(|span| span.trace_id == trace_id)
+        .collect()
+}
+
+#[tokio::test]
+async fn deliveries_continue_the_trace_of_the_publish_request() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let trace_id: [u8; 16] = rand::random();
+    let traceparent = format!(
+        "00-{}-{:016x}-01",
+        hex::encode(trace_id),
+        rand::random::<u64>()
+    );
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletters", &app.address))
+        .header("traceparent", &traceparent)
+        .form(
+            &app.with_csrf_token(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": uuid::Uuid::new_v4().to_string(),
+            }))
+            .await,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let stored = sqlx::query_scalar!("SELECT traceparent FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .expect("The trace context was not stored with the delivery task");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert!(stored.starts_with(&format!("00-{}-", hex::encode(trace_id))));
+    let spans = exported_spans(&trace_id).await;
+    let delivery = spans
+        .iter()
+        .find(|span| span.name == "Deliver issue")
+        .expect("The delivery was not exported in the trace of the publish request");
+    // a child of the span which enqueued the task
+    let enqueue_span_id = hex::decode(stored.split('-').nth(2).unwrap()).unwrap();
+    assert_eq!(delivery.parent_span_id, enqueue_span_id);
+    assert!(spans.iter().any(|span| span.span_id == enqueue_span_id));
+}
@@ -0,0 +1,284 @@
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::helpers::{TestApp, TestUser, assert_is_redirect_to, spawn_app};
+
+fn current_code(secret: &str) -> String {
+    code(secret, 0)
+}
+
+// A code still accepted, but of the next time step: each code can only be used once
+fn next_code(secret: &str) -> String {
+    code(secret, 1)
+}
+
+fn code(secret: &str, steps_from_now: u64) -> String {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+    let totp = TOTP::new_unchecked(Algorithm::SHA1, 6, 1, 30, secret, None, "".into());
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    totp.generate(now + steps_from_now * totp.step)
+}
+
+// Enroll the logged in user, returning their TOTP secret and recovery codes
+async fn enroll(app: &TestApp) -> (String, Vec<String>) {
+    let html_page = app.get_two_factor_html().await;
+    let secret = html_page
+        .split("secret=")
+        .nth(1)
+        .unwrap()
+        .split('&')
+        .next()
+        .unwrap()
+        .to_string();
+
+    let response = app.post_enable_two_factor(&current_code(&secret)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<p>Two-factor authentication is enabled.</p>"));
+    let recovery_codes = html_page
+        .split("<li><code>")
+        .skip(1)
+        .map(|s| s.split("</code>").next().unwrap().to_string())
+        .collect();
+
+    (secret, recovery_codes)
+}
+
+#[tokio::test]
+async fn enrolled_users_must_enter_a_code_to_log_in() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, recovery_codes) = enroll(&app).await;
+    assert_eq!(recovery_codes.len(), 10);
+    app.post_logout().await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_two_factor_login(&next_code(&secret)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn invalid_codes_are_rejected_at_login() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enroll(&app).await;
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_two_factor_login("not-a-code").await;
+
+    assert_is_redirect_to(&response, "/login/two-factor");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn codes_can_only_be_used_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll(&app).await;
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+
+    // the code used to enroll
+    let response = app.post_two_factor_login(&current_code(&secret)).await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+
+    let code = next_code(&secret);
+    let response = app.post_two_factor_login(&code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+    let response = app.post_two_factor_login(&code).await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+}
+
+#[tokio::test]
+async fn the_second_step_requires_a_valid_password_first() {
+    let app = spawn_app().await;
+
+    let response = app.post_two_factor_login("123456").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn recovery_codes_can_only_be_used_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (_, recovery_codes) = enroll(&app).await;
+
+    for expected_location in ["/admin/dashboard", "/login/two-factor"] {
+        app.post_logout().await;
+        app.test_user.login(&app).await;
+        let response = app.post_two_factor_login(&recovery_codes[0]).await;
+        assert_is_redirect_to(&response, expected_location);
+    }
+}
+
+#[tokio::test]
+async fn enrollment_requires_a_valid_code() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.get_two_factor_html().await;
+
+    let response = app.post_enable_two_factor("not-a-code").await;
+
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Invalid authentication code.</i></p>"));
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn replacing_the_secret_requires_a_current_code() {
+    let app = spawn_app().await;
+    // a pending secret from a session started before the user enrolled
+    let other_browser = app.another_api_client();
+    other_browser
+        .post(format!("{}/login", app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    let html_page = other_browser
+        .get(format!("{}/admin/two-factor", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let other_secret = html_page
+        .split("secret=")
+        .nth(1)
+        .unwrap()
+        .split('&')
+        .next()
+        .unwrap()
+        .to_string();
+    let csrf_token = html_page
+        .split(r#"name="csrf_token" value=""#)
+        .nth(1)
+        .unwrap()
+        .split('"')
+        .next()
+        .unwrap()
+        .to_string();
+    app.test_user.login(&app).await;
+    let (secret, recovery_codes) = enroll(&app).await;
+    let stored_secret = || async {
+        sqlx::query!(
+            "SELECT totp_secret FROM users WHERE user_id = $1",
+            app.test_user.user_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .totp_secret
+        .unwrap()
+    };
+
+    let response = other_browser
+        .post(format!("{}/admin/two-factor", app.address))
+        .form(&serde_json::json!({
+            "code": current_code(&other_secret),
+            "csrf_token": &csrf_token,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    assert_eq!(stored_secret().await, secret);
+
+    let response = other_browser
+        .post(format!("{}/admin/two-factor", app.address))
+        .form(&serde_json::json!({
+            "code": current_code(&other_secret),
+            "current_code": &recovery_codes[0],
+            "csrf_token": &csrf_token,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(stored_secret().await, other_secret);
+}
+
+#[tokio::test]
+async fn users_can_disable_two_factor_authentication() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll(&app).await;
+
+    let response = app.post_disable_two_factor(&next_code(&secret)).await;
+    assert_is_redirect_to(&response, "/admin/two-factor");
+
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn users_must_enroll_when_owners_make_two_factor_mandatory() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_settings(&serde_json::json!({ "two_factor_required": "on" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/settings");
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let (secret, _) = enroll(&app).await;
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // it can't be disabled while it's mandatory
+    app.post_disable_two_factor(&next_code(&secret)).await;
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is required for all users."));
+}
+
+#[tokio::test]
+async fn you_must_be_an_owner_to_change_the_settings() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    let response = app
+        .post_settings(&serde_json::json!({ "two_factor_required": "on" }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}